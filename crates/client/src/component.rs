@@ -8,6 +8,8 @@ pub trait Component: std::fmt::Debug {
     async fn init(&mut self) -> Result<()> {
         Ok(())
     }
+    /// Called once the app is quitting, before the connection is closed.
+    fn quit(&mut self) {}
     fn render(&mut self, frame: &mut Frame, area: Rect, is_focused: bool);
     async fn handle_event(&mut self, event: AppEvent, is_focused: bool) -> Result<bool>;
 }
@@ -1,20 +1,41 @@
+use std::time::Duration;
+
 use color_eyre::eyre::Result;
 use common::protocol;
 use ratatui::{
     Frame,
     crossterm::event::{self},
-    layout::{Constraint, Flex, Layout, Rect},
+    layout::{Constraint, Layout, Rect},
     style::{Color, Style, Stylize},
     text::{Line, Span},
     widgets::{
         Block, BorderType, Clear, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
     },
 };
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::Sender;
 use tui_input::backend::crossterm::EventHandler;
 use websocket::message::Message;
 
-use crate::{AppEvent, EventSender, component::Component, into_protocol_color};
+use crate::{
+    AppEvent, EventSender,
+    component::Component,
+    components::{Urgency, center_area, try_queue},
+    into_protocol_color,
+};
+
+/// Entry of [`ColorList`] picking the color typed in as RGB hex instead.
+const CUSTOM_COLOR: &str = "custom…";
+
+/// Parses `#rrggbb`, the `#` being optional.
+fn parse_hex_color(hex: &str) -> Option<Color> {
+    let hex = hex.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?))
+}
 
 #[derive(Debug)]
 struct ColorList {
@@ -22,10 +43,27 @@ struct ColorList {
     state: ListState,
 }
 
+impl ColorList {
+    fn custom_selected(&self) -> bool {
+        self.state
+            .selected()
+            .is_some_and(|i| self.items.get(i) == Some(&CUSTOM_COLOR))
+    }
+}
+
 impl Default for ColorList {
     fn default() -> Self {
         Self {
-            items: vec!["red", "yellow", "green", "cyan", "blue", "magenta", "reset"],
+            items: vec![
+                "red",
+                "yellow",
+                "green",
+                "cyan",
+                "blue",
+                "magenta",
+                "reset",
+                CUSTOM_COLOR,
+            ],
             state: ListState::default(),
         }
     }
@@ -36,26 +74,30 @@ enum Focus {
     #[default]
     Input,
     Colors,
+    /// RGB hex input, only reachable with [`CUSTOM_COLOR`] selected.
+    Hex,
 }
 
 impl Focus {
-    fn next(self) -> Self {
+    fn next(self, custom_selected: bool) -> Self {
         match self {
             Self::Input => Self::Colors,
-            Self::Colors => Self::Input,
+            Self::Colors if custom_selected => Self::Hex,
+            Self::Colors | Self::Hex => Self::Input,
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Auth {
-    ws_tx: UnboundedSender<Message>,
+    ws_tx: Sender<Message>,
     event_tx: EventSender,
 
     focus: Focus,
 
     nickname_input: tui_input::Input,
     color_list: ColorList,
+    hex_input: tui_input::Input,
 }
 
 struct NicknameWidget<'a> {
@@ -98,6 +140,36 @@ impl Widget for NicknameWidget<'_> {
     }
 }
 
+struct HexWidget<'a> {
+    input: &'a tui_input::Input,
+    focus: Focus,
+}
+
+impl Widget for HexWidget<'_> {
+    fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer)
+    where
+        Self: Sized,
+    {
+        let value = self.input.value();
+        let preview = match parse_hex_color(value) {
+            Some(color) => Span::styled(" ◼ ", color),
+            None if value.is_empty() => Span::raw(" #rrggbb ").dark_gray(),
+            None => Span::raw(" invalid ").red(),
+        };
+        let hex_block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .title_top(Span::raw(" RGB ").into_left_aligned_line())
+            .title_top(preview.into_right_aligned_line());
+        Paragraph::new(value)
+            .block(hex_block.style(if self.focus == Focus::Hex {
+                Style::new().magenta()
+            } else {
+                Style::new()
+            }))
+            .render(area, buf);
+    }
+}
+
 struct ColorWidget<'a> {
     list: &'a mut ColorList,
     focus: Focus,
@@ -137,27 +209,58 @@ impl Widget for ColorWidget<'_> {
 
 impl Auth {
     #[must_use]
-    pub fn new(ws_tx: UnboundedSender<Message>, event_tx: EventSender) -> Box<Self> {
+    pub fn new(ws_tx: Sender<Message>, event_tx: EventSender) -> Box<Self> {
         Box::new(Self {
             ws_tx,
             event_tx,
             focus: Focus::default(),
             nickname_input: tui_input::Input::default(),
             color_list: ColorList::default(),
+            hex_input: tui_input::Input::default(),
         })
     }
 
+    /// Sends the auth request and closes the pop-up, unless the nickname or the custom color
+    /// is invalid, in which case the user is told so and the pop-up stays open.
     fn try_authenticate(&mut self) -> Result<()> {
-        let selected = self.color_list.state.selected().unwrap();
-        self.ws_tx.send(
+        let color = if self.color_list.custom_selected() {
+            let Some(color) = parse_hex_color(self.hex_input.value()) else {
+                self.event_tx.notify(
+                    "Custom color should look like #aabbcc.",
+                    Urgency::Warning,
+                    Duration::from_secs(3),
+                )?;
+                self.focus = Focus::Hex;
+                return Ok(());
+            };
+            color
+        } else {
+            let selected = self.color_list.state.selected().unwrap();
+            self.color_list.items[selected].parse::<Color>().unwrap()
+        };
+        let name = self.nickname_input.value().trim().to_owned();
+        if !protocol::nickname_is_valid(&name) {
+            self.event_tx.notify(
+                "Nicknames can't be blank or have control characters.",
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?;
+            self.focus = Focus::Input;
+            return Ok(());
+        }
+        if !try_queue(
+            &self.ws_tx,
+            &mut self.event_tx,
             protocol::ClientMessage::Auth(protocol::MessageSender {
-                name: self.nickname_input.to_string(),
-                color: into_protocol_color(
-                    self.color_list.items[selected].parse::<Color>().unwrap(),
-                ),
+                name: name.clone(),
+                color: into_protocol_color(color),
             })
             .into(),
-        )?;
+        )? {
+            return Ok(());
+        }
+        self.event_tx.send(AppEvent::Authenticating(name))?;
+        self.event_tx.send(AppEvent::ComponentUnfocus)?;
         Ok(())
     }
 
@@ -167,6 +270,12 @@ impl Auth {
             .is_some()
     }
 
+    fn handle_hex_event(&mut self, event: event::KeyEvent) -> bool {
+        self.hex_input
+            .handle_event(&event::Event::Key(event))
+            .is_some()
+    }
+
     fn handle_colors_event(&mut self, event: event::KeyEvent) -> bool {
         match event.code {
             event::KeyCode::Char('j' | 'о' | 's' | 'і') | event::KeyCode::Down => {
@@ -182,14 +291,6 @@ impl Auth {
     }
 }
 
-fn center_area(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
-    let [area] = Layout::horizontal([horizontal])
-        .flex(Flex::Center)
-        .areas(area);
-    let [area] = Layout::vertical([vertical]).flex(Flex::Center).areas(area);
-    area
-}
-
 #[async_trait::async_trait]
 impl Component for Auth {
     async fn init(&mut self) -> Result<()> {
@@ -218,6 +319,19 @@ impl Component for Auth {
         };
         nickname_widget.render(input_area, frame.buffer_mut());
 
+        let color_area = if self.color_list.custom_selected() {
+            let [color_area, hex_area] =
+                Layout::vertical([Constraint::Fill(1), Constraint::Max(3)]).areas(color_area);
+            let hex_widget = HexWidget {
+                input: &self.hex_input,
+                focus: self.focus,
+            };
+            hex_widget.render(hex_area, frame.buffer_mut());
+            color_area
+        } else {
+            color_area
+        };
+
         let color_widget = ColorWidget {
             list: &mut self.color_list,
             focus: self.focus,
@@ -233,6 +347,7 @@ impl Component for Auth {
             if match self.focus {
                 Focus::Input => self.handle_input_event(key_event),
                 Focus::Colors => self.handle_colors_event(key_event),
+                Focus::Hex => self.handle_hex_event(key_event),
             } {
                 return Ok(true);
             }
@@ -242,12 +357,11 @@ impl Component for Auth {
                     true
                 }
                 event::KeyCode::Tab => {
-                    self.focus = self.focus.next();
+                    self.focus = self.focus.next(self.color_list.custom_selected());
                     true
                 }
                 event::KeyCode::Enter => {
                     self.try_authenticate()?;
-                    self.event_tx.send(AppEvent::ComponentUnfocus)?;
                     true
                 }
                 _ => false,
@@ -257,3 +371,41 @@ impl Component for Auth {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{
+        crossterm::event::{KeyCode, KeyEvent},
+        style::Color,
+    };
+    use tokio::sync::mpsc::{channel, unbounded_channel};
+
+    use super::{Auth, parse_hex_color};
+    use crate::{AppEvent, EventSender, component::Component};
+
+    #[test]
+    fn hex_colors_are_validated() {
+        assert_eq!(
+            parse_hex_color("#aabbcc"),
+            Some(Color::Rgb(0xaa, 0xbb, 0xcc))
+        );
+        assert_eq!(parse_hex_color("00FF7f"), Some(Color::Rgb(0, 0xff, 0x7f)));
+        for malformed in ["", "#abc", "#aabbccdd", "#gghhii", "#aabb+c", "#ааб"] {
+            assert_eq!(parse_hex_color(malformed), None, "{malformed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn blank_nickname_keeps_the_pop_up_open() {
+        let (ws_tx, mut ws_rx) = channel(1);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let mut auth = Auth::new(ws_tx, EventSender(event_tx));
+        auth.init().await.unwrap();
+
+        let enter = AppEvent::KeyEvent(KeyEvent::from(KeyCode::Enter));
+        assert!(auth.handle_event(enter, true).await.unwrap());
+        assert!(ws_rx.try_recv().is_err());
+        assert!(matches!(event_rx.try_recv(), Ok(AppEvent::Notify(..))));
+        assert!(event_rx.try_recv().is_err());
+    }
+}
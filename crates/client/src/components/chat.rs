@@ -1,29 +1,197 @@
 #![allow(clippy::cast_possible_truncation)]
-use std::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    ops::Range,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use color_eyre::eyre::Result;
 use common::protocol;
 use ratatui::{
     Frame,
-    crossterm::event::{self, KeyEvent},
+    crossterm::event::{self, KeyEvent, MouseEvent, MouseEventKind},
     layout::{Constraint, Layout, Rect},
     style::{Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, Paragraph, Widget},
+    widgets::{Block, BorderType, Clear, List, ListState, Paragraph, StatefulWidget, Widget},
 };
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{Sender, error::TrySendError};
 use tui_input::backend::crossterm::EventHandler;
-use websocket::message::Message;
+use unicode_width::UnicodeWidthChar;
+use websocket::message::{Message, StatusCode};
 
-use crate::{AppEvent, EventSender, component::Component, components::Urgency, into_ratatui_color};
+use crate::{
+    AppEvent, EventSender,
+    component::Component,
+    components::{Urgency, try_queue},
+    config::Config,
+    into_ratatui_color,
+    scrollback::{self, LoggedMessage},
+};
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     #[default]
     Normal,
     Insert,
+    Search,
+}
+
+/// Human-readable size of an attachment, in whole KiB past the first one.
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else {
+        format!("{} KiB", bytes.div_ceil(1024))
+    }
+}
+
+/// The link `word` is, without trailing punctuation, if it's an `http://` or `https://` one.
+fn as_url(word: &str) -> Option<&str> {
+    let url = word.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+        .then_some(url)
+}
+
+/// Splits `text` into plain spans and links, the latter underlined in blue.
+fn linkify<'a>(text: &str) -> Vec<Span<'a>> {
+    let mut spans = vec![];
+    let mut plain = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        let Some(url) = as_url(word.trim_end()) else {
+            plain.push_str(word);
+            continue;
+        };
+        if !plain.is_empty() {
+            spans.push(Span::raw(std::mem::take(&mut plain)));
+        }
+        spans.push(Span::raw(url.to_string()).blue().underlined());
+        plain.push_str(&word[url.len()..]);
+    }
+    if !plain.is_empty() {
+        spans.push(Span::raw(plain));
+    }
+    spans
+}
+
+/// Byte ranges of `query` in `text`, ignoring case unless lowercasing changes any lengths.
+fn match_ranges(text: &str, query: &str) -> Vec<Range<usize>> {
+    if query.is_empty() {
+        return vec![];
+    }
+    let (lower_text, lower_query) = (text.to_lowercase(), query.to_lowercase());
+    // offsets into the lowercase text only carry over if it's as long
+    let (haystack, needle) = if lower_text.len() == text.len() && lower_query.len() == query.len() {
+        (lower_text.as_str(), lower_query.as_str())
+    } else {
+        (text, query)
+    };
+    haystack
+        .match_indices(needle)
+        .map(|(i, m)| i..i + m.len())
+        .filter(|range| text.is_char_boundary(range.start) && text.is_char_boundary(range.end))
+        .collect()
+}
+
+/// Separates the sender of a message from its text, see [`Chat::message_lines`].
+const SENDER_SEPARATOR: &str = ": ";
+/// Starts every line of a multi-line message but the first one.
+const CONTINUATION_INDENT: &str = "  ";
+
+/// Index of the first span of `line` holding message text, if it's a line of a message:
+/// the one after [`SENDER_SEPARATOR`] or [`CONTINUATION_INDENT`].
+fn text_start(line: &Line) -> Option<usize> {
+    if line.spans.first()?.content == CONTINUATION_INDENT {
+        return Some(1);
+    }
+    line.spans
+        .iter()
+        .position(|span| span.content == SENDER_SEPARATOR)
+        .map(|i| i + 1)
+}
+
+/// Whether `query` occurs in the message text of `line`, see [`match_ranges`].
+/// Timestamps and senders don't count.
+fn line_matches(line: &Line, query: &str) -> bool {
+    let Some(start) = text_start(line) else {
+        return false;
+    };
+    let text: String = line.spans[start..]
+        .iter()
+        .map(|span| span.content.as_ref())
+        .collect();
+    !match_ranges(&text, query).is_empty()
+}
+
+/// Indices of the `lines` matching `query`, see [`line_matches`].
+fn matching_lines(lines: &[Line], query: &str) -> Vec<usize> {
+    (0..lines.len())
+        .filter(|&i| line_matches(&lines[i], query))
+        .collect()
+}
+
+/// `line` with every occurrence of `query` within its message text spans highlighted.
+fn highlight<'a>(line: &Line<'a>, query: &str) -> Line<'a> {
+    let start = text_start(line).unwrap_or(line.spans.len());
+    let mut highlighted = line.clone();
+    highlighted.spans = line
+        .spans
+        .iter()
+        .enumerate()
+        .flat_map(|(i, span)| {
+            if i < start {
+                return vec![span.clone()];
+            }
+            let content = span.content.as_ref();
+            let mut pieces = vec![];
+            let mut last = 0;
+            for range in match_ranges(content, query) {
+                pieces.push(Span::styled(
+                    content[last..range.start].to_string(),
+                    span.style,
+                ));
+                pieces.push(Span::styled(
+                    content[range.clone()].to_string(),
+                    span.style.black().on_yellow(),
+                ));
+                last = range.end;
+            }
+            pieces.push(Span::styled(content[last..].to_string(), span.style));
+            pieces.retain(|piece| !piece.content.is_empty());
+            pieces
+        })
+        .collect();
+    highlighted
+}
+
+/// Whether `text` mentions `name` as a whole word, ignoring case, e.g. `@Bob` but not `bobby`.
+fn mentions(text: &str, name: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    match_ranges(text, name).into_iter().any(|range| {
+        !text[..range.start].chars().next_back().is_some_and(is_word)
+            && !text[range.end..].chars().next().is_some_and(is_word)
+    })
+}
+
+/// Only abnormal drops are worth reconnecting after: a close frame from the server
+/// is a deliberate decision, be it a shutdown or a kick.
+fn should_reconnect(code: StatusCode) -> bool {
+    code == StatusCode::CloseAbnormal
 }
 
+/// Default [`Chat::timestamp_format`], 24-hour `HH:MM`.
+const TIMESTAMP_FORMAT: &str = "%H:%M";
+
+/// How long after the last edit of the input the user is considered to have stopped typing.
+const TYPING_IDLE: Duration = Duration::from_secs(3);
+
+/// Max number of sent messages kept in [`InputHistory`].
+const HISTORY_CAPACITY: usize = 100;
+
 /// This component is *not* on top of the stack, thus relying on [`AppEvent::SpawnAuth`]
 /// to be executed by the [App] when in focus - currently, this is the first component
 /// that is created, and is being focused by default, so we're fine.
@@ -35,43 +203,190 @@ pub enum Mode {
 pub struct Chat<'a> {
     mode: Mode,
     token: Option<protocol::Token>,
+    /// Set once the connection is closed, either by the server or abnormally
+    /// ([`StatusCode::CloseAbnormal`]). No input is accepted afterwards.
+    closed: Option<StatusCode>,
 
+    /// Scrollback of the active room.
     received_messages: Vec<Line<'a>>,
+    /// The room [`protocol::ClientMessage::SendMessage`] targets.
+    active_room: protocol::RoomId,
+    /// Scrollback of every other joined room, swapped with `received_messages` on switch.
+    room_buffers: BTreeMap<protocol::RoomId, Vec<Line<'a>>>,
+    /// Id of the next message sent, see [`protocol::ClientMessage::SendMessage`].
+    next_message_id: protocol::MessageId,
+    /// Sent messages the server hasn't echoed yet: their room, first line and line count.
+    /// Shown dimmed until then, and replaced by the echo.
+    pending: HashMap<protocol::MessageId, (protocol::RoomId, usize, usize)>,
     /// If `None`, snap to the bottom. Otherwise, fixed scroll towards the top.
     #[allow(clippy::struct_field_names)]
     chat_scroll_neg: Option<usize>,
+    /// Lines scrolled per `j`/`k` press, see [`Config::scroll_step`].
+    scroll_step: usize,
+    /// Message lines that fit in the chat the last time it was rendered,
+    /// scrolled per `PageUp`/`PageDown` press.
+    view_height: usize,
+    /// Width the messages were wrapped to the last time they were rendered.
+    view_width: u16,
+    /// The `/` search being typed in [`Mode::Search`], or cycled through in [`Mode::Normal`].
+    search: Option<Search>,
+    /// [`chrono::format::strftime`] format of the time received messages are stamped with.
+    /// The server doesn't send one, so it's the local time of receipt,
+    /// except for [`protocol::ServerMessage::History`].
+    timestamp_format: &'static str,
     current_input: tui_input::Input,
     input_scroll: usize,
+    history: InputHistory,
+    /// Whether [`protocol::ServerMessage::History`] was shown already.
+    /// It's sent again after reconnecting, but the buffers still hold it by then.
+    history_received: bool,
 
-    ws_tx: UnboundedSender<Message>,
+    /// Nicknames of users seen online, kept sorted for completion.
+    known_users: BTreeSet<String>,
+    /// Nicknames of users currently typing, see [`protocol::ServerMessage::UserTyping`].
+    typing_users: BTreeSet<String>,
+    /// When the input was last edited, as long as the server was told the user is typing.
+    typing_at: Option<Instant>,
+    completion: Option<Completion>,
+    mention_popup: Option<MentionPopup>,
+    /// File name and bytes of an image picked with `/attach <path>`,
+    /// sent along with the next message.
+    attachment: Option<(String, Vec<u8>)>,
+    /// The last link received, opened with `o` in Normal mode.
+    last_url: Option<String>,
+    /// The last image received, shown with `v` in Normal mode.
+    last_image: Option<Vec<u8>>,
+    /// The user's own nickname, as last requested, to notice mentions of.
+    nickname: Option<String>,
+    /// Where [`Chat::log`] is saved on quit, see [`Config::history_path`].
+    log_path: Option<PathBuf>,
+    /// Messages received this session and restored from the last ones,
+    /// only kept if there's a [`Chat::log_path`].
+    log: VecDeque<LoggedMessage>,
+
+    ws_tx: Sender<Message>,
     event_tx: EventSender,
 }
 
+/// An ongoing `@`-mention completion, kept between repeated Tab presses.
+#[derive(Debug)]
+struct Completion {
+    /// Char index of the `@` that starts the completed word.
+    start: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+/// Sent messages, recalled with `Up`/`Down` like in a shell.
+#[derive(Debug, Default)]
+struct InputHistory {
+    /// Oldest first, up to [`HISTORY_CAPACITY`].
+    entries: VecDeque<String>,
+    /// Index of the recalled entry, `None` while editing the draft.
+    position: Option<usize>,
+    /// Whatever was being typed when browsing started, restored past the newest entry.
+    draft: String,
+}
+
+impl InputHistory {
+    fn push(&mut self, entry: String) {
+        self.position = None;
+        self.draft.clear();
+        if entry.is_empty() {
+            return;
+        }
+        if self.entries.len() == HISTORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Steps to an older entry, stashing `current` as the draft if browsing just started.
+    /// Returns `None` if there's nothing older.
+    fn older(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(position) => position - 1,
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Steps to a newer entry, or back to the draft past the newest one.
+    /// Returns `None` if not browsing.
+    fn newer(&mut self) -> Option<&str> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(&self.entries[position])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+/// A search through the messages of the active room.
+#[derive(Debug, Default)]
+struct Search {
+    query: String,
+    /// Indices of the matching lines in [`Chat::received_messages`], oldest first.
+    /// Kept up to date by [`Chat::refresh_search`].
+    matches: Vec<usize>,
+    /// Index into `matches` of the one brought into view.
+    current: usize,
+}
+
+/// Pop-up of users matching the `@`-prefixed word being typed.
+#[derive(Debug)]
+struct MentionPopup {
+    /// Char index of the `@` that opened the pop-up.
+    start: usize,
+    state: ListState,
+}
+
 struct ChatWidget<'a> {
     messages: &'a [Line<'a>],
     scroll_neg: &'a mut Option<usize>,
+    view_height: &'a mut usize,
+    view_width: &'a mut u16,
+    search: Option<&'a Search>,
     authorized: bool,
+    closed: Option<StatusCode>,
+    room: &'a protocol::RoomId,
+    typing: &'a BTreeSet<String>,
 }
 
 impl ChatWidget<'_> {
     fn clamp_scroll(&mut self, area: Rect, text_height: usize) -> usize {
         let view_height = area.height.saturating_sub(2) as usize;
-
-        *self.scroll_neg = self
-            .scroll_neg
-            .map(|scroll| scroll.min(text_height.saturating_sub(view_height)));
-
-        let mut scroll = text_height.saturating_sub(view_height + self.scroll_neg.unwrap_or(0));
-        if let Some(0) = self.scroll_neg {
-            *self.scroll_neg = None;
-        }
-        if self.scroll_neg.is_none() && text_height - scroll > view_height {
-            scroll = view_height.saturating_add(area.height as usize);
-        }
-        scroll
+        *self.view_height = view_height;
+        *self.view_width = area.width.saturating_sub(2);
+        scroll_from_top(text_height, view_height, self.scroll_neg)
     }
 }
 
+/// Lines to skip from the top, so that the view ends `scroll_neg` lines above the bottom.
+///
+/// `scroll_neg` is clamped to the oldest line, and pinned back to the bottom
+/// once it reaches it, i.e. `Some(0)` becomes `None`.
+fn scroll_from_top(
+    text_height: usize,
+    view_height: usize,
+    scroll_neg: &mut Option<usize>,
+) -> usize {
+    let max_scroll = text_height.saturating_sub(view_height);
+    *scroll_neg = scroll_neg
+        .map(|scroll| scroll.min(max_scroll))
+        .filter(|&scroll| scroll > 0);
+    max_scroll - scroll_neg.unwrap_or(0)
+}
+
 impl Widget for ChatWidget<'_> {
     fn render(mut self, area: Rect, buf: &mut ratatui::prelude::Buffer)
     where
@@ -82,16 +397,62 @@ impl Widget for ChatWidget<'_> {
             .title_top(
                 (Span::raw(" j↓  k↑").bold().green() + Span::raw(" to scroll ")).right_aligned(),
             )
-            .title_top((Span::raw(" q").bold().green() + Span::raw(" to quit ")).left_aligned());
-        if !self.authorized {
-            chat_block = chat_block.title_top(
+            .title_top((Span::raw(" q").bold().green() + Span::raw(" to quit ")).left_aligned())
+            .title_top((Span::raw(" r").bold().green() + Span::raw(" for rooms ")).left_aligned())
+            .title_top((Span::raw(" u").bold().green() + Span::raw(" for users ")).left_aligned());
+        chat_block = if let Some(code) = self.closed {
+            chat_block.title_top(
+                Span::raw(if code == StatusCode::CloseAbnormal {
+                    " Connection lost "
+                } else {
+                    " Disconnected "
+                })
+                .red()
+                .into_centered_line(),
+            )
+        } else if self.authorized {
+            chat_block.title_top(
+                Span::raw(format!(" # {} ", self.room.0))
+                    .bold()
+                    .into_centered_line(),
+            )
+        } else {
+            chat_block.title_top(
                 Span::raw(" Authenticate first! ")
                     .red()
                     .into_centered_line(),
+            )
+        };
+
+        if !self.typing.is_empty() {
+            let names: Vec<_> = self.typing.iter().map(String::as_str).collect();
+            chat_block = chat_block.title_bottom(
+                Span::raw(format!(" {} typing… ", names.join(", ")))
+                    .italic()
+                    .into_left_aligned_line(),
+            );
+        }
+
+        let mut messages = self.messages.to_vec();
+        if let Some(search) = self.search {
+            let progress = if search.matches.is_empty() {
+                String::new()
+            } else {
+                format!("{}/{} ", search.current + 1, search.matches.len())
+            };
+            chat_block = chat_block.title_bottom(
+                Span::raw(format!(" /{} {progress}", search.query))
+                    .bold()
+                    .into_right_aligned_line(),
             );
+            for &i in &search.matches {
+                if let Some(line) = messages.get_mut(i) {
+                    *line = highlight(line, &search.query);
+                }
+            }
         }
 
-        let mut chat_paragraph = Paragraph::new(self.messages.to_vec())
+        let mut chat_paragraph = Paragraph::new(messages)
             .block(chat_block.clone())
             .wrap(ratatui::widgets::Wrap { trim: false });
         let line_count = chat_paragraph.line_count(area.width).saturating_sub(2);
@@ -104,17 +465,52 @@ struct InputWidget<'a> {
     input: &'a tui_input::Input,
     mode: Mode,
     scroll: &'a mut usize,
+    /// Name of the pending attachment, if any.
+    attachment: Option<&'a str>,
+}
+
+/// Splits the input into rows at most `width` cells wide, breaking at newlines too,
+/// along with the row and column the char at `cursor` lands on.
+///
+/// Widths are measured the way the terminal draws them, so a wide char
+/// that doesn't fit at the end of a row starts the next one.
+fn wrap_input(text: &str, width: usize, cursor: usize) -> (Vec<String>, (usize, usize)) {
+    let width = width.max(1);
+    let mut rows = vec![String::new()];
+    let mut row_width = 0;
+    let mut cursor_at = None;
+    for (i, c) in text.chars().enumerate() {
+        let char_width = c.width().unwrap_or(0);
+        if c != '\n' && row_width > 0 && row_width + char_width > width {
+            rows.push(String::new());
+            row_width = 0;
+        }
+        if i == cursor {
+            cursor_at = Some((rows.len() - 1, row_width));
+        }
+        if c == '\n' {
+            rows.push(String::new());
+            row_width = 0;
+        } else if let Some(row) = rows.last_mut() {
+            row.push(c);
+            row_width += char_width;
+        }
+    }
+    // past the end of a full row, the cursor starts the next one
+    let cursor_at = cursor_at.unwrap_or(if row_width >= width {
+        (rows.len(), 0)
+    } else {
+        (rows.len() - 1, row_width)
+    });
+    (rows, cursor_at)
 }
 
 impl InputWidget<'_> {
     fn cursor_position(&mut self, area: Rect) -> (u16, u16) {
-        let width = area.width as usize - 2;
-        let height = area.height as usize - 2;
-        let cursor_absolute = self.input.visual_cursor();
-        let (cursor_x, mut cursor_y) = (
-            cursor_absolute % width,
-            cursor_absolute.checked_div(width).unwrap_or(0),
-        );
+        let width = (area.width as usize).saturating_sub(2).max(1);
+        let height = (area.height as usize).saturating_sub(2).max(1);
+        let (_, (mut cursor_y, cursor_x)) =
+            wrap_input(self.input.value(), width, self.input.cursor());
         if cursor_y > (height - 1) {
             *self.scroll = cursor_y - (height - 1);
             cursor_y = height - 1;
@@ -130,129 +526,552 @@ impl Widget for InputWidget<'_> {
     where
         Self: Sized,
     {
-        let input_block = Block::bordered()
+        let mut input_block = Block::bordered()
             .border_type(ratatui::widgets::BorderType::Rounded)
-            .title_top(if self.mode == Mode::Normal {
-                Span::raw(" a/i").bold().green() + Span::raw(" to enter INSERT mode ")
-            } else {
-                Span::raw(" <ESC>").bold().green() + Span::raw(" to exit INSERT mode ")
+            .title_top(match self.mode {
+                Mode::Normal => {
+                    Span::raw(" a/i").bold().green() + Span::raw(" to enter INSERT mode ")
+                }
+                Mode::Insert => {
+                    Span::raw(" <ESC>").bold().green() + Span::raw(" to exit INSERT mode ")
+                }
+                Mode::Search => {
+                    Span::raw(" <ESC>").bold().green() + Span::raw(" to cancel the search ")
+                }
             })
             .title_alignment(ratatui::layout::Alignment::Right);
-        let input_paragraph = Paragraph::new(self.input.value())
+        if let Some(name) = self.attachment {
+            input_block =
+                input_block.title_top(Line::raw(format!(" + {name} ")).italic().left_aligned());
+        }
+        let (rows, _) = wrap_input(
+            self.input.value(),
+            area.width.saturating_sub(2) as usize,
+            self.input.cursor(),
+        );
+        let input_paragraph = Paragraph::new(rows.into_iter().map(Line::from).collect::<Vec<_>>())
             .block(if self.mode == Mode::Insert {
                 input_block.blue()
             } else {
                 input_block
             })
-            .wrap(ratatui::widgets::Wrap { trim: false })
             .scroll((*self.scroll as u16, 0));
         input_paragraph.render(area, buf);
     }
 }
 
-impl Chat<'_> {
+impl<'a> Chat<'a> {
     #[must_use]
-    pub fn new(ws_tx: UnboundedSender<Message>, event_tx: EventSender) -> Box<Self> {
+    pub fn new(ws_tx: Sender<Message>, event_tx: EventSender, config: &Config) -> Box<Self> {
         Box::new(Self {
             mode: Mode::default(),
             token: None,
+            closed: None,
             received_messages: vec![],
+            active_room: protocol::RoomId::default(),
+            room_buffers: BTreeMap::new(),
+            next_message_id: 0,
+            pending: HashMap::new(),
             chat_scroll_neg: None,
+            scroll_step: config.scroll_step(),
+            view_height: 0,
+            view_width: 0,
+            search: None,
+            timestamp_format: TIMESTAMP_FORMAT,
             current_input: tui_input::Input::default(),
             input_scroll: 0,
+            history: InputHistory::default(),
+            history_received: false,
+            known_users: BTreeSet::new(),
+            typing_users: BTreeSet::new(),
+            typing_at: None,
+            completion: None,
+            mention_popup: None,
+            attachment: None,
+            last_url: None,
+            last_image: None,
+            nickname: None,
+            log_path: config.history_path(),
+            log: VecDeque::new(),
             ws_tx,
             event_tx,
         })
     }
 
     fn handle_key_event(&mut self, event: KeyEvent) -> Result<bool> {
-        Ok(match self.mode {
-            Mode::Normal => match event.code {
-                event::KeyCode::Char('i' | 'ш' | 'a' | 'ф') => {
-                    self.mode = Mode::Insert;
-                    true
+        if event.code != event::KeyCode::Tab {
+            self.completion = None;
+        }
+        if self.mode == Mode::Insert && self.handle_mention_key(event) {
+            return Ok(true);
+        }
+        match self.mode {
+            Mode::Normal => self.handle_normal_key(event),
+            Mode::Insert => self.handle_insert_key(event),
+            Mode::Search => self.handle_search_key(event),
+        }
+    }
+
+    fn handle_normal_key(&mut self, event: KeyEvent) -> Result<bool> {
+        Ok(match event.code {
+            event::KeyCode::Char('i' | 'ш' | 'a' | 'ф') if self.closed.is_none() => {
+                self.mode = Mode::Insert;
+                true
+            }
+            event::KeyCode::Char('j' | 'о') => {
+                self.scroll_down();
+                true
+            }
+            event::KeyCode::Char('k' | 'л') => {
+                self.scroll_up();
+                true
+            }
+            event::KeyCode::PageUp => {
+                self.chat_scroll_neg = Some(
+                    self.chat_scroll_neg
+                        .unwrap_or(0)
+                        .saturating_add(self.view_height.max(1)),
+                );
+                true
+            }
+            event::KeyCode::PageDown => {
+                self.chat_scroll_neg = Some(
+                    self.chat_scroll_neg
+                        .unwrap_or(0)
+                        .saturating_sub(self.view_height.max(1)),
+                );
+                true
+            }
+            event::KeyCode::Home => {
+                // clamped to the oldest message while rendering
+                self.chat_scroll_neg = Some(usize::MAX);
+                true
+            }
+            event::KeyCode::End => {
+                self.chat_scroll_neg = None;
+                true
+            }
+            event::KeyCode::Char('r' | 'к') => {
+                self.event_tx
+                    .send(AppEvent::SpawnRooms(self.joined_rooms()))?;
+                true
+            }
+            event::KeyCode::Char('o' | 'щ') => {
+                self.open_last_url()?;
+                true
+            }
+            event::KeyCode::Char('v' | 'м') => {
+                if let Some(image) = &self.last_image {
+                    self.event_tx.send(AppEvent::SpawnImage(image.clone()))?;
                 }
-                event::KeyCode::Char('j' | 'о') => {
-                    self.chat_scroll_neg =
-                        Some(self.chat_scroll_neg.unwrap_or(0).saturating_sub(1));
-                    true
+                true
+            }
+            event::KeyCode::Char('u' | 'г') => {
+                if let Some(token) = &self.token {
+                    try_queue(
+                        &self.ws_tx,
+                        &mut self.event_tx,
+                        protocol::ClientMessage::ListUsers(token.clone()).into(),
+                    )?;
                 }
-                event::KeyCode::Char('k' | 'л') => {
-                    self.chat_scroll_neg =
-                        Some(self.chat_scroll_neg.unwrap_or(0).saturating_add(1));
-                    true
+                true
+            }
+            event::KeyCode::Char('/' | '.') => {
+                self.mode = Mode::Search;
+                self.search = Some(Search::default());
+                true
+            }
+            event::KeyCode::Char('n' | 'т') => self.step_search(true),
+            event::KeyCode::Char('N' | 'Т') => self.step_search(false),
+            event::KeyCode::Esc => self.search.take().is_some(),
+            _ => false,
+        })
+    }
+
+    fn handle_insert_key(&mut self, event: KeyEvent) -> Result<bool> {
+        Ok(match event.code {
+            event::KeyCode::Tab => self.complete_mention(),
+            event::KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                true
+            }
+            event::KeyCode::Enter
+                if event
+                    .modifiers
+                    .intersects(event::KeyModifiers::SHIFT | event::KeyModifiers::ALT) =>
+            {
+                self.insert_text("\n")?;
+                true
+            }
+            event::KeyCode::Enter => {
+                self.send_chat_message()?;
+                true
+            }
+            event::KeyCode::Up => {
+                if let Some(entry) = self.history.older(self.current_input.value()) {
+                    self.current_input = tui_input::Input::new(entry.to_string());
                 }
-                _ => false,
-            },
-            Mode::Insert => match event.code {
-                event::KeyCode::Esc => {
-                    self.mode = Mode::Normal;
-                    true
+                true
+            }
+            event::KeyCode::Down => {
+                if let Some(entry) = self.history.newer() {
+                    self.current_input = tui_input::Input::new(entry.to_string());
                 }
-                event::KeyCode::Enter => {
-                    self.send_chat_message()?;
-                    true
+                true
+            }
+            _ => {
+                let change = self.current_input.handle_event(&event::Event::Key(event));
+                if change.is_some_and(|change| change.value) {
+                    self.set_typing(true)?;
                 }
-                _ => self
-                    .current_input
-                    .handle_event(&event::Event::Key(event))
-                    .is_some(),
-            },
+                self.update_mention_popup();
+                change.is_some()
+            }
         })
     }
 
+    /// Types `text` at the cursor, as if it was typed char by char.
+    fn insert_text(&mut self, text: &str) -> Result<()> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        for c in text.chars() {
+            self.current_input
+                .handle(tui_input::InputRequest::InsertChar(c));
+        }
+        if !text.is_empty() {
+            self.set_typing(true)?;
+        }
+        self.update_mention_popup();
+        Ok(())
+    }
+
+    /// Edits the query of the search being typed, running it on `Enter`.
+    fn handle_search_key(&mut self, event: KeyEvent) -> Result<bool> {
+        let Some(search) = &mut self.search else {
+            self.mode = Mode::Normal;
+            return Ok(false);
+        };
+        match event.code {
+            event::KeyCode::Char(c) => search.query.push(c),
+            event::KeyCode::Backspace => _ = search.query.pop(),
+            event::KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                self.run_search()?;
+            }
+            event::KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.search = None;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Finds the lines matching the typed query and brings the newest one into view.
+    fn run_search(&mut self) -> Result<()> {
+        let Some(search) = &mut self.search else {
+            return Ok(());
+        };
+        search.matches = matching_lines(&self.received_messages, &search.query);
+        if search.matches.is_empty() {
+            let query = std::mem::take(&mut search.query);
+            self.search = None;
+            self.event_tx.notify(
+                format!("No messages match `{query}`."),
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?;
+            return Ok(());
+        }
+        search.current = search.matches.len() - 1;
+        self.scroll_to_match();
+        Ok(())
+    }
+
+    /// Matches the ran search against the buffer as it is now, since messages keep arriving
+    /// and replacing pending ones. The current match stays the same line if it still matches.
+    fn refresh_search(&mut self) {
+        let Some(search) = self.search.as_mut().filter(|_| self.mode != Mode::Search) else {
+            return;
+        };
+        let matches = matching_lines(&self.received_messages, &search.query);
+        let current_line = search.matches.get(search.current);
+        search.current = current_line
+            .and_then(|line| matches.iter().position(|i| i == line))
+            .unwrap_or(search.current.min(matches.len().saturating_sub(1)));
+        search.matches = matches;
+    }
+
+    /// Brings the next older match into view, or the next newer one if not `older`,
+    /// wrapping around at either end.
+    fn step_search(&mut self, older: bool) -> bool {
+        let Some(search) = self.search.as_mut().filter(|s| !s.matches.is_empty()) else {
+            return false;
+        };
+        let len = search.matches.len();
+        search.current = if older {
+            (search.current + len - 1) % len
+        } else {
+            (search.current + 1) % len
+        };
+        self.scroll_to_match();
+        true
+    }
+
+    /// Scrolls so that the current match is the bottom line of the chat.
+    fn scroll_to_match(&mut self) {
+        let Some(&line) = self
+            .search
+            .as_ref()
+            .and_then(|search| search.matches.get(search.current))
+        else {
+            return;
+        };
+        let below = self.received_messages.get(line + 1..).unwrap_or_default();
+        let below = Paragraph::new(below.to_vec())
+            .wrap(ratatui::widgets::Wrap { trim: false })
+            .line_count(self.view_width);
+        // `Some(0)` snaps back to the bottom on the next render
+        self.chat_scroll_neg = Some(below);
+    }
+
+    /// Scrolls [`Config::scroll_step`] lines towards older messages.
+    fn scroll_up(&mut self) {
+        self.chat_scroll_neg = Some(
+            self.chat_scroll_neg
+                .unwrap_or(0)
+                .saturating_add(self.scroll_step),
+        );
+    }
+
+    /// Scrolls [`Config::scroll_step`] lines towards the bottom.
+    fn scroll_down(&mut self) {
+        self.chat_scroll_neg = Some(
+            self.chat_scroll_neg
+                .unwrap_or(0)
+                .saturating_sub(self.scroll_step),
+        );
+    }
+
+    fn handle_mouse_event(&mut self, event: MouseEvent) -> bool {
+        match event.kind {
+            MouseEventKind::ScrollUp => self.scroll_up(),
+            MouseEventKind::ScrollDown => self.scroll_down(),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handles navigation within an open [`MentionPopup`].
+    /// Returns `false` for keys that should reach the input instead.
+    fn handle_mention_key(&mut self, event: KeyEvent) -> bool {
+        let Some(popup) = &mut self.mention_popup else {
+            return false;
+        };
+        match event.code {
+            event::KeyCode::Esc => {
+                self.mention_popup = None;
+                true
+            }
+            event::KeyCode::Down => {
+                popup.state.select_next();
+                true
+            }
+            event::KeyCode::Up => {
+                popup.state.select_previous();
+                true
+            }
+            event::KeyCode::Enter => {
+                let start = popup.start;
+                let selected = popup.state.selected().unwrap_or(0);
+                let candidates = self.mention_candidates(start);
+                self.mention_popup = None;
+                let Some(name) = candidates
+                    .get(selected.min(candidates.len().saturating_sub(1)))
+                    .cloned()
+                else {
+                    return false;
+                };
+                self.replace_mention(start, &name);
+                true
+            }
+            event::KeyCode::Char(' ') => {
+                self.mention_popup = None;
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Opens the [`MentionPopup`] right after an `@` starting a word is typed,
+    /// and closes it once the cursor leaves that word.
+    fn update_mention_popup(&mut self) {
+        let chars: Vec<char> = self.current_input.value().chars().collect();
+        let cursor = self.current_input.cursor();
+
+        if let Some(popup) = &self.mention_popup {
+            let left_word = cursor <= popup.start
+                || chars.get(popup.start) != Some(&'@')
+                || chars[popup.start..cursor].iter().any(|c| c.is_whitespace());
+            if left_word {
+                self.mention_popup = None;
+            }
+        } else if cursor > 0
+            && chars[cursor - 1] == '@'
+            && (cursor == 1 || chars[cursor - 2].is_whitespace())
+        {
+            self.mention_popup = Some(MentionPopup {
+                start: cursor - 1,
+                state: ListState::default().with_selected(Some(0)),
+            });
+        }
+    }
+
+    /// Known users whose names start with the word typed after the `@` at `start`.
+    fn mention_candidates(&self, start: usize) -> Vec<String> {
+        let prefix: String = self
+            .current_input
+            .value()
+            .chars()
+            .skip(start + 1)
+            .take(self.current_input.cursor().saturating_sub(start + 1))
+            .collect::<String>()
+            .to_lowercase();
+        self.known_users
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces the `@`-prefixed word at `start` with `@name`, moving the cursor after it.
+    fn replace_mention(&mut self, start: usize, name: &str) {
+        let chars: Vec<char> = self.current_input.value().chars().collect();
+        let word_end = chars[start..]
+            .iter()
+            .position(|c| c.is_whitespace())
+            .map_or(chars.len(), |i| start + i);
+
+        let mut value: String = chars[..start].iter().collect();
+        value.push('@');
+        value.push_str(name);
+        let new_cursor = value.chars().count();
+        value.extend(&chars[word_end..]);
+        self.current_input = tui_input::Input::new(value).with_cursor(new_cursor);
+    }
+
+    fn handle_close(&mut self, code: StatusCode, reason: Option<&str>) -> Result<()> {
+        #[allow(clippy::match_same_arms)]
+        let description = match code {
+            StatusCode::Normal => "Disconnected",
+            StatusCode::GoingAway => "Disconnected (server going away)",
+            StatusCode::PolicyViolated => "Kicked by the server",
+            StatusCode::ProtocolError => "Disconnected (protocol error)",
+            StatusCode::MessageTooBig => "Disconnected (message too big)",
+            StatusCode::InvalidPayloadData => "Disconnected (invalid data)",
+            StatusCode::CloseAbnormal => "Connection lost",
+            _ => "Disconnected",
+        };
+        self.closed = Some(code);
+        self.mode = Mode::Normal;
+        let reconnect = should_reconnect(code);
+        let code = u16::from(code);
+        self.event_tx.notify(
+            match reason {
+                Some(reason) => format!("{description} ({code}): {reason}"),
+                None => format!("{description} ({code})."),
+            },
+            Urgency::Error,
+            Duration::from_secs(10),
+        )?;
+        if reconnect {
+            self.event_tx.send(AppEvent::Reconnect)?;
+        }
+        Ok(())
+    }
+
     fn handle_ws_message(&mut self, message: &Message) -> Result<bool> {
-        if let Ok(server_msg) = protocol::ServerMessage::try_from(message) {
+        if let Message::Close(code, reason) = message {
+            self.handle_close(*code, reason.as_deref())?;
+        } else if let Ok(server_msg) = protocol::ServerMessage::try_from(message) {
             match server_msg {
-                protocol::ServerMessage::AuthSuccess(Err(e)) => {
+                protocol::ServerMessage::AuthSuccess(result) => self.handle_auth_result(result)?,
+                protocol::ServerMessage::PropagateMessage(sender, text, image, room, id) => {
+                    self.log_message(&sender, &text, &room);
+                    self.notice_mention(&sender, &text)?;
+                    self.show_message(
+                        chrono::Local::now(),
+                        sender,
+                        &text,
+                        image.as_ref().map(Vec::len),
+                        room,
+                        id,
+                    );
+                    if image.is_some() {
+                        self.last_image = image;
+                    }
+                }
+                protocol::ServerMessage::History(entries) if !self.history_received => {
+                    self.history_received = true;
+                    self.show_history(entries);
+                }
+                protocol::ServerMessage::Notification(notification) => {
+                    self.handle_notification(notification)?;
+                }
+                protocol::ServerMessage::UserTyping(sender, true) => {
+                    self.typing_users.insert(sender.name);
+                }
+                protocol::ServerMessage::UserTyping(sender, false) => {
+                    self.typing_users.remove(&sender.name);
+                }
+                protocol::ServerMessage::UserList(users) => {
+                    self.event_tx.send(AppEvent::SpawnUsers(users))?;
+                }
+                protocol::ServerMessage::PropagateDirect { from, to, text } => {
+                    self.known_users.insert(from.name.clone());
+                    let lines = self.message_lines(
+                        chrono::Local::now(),
+                        Span::raw(format!("[DM → {to}] ")).magenta()
+                            + Span::styled(from.name, into_ratatui_color(from.color)),
+                        &text,
+                        None,
+                    );
+                    self.received_messages.extend(lines);
+                }
+                // Accepted ones were already confirmed by the sender's copy, which comes first.
+                protocol::ServerMessage::Ack {
+                    id,
+                    accepted: false,
+                    reason,
+                } => {
+                    if let Some((room, start, len)) = id.and_then(|id| self.pending.remove(&id)) {
+                        self.mark_failed(room, start, len);
+                    }
+                    self.event_tx.notify(
+                        reason.unwrap_or_else(|| String::from("This message wasn't sent.")),
+                        Urgency::Warning,
+                        Duration::from_secs(3),
+                    )?;
+                }
+                protocol::ServerMessage::MessageRejected(reason) => {
+                    self.event_tx.notify(
+                        reason.to_string(),
+                        Urgency::Warning,
+                        Duration::from_secs(3),
+                    )?;
+                }
+                protocol::ServerMessage::ChangeNickFailed(e) => {
                     self.event_tx.notify(
                         match e {
-                            protocol::AuthError::NicknameUnavailable => {
-                                "This nickname is unavailable. Try again."
+                            protocol::AuthError::NicknameTooLong => "This nickname is too long.",
+                            protocol::AuthError::NicknameInvalid => {
+                                "Nicknames can't be blank or have control characters."
                             }
-                            protocol::AuthError::NicknameTooLong => {
-                                "This nickname is too long. Try again."
-                            }
-                            protocol::AuthError::AlreadyAuthorized => "You are already authorized.",
+                            _ => "This nickname is unavailable.",
                         },
                         Urgency::Warning,
                         Duration::from_secs(3),
                     )?;
-                    self.event_tx.send(AppEvent::SpawnAuth)?;
-                }
-                protocol::ServerMessage::AuthSuccess(Ok(token)) => {
-                    self.token = Some(token);
                 }
-                protocol::ServerMessage::PropagateMessage(sender, text, _image) => {
-                    self.received_messages.push(
-                        Span::styled(
-                            sender.name,
-                            Style::new().fg(into_ratatui_color(sender.color)),
-                        ) + Span::raw(": ")
-                            + Span::raw(text),
-                    );
-                }
-                protocol::ServerMessage::Notification(notif) => match notif {
-                    protocol::ServerNotification::Literal(text) => {
-                        self.event_tx.notify(
-                            String::from("Server: ") + &text,
-                            Urgency::Info,
-                            Duration::from_secs(5),
-                        )?;
-                    }
-                    protocol::ServerNotification::ClientConnected(sender) => {
-                        self.received_messages.push(
-                            Span::styled(sender.name, into_ratatui_color(sender.color))
-                                + Span::raw(" has connected.").gray().italic(),
-                        );
-                    }
-                    protocol::ServerNotification::ClientDisconnected(sender) => {
-                        self.received_messages.push(
-                            Span::styled(sender.name, into_ratatui_color(sender.color))
-                                + Span::raw(" has disconnected.").gray().italic(),
-                        );
-                    }
-                },
                 _ => {}
             }
         } else {
@@ -262,40 +1081,586 @@ impl Chat<'_> {
         Ok(true)
     }
 
-    fn send_chat_message(&mut self) -> Result<()> {
-        if self.token.is_none() {
-            return Ok(());
+    fn handle_auth_result(
+        &mut self,
+        result: Result<protocol::Token, protocol::AuthError>,
+    ) -> Result<()> {
+        match result {
+            Ok(token) => {
+                self.token = Some(token);
+                // rooms joined before reconnecting, the new session is only in the lobby
+                for room in self.joined_rooms() {
+                    if room != protocol::RoomId::default() {
+                        try_queue(
+                            &self.ws_tx,
+                            &mut self.event_tx,
+                            protocol::ClientMessage::JoinRoom(room).into(),
+                        )?;
+                    }
+                }
+            }
+            Err(e) => {
+                self.event_tx.notify(
+                    match e {
+                        protocol::AuthError::NicknameUnavailable => {
+                            "This nickname is unavailable. Try again."
+                        }
+                        protocol::AuthError::NicknameTooLong => {
+                            "This nickname is too long. Try again."
+                        }
+                        protocol::AuthError::NicknameInvalid => {
+                            "Nicknames can't be blank or have control characters. Try again."
+                        }
+                        protocol::AuthError::AlreadyAuthorized => "You are already authorized.",
+                        protocol::AuthError::UnknownToken => {
+                            "Your session has expired. Authenticate again."
+                        }
+                    },
+                    Urgency::Warning,
+                    Duration::from_secs(3),
+                )?;
+                self.event_tx.send(AppEvent::SpawnAuth)?;
+            }
         }
+        Ok(())
+    }
 
-        self.ws_tx.send(
-            protocol::ClientMessage::SendMessage {
-                token: self.token.clone().unwrap(),
-                text: self.current_input.to_string(),
-                image: None,
+    fn handle_notification(&mut self, notification: protocol::ServerNotification) -> Result<()> {
+        match notification {
+            protocol::ServerNotification::Literal(text) => {
+                self.event_tx.notify(
+                    String::from("Server: ") + &text,
+                    Urgency::Info,
+                    Duration::from_secs(5),
+                )?;
             }
-            .into(),
-        )?;
-        self.current_input.reset();
+            protocol::ServerNotification::ClientConnected(sender) => {
+                self.known_users.insert(sender.name.clone());
+                self.received_messages.push(
+                    Span::styled(sender.name, into_ratatui_color(sender.color))
+                        + Span::raw(" has connected.").gray().italic(),
+                );
+            }
+            protocol::ServerNotification::ClientDisconnected(sender) => {
+                self.known_users.remove(&sender.name);
+                self.typing_users.remove(&sender.name);
+                self.received_messages.push(
+                    Span::styled(sender.name, into_ratatui_color(sender.color))
+                        + Span::raw(" has disconnected.").gray().italic(),
+                );
+            }
+            protocol::ServerNotification::NicknameChanged(old_name, sender) => {
+                if self.nickname.as_ref() == Some(&old_name) {
+                    self.nickname = Some(sender.name.clone());
+                }
+                self.known_users.remove(&old_name);
+                self.known_users.insert(sender.name.clone());
+                if self.typing_users.remove(&old_name) {
+                    self.typing_users.insert(sender.name.clone());
+                }
+                let color = into_ratatui_color(sender.color);
+                self.received_messages.push(
+                    Span::styled(old_name, color)
+                        + Span::raw(" is now known as ").gray().italic()
+                        + Span::styled(sender.name, color),
+                );
+            }
+        }
         Ok(())
     }
-}
 
-#[async_trait::async_trait]
-impl Component for Chat<'_> {
-    async fn init(&mut self) -> Result<()> {
-        // See `Chat` doc
-        self.event_tx.send(AppEvent::SpawnAuth)?;
+    /// Tells the server whether the user is typing, only when that changes.
+    /// Typing again just postpones the stop, see [`TYPING_IDLE`].
+    fn set_typing(&mut self, typing: bool) -> Result<()> {
+        let Some(token) = self.token.clone() else {
+            return Ok(());
+        };
+        let was_typing = if typing {
+            self.typing_at.replace(Instant::now()).is_some()
+        } else {
+            self.typing_at.take().is_some()
+        };
+        if was_typing != typing {
+            try_queue(
+                &self.ws_tx,
+                &mut self.event_tx,
+                protocol::ClientMessage::Typing(token, typing).into(),
+            )?;
+        }
         Ok(())
     }
 
-    fn render(&mut self, frame: &mut Frame, area: Rect, _is_focused: bool) {
-        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Max(5)]);
-        let [chat_area, input_area] = layout.areas(area);
+    /// Lines a chat message is shown as: the text, and a placeholder for the image if any,
+    /// which is viewed through [`Chat::last_image`].
+    /// Control characters in the sender and the text are escaped, see [`protocol::escape_controls`].
+    fn message_lines(
+        &self,
+        sent_at: chrono::DateTime<chrono::Local>,
+        sender: impl Into<Line<'a>>,
+        text: &str,
+        image_size: Option<usize>,
+    ) -> Vec<Line<'a>> {
+        let timestamp =
+            Span::raw(format!("{} ", sent_at.format(self.timestamp_format))).dark_gray();
+        let mut line = Line::from(timestamp);
+        line.spans
+            .extend(sender.into().spans.into_iter().map(|mut span| {
+                if let Cow::Owned(escaped) = protocol::escape_controls(&span.content) {
+                    span.content = escaped.into();
+                }
+                span
+            }));
+        line.spans.push(Span::raw(SENDER_SEPARATOR));
+        let text = protocol::escape_controls(text);
+        let mut text_lines = text.lines();
+        line.spans
+            .extend(linkify(text_lines.next().unwrap_or_default()));
+        let mut lines = vec![line];
+        // the rest of a multi-line message is indented under the first line
+        lines.extend(text_lines.map(|text_line| {
+            let mut line = Line::from(Span::raw(CONTINUATION_INDENT));
+            line.spans.extend(linkify(text_line));
+            line
+        }));
+        if let Some(size) = image_size {
+            lines.push(Line::from(
+                Span::raw(format!("  [image, {}]", format_size(size)))
+                    .gray()
+                    .italic(),
+            ));
+        }
+        lines
+    }
+
+    /// Opens [`Chat::last_url`] in the browser, if any.
+    fn open_last_url(&mut self) -> Result<()> {
+        if let Some(url) = &self.last_url
+            && open::that_detached(url).is_err()
+        {
+            self.event_tx.notify(
+                format!("Couldn't open {url}."),
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Appends a propagated message to its room, or replaces the pending one it echoes.
+    fn show_message(
+        &mut self,
+        sent_at: chrono::DateTime<chrono::Local>,
+        sender: protocol::MessageSender,
+        text: &str,
+        image_size: Option<usize>,
+        room: protocol::RoomId,
+        id: Option<protocol::MessageId>,
+    ) {
+        self.known_users.insert(sender.name.clone());
+        if let Some(url) = text.split_whitespace().filter_map(as_url).next_back() {
+            self.last_url = Some(url.to_string());
+        }
+        let lines = self.message_lines(
+            sent_at,
+            Span::styled(
+                sender.name,
+                Style::new().fg(into_ratatui_color(sender.color)),
+            ),
+            text,
+            image_size,
+        );
+        match id.and_then(|id| self.pending.remove(&id)) {
+            Some((room, start, len)) => {
+                let buffer = self.room_buffer(room);
+                let end = (start + len).min(buffer.len());
+                buffer.splice(start.min(end)..end, lines);
+            }
+            None => self.room_buffer(room).extend(lines),
+        }
+    }
+
+    /// Appends messages sent before connecting, stamped with the time the server sent them.
+    fn show_history(&mut self, entries: Vec<protocol::HistoryEntry>) {
+        for entry in entries {
+            let protocol::ServerMessage::PropagateMessage(sender, text, _, room, _) = entry.message
+            else {
+                continue;
+            };
+            let sent_at = i64::try_from(entry.sent_at)
+                .ok()
+                .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+                .map_or_else(chrono::Local::now, |time| {
+                    time.with_timezone(&chrono::Local)
+                });
+            self.show_message(sent_at, sender, &text, entry.image_size, room, None);
+        }
+    }
+
+    /// Notifies the user, and rings the bell, if someone else's message mentions them.
+    fn notice_mention(&mut self, sender: &protocol::MessageSender, text: &str) -> Result<()> {
+        let Some(nickname) = &self.nickname else {
+            return Ok(());
+        };
+        if sender.name == *nickname || !mentions(text, nickname) {
+            return Ok(());
+        }
+        self.event_tx.notify(
+            format!("{} mentioned you.", sender.name),
+            Urgency::Warning,
+            Duration::from_secs(5),
+        )?;
+        self.event_tx.send(AppEvent::Bell)?;
+        Ok(())
+    }
+
+    /// Keeps a received message to be saved on quit, if enabled.
+    fn log_message(
+        &mut self,
+        sender: &protocol::MessageSender,
+        text: &str,
+        room: &protocol::RoomId,
+    ) {
+        if self.log_path.is_none() {
+            return;
+        }
+        if self.log.len() == scrollback::CAPACITY {
+            self.log.pop_front();
+        }
+        self.log.push_back(LoggedMessage {
+            sender: sender.clone(),
+            text: text.to_string(),
+            room: room.clone(),
+            sent_at: chrono::Local::now().timestamp(),
+        });
+    }
+
+    /// Shows messages saved by a previous session dimmed, above whatever arrives next.
+    fn restore_log(&mut self, log: Vec<LoggedMessage>) {
+        let mut rooms = BTreeSet::new();
+        for entry in &log {
+            let sent_at = chrono::DateTime::from_timestamp(entry.sent_at, 0)
+                .map_or_else(chrono::Local::now, |time| {
+                    time.with_timezone(&chrono::Local)
+                });
+            let lines: Vec<_> = self
+                .message_lines(
+                    sent_at,
+                    Span::raw(entry.sender.name.clone()),
+                    &entry.text,
+                    None,
+                )
+                .into_iter()
+                .map(Line::dark_gray)
+                .collect();
+            self.room_buffer(entry.room.clone()).extend(lines);
+            rooms.insert(entry.room.clone());
+        }
+        for room in rooms {
+            self.room_buffer(room)
+                .push(Line::raw("── previous session ──").dark_gray().centered());
+        }
+        self.log = log.into();
+    }
+
+    /// Marks the lines of a message that won't be echoed anymore.
+    fn mark_failed(&mut self, room: protocol::RoomId, start: usize, len: usize) {
+        let buffer = self.room_buffer(room);
+        for line in buffer.iter_mut().skip(start).take(len) {
+            *line = std::mem::take(line).red();
+        }
+    }
+
+    /// All joined rooms, including the active one, in order.
+    fn joined_rooms(&self) -> Vec<protocol::RoomId> {
+        let mut rooms: Vec<protocol::RoomId> = self.room_buffers.keys().cloned().collect();
+        let index = rooms.binary_search(&self.active_room).unwrap_or_else(|i| i);
+        rooms.insert(index, self.active_room.clone());
+        rooms
+    }
+
+    /// Scrollback of the given room, joining it if it's not known yet.
+    fn room_buffer(&mut self, room: protocol::RoomId) -> &mut Vec<Line<'a>> {
+        if room == self.active_room {
+            &mut self.received_messages
+        } else {
+            self.room_buffers.entry(room).or_default()
+        }
+    }
+
+    /// Makes `room` the active one, restoring its scrollback.
+    fn switch_room(&mut self, room: protocol::RoomId) {
+        if room == self.active_room {
+            return;
+        }
+        let restored = self.room_buffers.remove(&room).unwrap_or_default();
+        let stashed = std::mem::replace(&mut self.received_messages, restored);
+        let previous = std::mem::replace(&mut self.active_room, room);
+        self.room_buffers.insert(previous, stashed);
+        self.chat_scroll_neg = None;
+        self.search = None;
+    }
+
+    /// Completes the `@`-prefixed word under the cursor against [`Chat::known_users`].
+    /// Repeated calls cycle through the matches.
+    fn complete_mention(&mut self) -> bool {
+        let chars: Vec<char> = self.current_input.value().chars().collect();
+        let cursor = self.current_input.cursor();
+
+        if let Some(completion) = &mut self.completion {
+            completion.index = (completion.index + 1) % completion.candidates.len();
+        } else {
+            let start = chars[..cursor]
+                .iter()
+                .rposition(|c| c.is_whitespace())
+                .map_or(0, |i| i + 1);
+            if chars.get(start) != Some(&'@') {
+                return false;
+            }
+            let candidates = self.mention_candidates(start);
+            if candidates.is_empty() {
+                return false;
+            }
+            self.completion = Some(Completion {
+                start,
+                candidates,
+                index: 0,
+            });
+        }
+
+        let completion = self.completion.as_ref().unwrap();
+        let (start, name) = (
+            completion.start,
+            completion.candidates[completion.index].clone(),
+        );
+        self.replace_mention(start, &name);
+        true
+    }
+
+    fn send_chat_message(&mut self) -> Result<()> {
+        if self.token.is_none() || self.closed.is_some() {
+            return Ok(());
+        }
+
+        let input = self.current_input.to_string();
+        self.history.push(input.clone());
+        self.current_input.reset();
+        self.set_typing(false)?;
+        if input.starts_with('/') {
+            return self.run_command(&input);
+        }
+
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        let image = self.attachment.take().map(|(_, bytes)| bytes);
+        let lines: Vec<_> = self
+            .message_lines(
+                chrono::Local::now(),
+                Span::raw("you"),
+                &input,
+                image.as_ref().map(Vec::len),
+            )
+            .into_iter()
+            .map(|line| line.dark_gray().italic())
+            .collect();
+        let (start, len) = (self.received_messages.len(), lines.len());
+        self.received_messages.extend(lines);
+
+        let sent = self.ws_tx.try_send(
+            protocol::ClientMessage::SendMessage {
+                token: self.token.clone().unwrap(),
+                text: input,
+                image,
+                room: self.active_room.clone(),
+                id: Some(id),
+            }
+            .into(),
+        );
+        match sent {
+            Ok(()) => {
+                self.pending
+                    .insert(id, (self.active_room.clone(), start, len));
+            }
+            Err(e) => {
+                self.mark_failed(self.active_room.clone(), start, len);
+                // the connection can't keep up, rather than queueing forever
+                if let TrySendError::Full(_) = e {
+                    self.event_tx.notify(
+                        "Sending too fast, this message wasn't sent.",
+                        Urgency::Warning,
+                        Duration::from_secs(3),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a `/`-prefixed input line, e.g. `/nick <name>`, instead of sending it.
+    fn run_command(&mut self, line: &str) -> Result<()> {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            "/attach" => self.attach(argument),
+            "/nick" if !argument.is_empty() => {
+                try_queue(
+                    &self.ws_tx,
+                    &mut self.event_tx,
+                    protocol::ClientMessage::ChangeNick(
+                        self.token.clone().unwrap(),
+                        argument.to_string(),
+                    )
+                    .into(),
+                )?;
+                Ok(())
+            }
+            "/nick" => Ok(self.event_tx.notify(
+                "Usage: /nick <name>",
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?),
+            "/msg" => match argument.split_once(' ') {
+                Some((to, text)) if !text.trim().is_empty() => {
+                    try_queue(
+                        &self.ws_tx,
+                        &mut self.event_tx,
+                        protocol::ClientMessage::DirectMessage {
+                            token: self.token.clone().unwrap(),
+                            to: to.to_string(),
+                            text: text.trim().to_string(),
+                        }
+                        .into(),
+                    )?;
+                    Ok(())
+                }
+                _ => Ok(self.event_tx.notify(
+                    "Usage: /msg <name> <text>",
+                    Urgency::Warning,
+                    Duration::from_secs(3),
+                )?),
+            },
+            _ => Ok(self.event_tx.notify(
+                format!("Unknown command `{command}`."),
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?),
+        }
+    }
+
+    /// Reads the image at `path` to be sent with the next message.
+    fn attach(&mut self, path: &str) -> Result<()> {
+        match std::fs::read(path) {
+            Ok(bytes) => {
+                let name = std::path::Path::new(path)
+                    .file_name()
+                    .map_or_else(|| path.to_string(), |n| n.to_string_lossy().into_owned());
+                self.event_tx.notify(
+                    format!("Attached {name} ({})", format_size(bytes.len())),
+                    Urgency::Info,
+                    Duration::from_secs(3),
+                )?;
+                self.attachment = Some((name, bytes));
+            }
+            Err(e) => {
+                self.event_tx.notify(
+                    format!("Couldn't attach {path}: {e}"),
+                    Urgency::Warning,
+                    Duration::from_secs(3),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Chat<'_> {
+    /// Renders the [`MentionPopup`] in the bottom left corner of `area`, right above the input.
+    fn render_mention_popup(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(start) = self.mention_popup.as_ref().map(|popup| popup.start) else {
+            return;
+        };
+        let candidates = self.mention_candidates(start);
+        if candidates.is_empty() {
+            return;
+        }
+
+        let width = candidates
+            .iter()
+            .map(|name| name.chars().count() as u16 + 4)
+            .max()
+            .unwrap_or(0)
+            .max(12)
+            .min(area.width);
+        let height = (candidates.len().min(5) as u16 + 2).min(area.height);
+        let popup_area = Rect {
+            x: area.x + 1,
+            y: area.bottom().saturating_sub(height),
+            width,
+            height,
+        };
+
+        let list = List::new(candidates)
+            .block(Block::bordered().border_type(BorderType::Rounded))
+            .highlight_symbol(">")
+            .blue();
+        frame.render_widget(Clear, popup_area);
+        StatefulWidget::render(
+            list,
+            popup_area,
+            frame.buffer_mut(),
+            &mut self.mention_popup.as_mut().unwrap().state,
+        );
+    }
+}
+
+#[async_trait::async_trait]
+impl Component for Chat<'_> {
+    async fn init(&mut self) -> Result<()> {
+        // See `Chat` doc
+        self.event_tx.send(AppEvent::SpawnAuth)?;
+        if let Some(path) = &self.log_path {
+            match scrollback::load(path) {
+                Ok(log) => self.restore_log(log),
+                Err(_) => self.event_tx.notify(
+                    "Couldn't read the saved chat history.",
+                    Urgency::Warning,
+                    Duration::from_secs(3),
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    fn quit(&mut self) {
+        if let Some(path) = &self.log_path {
+            // the terminal is being restored, there's nowhere left to report it
+            _ = scrollback::save(path, self.log.make_contiguous());
+        }
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect, _is_focused: bool) {
+        if self
+            .typing_at
+            .is_some_and(|typing_at| typing_at.elapsed() >= TYPING_IDLE)
+        {
+            // Failing to send means the connection is gone, which is handled elsewhere.
+            _ = self.set_typing(false);
+        }
+
+        self.refresh_search();
+
+        let layout = Layout::vertical([Constraint::Fill(1), Constraint::Max(5)]);
+        let [chat_area, input_area] = layout.areas(area);
 
         let chat_widget = ChatWidget {
             messages: &self.received_messages,
             scroll_neg: &mut self.chat_scroll_neg,
+            view_height: &mut self.view_height,
+            view_width: &mut self.view_width,
+            search: self.search.as_ref(),
             authorized: self.token.is_some(),
+            closed: self.closed,
+            room: &self.active_room,
+            typing: &self.typing_users,
         };
         // Mutates the outer state. In my defence,
         // that specific part is determined during rendering.
@@ -305,18 +1670,983 @@ impl Component for Chat<'_> {
             input: &self.current_input,
             mode: self.mode,
             scroll: &mut self.input_scroll,
+            attachment: self.attachment.as_ref().map(|(name, _)| name.as_str()),
         };
         if self.mode == Mode::Insert {
             frame.set_cursor_position(input_widget.cursor_position(input_area));
         }
         input_widget.render(input_area, frame.buffer_mut());
+
+        if self.mode == Mode::Insert {
+            self.render_mention_popup(frame, chat_area);
+        }
     }
 
     async fn handle_event(&mut self, event: AppEvent, is_focused: bool) -> Result<bool> {
         Ok(match event {
             AppEvent::KeyEvent(key_event) if is_focused => self.handle_key_event(key_event)?,
+            AppEvent::Mouse(mouse_event) if is_focused => self.handle_mouse_event(mouse_event),
+            AppEvent::Paste(text) if is_focused && self.mode == Mode::Insert => {
+                self.insert_text(&text)?;
+                true
+            }
             AppEvent::WsMessage(msg) => self.handle_ws_message(&msg)?,
+            AppEvent::Authenticating(name) => {
+                self.nickname = Some(name);
+                true
+            }
+            AppEvent::SwitchRoom(room) => {
+                self.switch_room(room);
+                true
+            }
+            AppEvent::Reconnected => {
+                // the old session is gone, and so are the echoes it owed
+                for (room, start, len) in std::mem::take(&mut self.pending).into_values() {
+                    self.mark_failed(room, start, len);
+                }
+                self.closed = None;
+                self.typing_users.clear();
+                self.typing_at = None;
+                // the server may still hold the old identity, see `ClientMessage::Resume`
+                match self.token.clone() {
+                    // kept until the request is queued, or it's never made again
+                    Some(token) => {
+                        if try_queue(
+                            &self.ws_tx,
+                            &mut self.event_tx,
+                            protocol::ClientMessage::Resume(token).into(),
+                        )? {
+                            self.token = None;
+                        }
+                    }
+                    None => self.event_tx.send(AppEvent::SpawnAuth)?,
+                }
+                true
+            }
             _ => false,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use common::protocol;
+    use ratatui::{
+        crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent, MouseEventKind},
+        layout::Rect,
+        style::{Color, Modifier, Style, Stylize},
+        text::{Line, Span},
+    };
+    use tokio::sync::mpsc::{Receiver, UnboundedReceiver, channel, unbounded_channel};
+    use websocket::message::{Message, StatusCode};
+
+    use super::{
+        Chat, HISTORY_CAPACITY, InputHistory, InputWidget, Mode, highlight, linkify, mentions,
+        scroll_from_top, wrap_input,
+    };
+    use crate::{
+        AppEvent, EventSender, component::Component, components::Urgency, config::Config,
+        scrollback::LoggedMessage,
+    };
+
+    /// A chat with the default config, along with the other ends of its channels.
+    fn chat() -> (
+        Box<Chat<'static>>,
+        Receiver<Message>,
+        UnboundedReceiver<AppEvent>,
+    ) {
+        chat_with(&Config::default())
+    }
+
+    fn chat_with(
+        config: &Config,
+    ) -> (
+        Box<Chat<'static>>,
+        Receiver<Message>,
+        UnboundedReceiver<AppEvent>,
+    ) {
+        let (ws_tx, ws_rx) = channel(16);
+        let (event_tx, event_rx) = unbounded_channel();
+        (
+            Chat::new(ws_tx, EventSender(event_tx), config),
+            ws_rx,
+            event_rx,
+        )
+    }
+
+    fn alice() -> protocol::MessageSender {
+        protocol::MessageSender {
+            name: String::from("alice"),
+            color: protocol::Color::default(),
+        }
+    }
+
+    fn connected(name: &str) -> Message {
+        protocol::ServerMessage::Notification(protocol::ServerNotification::ClientConnected(
+            protocol::MessageSender {
+                name: name.to_string(),
+                color: protocol::Color::default(),
+            },
+        ))
+        .into()
+    }
+
+    fn type_str(chat: &mut Chat, text: &str) {
+        for c in text.chars() {
+            chat.handle_key_event(KeyEvent::from(KeyCode::Char(c)))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn scroll_step_applies_per_keypress() {
+        let config = Config::from_toml("scroll_step = 3").unwrap();
+        let (mut chat, _ws_rx, _event_rx) = chat_with(&config);
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('k')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(3));
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('k')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(6));
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('j')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(3));
+    }
+
+    #[test]
+    fn mentions_are_whole_words() {
+        assert!(mentions("hey Bob!", "bob"));
+        assert!(mentions("@bob, look", "Bob"));
+        assert!(mentions("bobby and bob", "bob"));
+        assert!(!mentions("bobby", "bob"));
+        assert!(!mentions("jimbob_", "bob"));
+        assert!(!mentions("anything", ""));
+    }
+
+    #[test]
+    fn mentions_ring_the_bell() {
+        let (mut chat, _ws_rx, mut event_rx) = chat();
+        chat.nickname = Some(String::from("bob"));
+        let mut say = |name: &str, text: &str| {
+            chat.handle_ws_message(
+                &protocol::ServerMessage::PropagateMessage(
+                    protocol::MessageSender {
+                        name: name.to_string(),
+                        color: protocol::Color::default(),
+                    },
+                    text.to_string(),
+                    None,
+                    protocol::RoomId::default(),
+                    None,
+                )
+                .into(),
+            )
+            .unwrap();
+        };
+
+        say("alice", "ask bobby");
+        say("bob", "I'm bob");
+        assert!(event_rx.try_recv().is_err());
+
+        say("alice", "hi @Bob");
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::Notify(_, Urgency::Warning, _))
+        ));
+        assert_eq!(event_rx.try_recv(), Ok(AppEvent::Bell));
+    }
+
+    #[test]
+    fn restored_log_is_shown_and_kept() {
+        let config =
+            Config::from_toml("save_history = true\nhistory_path = \"/nonexistent\"").unwrap();
+        let (mut chat, _ws_rx, _event_rx) = chat_with(&config);
+
+        chat.restore_log(vec![LoggedMessage {
+            sender: alice(),
+            text: String::from("from yesterday"),
+            room: protocol::RoomId::default(),
+            sent_at: 1_700_000_000,
+        }]);
+        // the message and the separator
+        assert_eq!(chat.received_messages.len(), 2);
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::PropagateMessage(
+                alice(),
+                String::from("today"),
+                None,
+                protocol::RoomId::default(),
+                None,
+            )
+            .into(),
+        )
+        .unwrap();
+        let texts: Vec<_> = chat.log.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(texts, ["from yesterday", "today"]);
+    }
+
+    #[test]
+    fn search_cycles_through_matches() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.view_width = 80;
+        // the sender's name doesn't count as a match
+        let lines = ["Rust is fun", "lunch?", "more rust", "bye"]
+            .into_iter()
+            .flat_map(|text| {
+                chat.message_lines(chrono::Local::now(), Span::raw("rustacean"), text, None)
+            })
+            .collect();
+        chat.received_messages = lines;
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        assert_eq!(chat.mode, Mode::Search);
+        type_str(&mut chat, "rust");
+        chat.handle_key_event(KeyEvent::from(KeyCode::Enter))
+            .unwrap();
+        assert_eq!(chat.mode, Mode::Normal);
+        assert_eq!(chat.search.as_ref().unwrap().matches, [0, 2]);
+        // the newest match is the bottom line, with one line below it
+        assert_eq!(chat.chat_scroll_neg, Some(1));
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(3));
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(1));
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('N')))
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(3));
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Esc)).unwrap();
+        assert!(chat.search.is_none());
+    }
+
+    #[test]
+    fn search_follows_new_messages() {
+        fn message<'a>(chat: &Chat<'a>, sender: &'a str, text: &str) -> Vec<Line<'a>> {
+            chat.message_lines(chrono::Local::now(), Span::raw(sender), text, None)
+        }
+
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.view_width = 80;
+        let mut lines = message(&chat, "alice", "hi");
+        lines.extend(message(&chat, "bob", "hi alice\nhow are you?"));
+        chat.received_messages = lines;
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        type_str(&mut chat, "alice");
+        chat.handle_key_event(KeyEvent::from(KeyCode::Enter))
+            .unwrap();
+        assert_eq!(chat.search.as_ref().unwrap().matches, [1]);
+
+        let lines = message(&chat, "carol", "alice?");
+        chat.received_messages.extend(lines);
+        chat.refresh_search();
+        let search = chat.search.as_ref().unwrap();
+        assert_eq!(search.matches, [1, 3]);
+        assert_eq!(search.current, 0);
+
+        assert_eq!(
+            highlight(&chat.received_messages[3], "carol"),
+            chat.received_messages[3]
+        );
+    }
+
+    #[test]
+    fn search_without_matches_notifies() {
+        let (mut chat, _ws_rx, mut event_rx) = chat();
+        chat.received_messages = vec![Line::raw("hello")];
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        type_str(&mut chat, "nope");
+        chat.handle_key_event(KeyEvent::from(KeyCode::Enter))
+            .unwrap();
+        assert!(chat.search.is_none());
+        assert_eq!(chat.chat_scroll_neg, None);
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::Notify(_, Urgency::Warning, _))
+        ));
+    }
+
+    #[test]
+    fn matches_are_highlighted_within_spans() {
+        let line = Line::from(vec![
+            Span::raw("alice").red(),
+            Span::raw(": "),
+            Span::raw("Hi, hi"),
+        ]);
+        let highlighted = highlight(&line, "hi");
+        let contents: Vec<_> = highlighted
+            .spans
+            .iter()
+            .map(|s| s.content.as_ref())
+            .collect();
+        assert_eq!(contents, ["alice", ": ", "Hi", ", ", "hi"]);
+        assert_eq!(highlighted.spans[0].style, line.spans[0].style);
+        assert_eq!(highlighted.spans[2].style.bg, Some(Color::Yellow));
+    }
+
+    #[test]
+    fn scroll_is_pinned_or_offset_from_the_bottom() {
+        for (text_height, view_height) in [
+            (0usize, 10),
+            (5, 10),
+            (10, 10),
+            (11, 10),
+            (100, 10),
+            (100, 0),
+        ] {
+            let last_page = text_height.saturating_sub(view_height);
+
+            let mut pinned = None;
+            assert_eq!(
+                scroll_from_top(text_height, view_height, &mut pinned),
+                last_page
+            );
+            assert_eq!(pinned, None);
+
+            let mut bottom = Some(0);
+            assert_eq!(
+                scroll_from_top(text_height, view_height, &mut bottom),
+                last_page
+            );
+            assert_eq!(bottom, None);
+
+            let mut top = Some(usize::MAX);
+            assert_eq!(scroll_from_top(text_height, view_height, &mut top), 0);
+            assert_eq!(top, (last_page > 0).then_some(last_page));
+
+            let mut offset = Some(3);
+            let scroll = scroll_from_top(text_height, view_height, &mut offset);
+            assert_eq!(scroll, last_page.saturating_sub(3));
+            // rendering again doesn't move it
+            assert_eq!(
+                scroll_from_top(text_height, view_height, &mut offset),
+                scroll
+            );
+        }
+
+        // new lines arriving keep the pinned view on the last ones
+        let mut pinned = None;
+        assert_eq!(scroll_from_top(30, 10, &mut pinned), 20);
+        assert_eq!(scroll_from_top(31, 10, &mut pinned), 21);
+    }
+
+    #[test]
+    fn page_keys_scroll_by_view_height() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.view_height = 10;
+
+        let mut press = |code| {
+            chat.handle_key_event(KeyEvent::from(code)).unwrap();
+            chat.chat_scroll_neg
+        };
+        assert_eq!(press(KeyCode::PageUp), Some(10));
+        assert_eq!(press(KeyCode::PageUp), Some(20));
+        assert_eq!(press(KeyCode::PageDown), Some(10));
+        assert_eq!(press(KeyCode::Home), Some(usize::MAX));
+        assert_eq!(press(KeyCode::End), None);
+    }
+
+    #[tokio::test]
+    async fn mouse_wheel_scrolls_only_when_focused() {
+        let config = Config::from_toml("scroll_step = 3").unwrap();
+        let (mut chat, _ws_rx, _event_rx) = chat_with(&config);
+        let wheel = |kind| {
+            AppEvent::Mouse(MouseEvent {
+                kind,
+                column: 0,
+                row: 0,
+                modifiers: KeyModifiers::NONE,
+            })
+        };
+
+        assert!(
+            !chat
+                .handle_event(wheel(MouseEventKind::ScrollUp), false)
+                .await
+                .unwrap()
+        );
+        assert_eq!(chat.chat_scroll_neg, None);
+        chat.handle_event(wheel(MouseEventKind::ScrollUp), true)
+            .await
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(3));
+        chat.handle_event(wheel(MouseEventKind::ScrollDown), true)
+            .await
+            .unwrap();
+        assert_eq!(chat.chat_scroll_neg, Some(0));
+    }
+
+    #[test]
+    fn close_reason_is_notified() {
+        let (mut chat, _ws_rx, mut event_rx) = chat();
+
+        chat.handle_ws_message(&Message::Close(
+            StatusCode::GoingAway,
+            Some(String::from("restarting")),
+        ))
+        .unwrap();
+
+        let Ok(AppEvent::Notify(text, Urgency::Error, _)) = event_rx.try_recv() else {
+            panic!("expected an error notification");
+        };
+        let text = text.to_string();
+        assert!(text.contains("going away"));
+        assert!(text.contains("1001"));
+        assert!(text.contains("restarting"));
+
+        assert!(
+            !chat
+                .handle_key_event(KeyEvent::from(KeyCode::Char('i')))
+                .unwrap()
+        );
+        assert_eq!(chat.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn only_abnormal_drops_reconnect() {
+        let (mut going_away, _ws_rx, mut event_rx) = chat();
+        going_away
+            .handle_ws_message(&Message::Close(StatusCode::GoingAway, None))
+            .unwrap();
+        let events: Vec<_> = std::iter::from_fn(|| event_rx.try_recv().ok()).collect();
+        assert!(!events.contains(&AppEvent::Reconnect));
+
+        let (mut chat, _ws_rx, mut event_rx) = chat();
+        chat.handle_ws_message(&Message::Close(StatusCode::CloseAbnormal, None))
+            .unwrap();
+        let events: Vec<_> = std::iter::from_fn(|| event_rx.try_recv().ok()).collect();
+        assert!(events.contains(&AppEvent::Reconnect));
+        let Some(AppEvent::Notify(text, ..)) = events.first() else {
+            panic!("expected a notification");
+        };
+        assert!(text.to_string().contains("Connection lost"));
+    }
+
+    #[test]
+    fn attached_image_is_sent_once() {
+        let path = std::env::temp_dir().join(format!("tungsto-attach-{}.png", std::process::id()));
+        std::fs::write(&path, [0x89, b'P', b'N', b'G']).unwrap();
+
+        let (mut chat, mut ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+
+        chat.current_input = tui_input::Input::new(format!("/attach {}", path.display()));
+        chat.send_chat_message().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(ws_rx.try_recv().is_err());
+        assert!(chat.current_input.value().is_empty());
+
+        let mut sent_images = vec![];
+        for text in ["look", "again"] {
+            chat.current_input = tui_input::Input::new(String::from(text));
+            chat.send_chat_message().unwrap();
+            let Ok(protocol::ClientMessage::SendMessage { image, .. }) =
+                protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap())
+            else {
+                panic!("expected a chat message");
+            };
+            sent_images.push(image);
+        }
+        assert_eq!(sent_images, [Some(vec![0x89, b'P', b'N', b'G']), None]);
+    }
+
+    #[test]
+    fn links_are_highlighted() {
+        let spans = linkify("see https://example.com/a?b, or http://x.org");
+        let contents: Vec<_> = spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(
+            contents,
+            ["see ", "https://example.com/a?b", ", or ", "http://x.org"]
+        );
+        assert!(spans[1].style.add_modifier.contains(Modifier::UNDERLINED));
+        assert_eq!(spans[0].style, Style::default());
+
+        let plain = linkify("https:// isn't a link");
+        assert_eq!(plain.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn newlines_are_typed_with_a_modifier_or_pasted() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.mode = Mode::Insert;
+
+        type_str(&mut chat, "a");
+        chat.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT))
+            .unwrap();
+        type_str(&mut chat, "b");
+        chat.handle_key_event(KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT))
+            .unwrap();
+        assert!(
+            chat.handle_event(AppEvent::Paste(String::from("c\r\nd")), true)
+                .await
+                .unwrap()
+        );
+        assert_eq!(chat.current_input.value(), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn input_cursor_follows_wrapped_rows() {
+        assert_eq!(wrap_input("abcdef\n\ngh", 4, 0).0, ["abcd", "ef", "", "gh"]);
+
+        let input = tui_input::Input::new(String::from("abcdef\ngh"));
+        let mut scroll = 0;
+        let mut widget = InputWidget {
+            input: &input,
+            mode: Mode::Insert,
+            scroll: &mut scroll,
+            attachment: None,
+        };
+        // 4 columns and 2 rows inside the borders, so the third row scrolls
+        assert_eq!(widget.cursor_position(Rect::new(0, 0, 6, 4)), (3, 2));
+        assert_eq!(scroll, 1);
+
+        let input = tui_input::Input::new(String::from("abcd"));
+        let mut widget = InputWidget {
+            input: &input,
+            mode: Mode::Insert,
+            scroll: &mut scroll,
+            attachment: None,
+        };
+        assert_eq!(widget.cursor_position(Rect::new(0, 0, 6, 4)), (1, 2));
+        assert_eq!(scroll, 0);
+    }
+
+    #[test]
+    fn wide_chars_are_measured_in_cells() {
+        // `字` takes two cells, and doesn't fit after `ab漢`
+        let text = "ab漢字c";
+        let (rows, _) = wrap_input(text, 4, 0);
+        assert_eq!(rows, ["ab漢", "字c"]);
+        let positions: Vec<_> = (0..=text.chars().count())
+            .map(|cursor| wrap_input(text, 4, cursor).1)
+            .collect();
+        assert_eq!(positions, [(0, 0), (0, 1), (0, 2), (1, 0), (1, 2), (1, 3)]);
+
+        // typing past the box height scrolls just enough to keep the cursor in it
+        let mut input = tui_input::Input::default();
+        let mut scroll = 0;
+        for (typed, c) in (1usize..).zip("日本語のテキスト".chars()) {
+            input.handle(tui_input::InputRequest::InsertChar(c));
+            let mut widget = InputWidget {
+                input: &input,
+                mode: Mode::Insert,
+                scroll: &mut scroll,
+                attachment: None,
+            };
+            // two wide chars per row, and two rows
+            let (x, y) = widget.cursor_position(Rect::new(0, 0, 6, 4));
+            assert!((1..=2).contains(&y));
+            assert_eq!(x, if typed % 2 == 0 { 1 } else { 3 });
+            assert_eq!(scroll, (typed / 2).saturating_sub(1));
+        }
+    }
+
+    #[test]
+    fn multi_line_messages_are_indented() {
+        let (chat, _ws_rx, _event_rx) = chat();
+        let lines = chat.message_lines(chrono::Local::now(), Span::raw("you"), "one\ntwo", None);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].to_string().ends_with("you: one"));
+        assert_eq!(lines[1].to_string(), "  two");
+    }
+
+    #[test]
+    fn control_sequences_are_escaped() {
+        let (chat, _ws_rx, _event_rx) = chat();
+        let lines = chat.message_lines(
+            chrono::Local::now(),
+            Span::raw("eve\x1b[8m"),
+            "\x1b[2Jwiped\x08\x08\x08ped",
+            None,
+        );
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].to_string().ends_with("eve␛[8m: ␛[2Jwiped␈␈␈ped"));
+    }
+
+    #[test]
+    fn last_link_is_remembered() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        for text in [
+            "https://first.example",
+            "no links",
+            "then https://second.example.",
+        ] {
+            chat.show_message(
+                chrono::Local::now(),
+                alice(),
+                text,
+                None,
+                protocol::RoomId::default(),
+                None,
+            );
+        }
+        assert_eq!(chat.last_url.as_deref(), Some("https://second.example"));
+    }
+
+    #[test]
+    fn last_image_is_shown_with_v() {
+        let (mut chat, _ws_rx, mut event_rx) = chat();
+        for image in [Some(vec![1, 2, 3]), None] {
+            chat.handle_ws_message(
+                &protocol::ServerMessage::PropagateMessage(
+                    alice(),
+                    String::from("look"),
+                    image,
+                    protocol::RoomId::default(),
+                    None,
+                )
+                .into(),
+            )
+            .unwrap();
+        }
+
+        chat.handle_key_event(KeyEvent::from(KeyCode::Char('v')))
+            .unwrap();
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::SpawnImage(image)) if image == [1, 2, 3]
+        ));
+    }
+
+    #[test]
+    fn full_queue_fails_the_message() {
+        let (ws_tx, mut ws_rx) = channel(1);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let mut chat = Chat::new(ws_tx, EventSender(event_tx), &Config::default());
+        chat.token = Some(String::from("token"));
+
+        for text in ["first", "second"] {
+            chat.current_input = tui_input::Input::new(String::from(text));
+            chat.send_chat_message().unwrap();
+        }
+        assert!(ws_rx.try_recv().is_ok());
+        assert!(ws_rx.try_recv().is_err());
+        assert_eq!(chat.pending.len(), 1);
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::Notify(_, Urgency::Warning, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn full_queue_keeps_the_token_and_notifies() {
+        let (ws_tx, _ws_rx) = channel(1);
+        let (event_tx, mut event_rx) = unbounded_channel();
+        let mut chat = Chat::new(ws_tx, EventSender(event_tx), &Config::default());
+        chat.token = Some(String::from("token"));
+        chat.ws_tx.try_send(Message::text("filler")).unwrap();
+
+        chat.current_input = tui_input::Input::new(String::from("/nick bob"));
+        chat.send_chat_message().unwrap();
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::Notify(_, Urgency::Warning, _))
+        ));
+
+        chat.handle_event(AppEvent::Reconnected, false)
+            .await
+            .unwrap();
+        assert_eq!(chat.token.as_deref(), Some("token"));
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(AppEvent::Notify(_, Urgency::Warning, _))
+        ));
+    }
+
+    #[test]
+    fn switching_rooms_restores_buffer() {
+        let (mut chat, mut ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+        let lobby = protocol::RoomId::default();
+        let rust = protocol::RoomId(String::from("rust"));
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::PropagateMessage(
+                alice(),
+                String::from("hi"),
+                None,
+                lobby.clone(),
+                None,
+            )
+            .into(),
+        )
+        .unwrap();
+        assert_eq!(chat.received_messages.len(), 1);
+
+        chat.switch_room(rust.clone());
+        assert_eq!(chat.active_room, rust);
+        assert!(chat.received_messages.is_empty());
+        assert_eq!(chat.joined_rooms(), vec![lobby.clone(), rust.clone()]);
+
+        chat.current_input = tui_input::Input::new(String::from("hey"));
+        chat.send_chat_message().unwrap();
+        let Ok(protocol::ClientMessage::SendMessage { room, .. }) =
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap())
+        else {
+            panic!("expected a chat message");
+        };
+        assert_eq!(room, rust);
+
+        chat.switch_room(lobby.clone());
+        assert_eq!(chat.active_room, lobby);
+        assert_eq!(chat.received_messages.len(), 1);
+    }
+
+    #[test]
+    fn at_opens_mention_popup() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.handle_ws_message(&connected("alice")).unwrap();
+        chat.handle_ws_message(&connected("bob")).unwrap();
+        chat.mode = Mode::Insert;
+
+        type_str(&mut chat, "@");
+        assert!(chat.mention_popup.is_some());
+        type_str(&mut chat, "b");
+        assert_eq!(chat.mention_candidates(0), vec![String::from("bob")]);
+        assert!(
+            chat.handle_key_event(KeyEvent::from(KeyCode::Enter))
+                .unwrap()
+        );
+        assert!(chat.mention_popup.is_none());
+        assert_eq!(chat.current_input.value(), "@bob");
+
+        type_str(&mut chat, " @");
+        assert!(chat.mention_popup.is_some());
+        type_str(&mut chat, " ");
+        assert!(chat.mention_popup.is_none());
+    }
+
+    #[test]
+    fn tab_completes_mention() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.handle_ws_message(&connected("alice")).unwrap();
+        chat.handle_ws_message(&connected("bob")).unwrap();
+        chat.mode = Mode::Insert;
+
+        type_str(&mut chat, "hi @al");
+        assert!(chat.handle_key_event(KeyEvent::from(KeyCode::Tab)).unwrap());
+        assert_eq!(chat.current_input.value(), "hi @alice");
+
+        chat.current_input.reset();
+        type_str(&mut chat, "al");
+        assert!(!chat.handle_key_event(KeyEvent::from(KeyCode::Tab)).unwrap());
+        assert_eq!(chat.current_input.value(), "al");
+    }
+
+    #[test]
+    fn up_and_down_browse_sent_messages() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+        chat.mode = Mode::Insert;
+        let press = |chat: &mut Chat, code| chat.handle_key_event(KeyEvent::from(code)).unwrap();
+
+        for text in ["first", "second"] {
+            type_str(&mut chat, text);
+            press(&mut chat, KeyCode::Enter);
+        }
+        type_str(&mut chat, "draft");
+
+        press(&mut chat, KeyCode::Up);
+        assert_eq!(chat.current_input.value(), "second");
+        press(&mut chat, KeyCode::Up);
+        press(&mut chat, KeyCode::Up);
+        assert_eq!(chat.current_input.value(), "first");
+        press(&mut chat, KeyCode::Down);
+        assert_eq!(chat.current_input.value(), "second");
+        press(&mut chat, KeyCode::Down);
+        assert_eq!(chat.current_input.value(), "draft");
+        press(&mut chat, KeyCode::Down);
+        assert_eq!(chat.current_input.value(), "draft");
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut history = InputHistory::default();
+        for i in 0..=HISTORY_CAPACITY {
+            history.push(i.to_string());
+        }
+        assert_eq!(history.entries.len(), HISTORY_CAPACITY);
+        assert_eq!(history.entries.front().map(String::as_str), Some("1"));
+    }
+
+    #[test]
+    fn received_messages_are_timestamped() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+        // literal, so that the test doesn't depend on the clock
+        chat.timestamp_format = "12:34";
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::PropagateMessage(
+                alice(),
+                String::from("hi"),
+                None,
+                protocol::RoomId::default(),
+                None,
+            )
+            .into(),
+        )
+        .unwrap();
+        assert_eq!(chat.received_messages[0].to_string(), "12:34 alice: hi");
+    }
+
+    #[test]
+    fn slash_commands_are_not_sent_as_text() {
+        let (mut chat, mut ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+
+        chat.current_input = tui_input::Input::new(String::from("/nick  bob "));
+        chat.send_chat_message().unwrap();
+        let Ok(protocol::ClientMessage::ChangeNick(token, name)) =
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap())
+        else {
+            panic!("expected a nickname change");
+        };
+        assert_eq!((token.as_str(), name.as_str()), ("token", "bob"));
+
+        chat.current_input = tui_input::Input::new(String::from("/shrug"));
+        chat.send_chat_message().unwrap();
+        assert!(ws_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn reconnecting_resumes_or_reauthenticates_and_rejoins_rooms() {
+        let (mut chat, mut ws_rx, mut event_rx) = chat();
+        chat.token = Some(String::from("token"));
+        chat.switch_room(protocol::RoomId(String::from("rust")));
+        chat.handle_ws_message(&Message::Close(StatusCode::CloseAbnormal, None))
+            .unwrap();
+        while event_rx.try_recv().is_ok() {}
+
+        assert!(
+            chat.handle_event(AppEvent::Reconnected, false)
+                .await
+                .unwrap()
+        );
+        assert_eq!(chat.closed, None);
+        assert_eq!(chat.token, None);
+        assert!(matches!(
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap()),
+            Ok(protocol::ClientMessage::Resume(token)) if token == "token"
+        ));
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::AuthSuccess(Err(protocol::AuthError::UnknownToken)).into(),
+        )
+        .unwrap();
+        assert!(matches!(event_rx.try_recv(), Ok(AppEvent::Notify(..))));
+        assert_eq!(event_rx.try_recv().ok(), Some(AppEvent::SpawnAuth));
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::AuthSuccess(Ok(String::from("new token"))).into(),
+        )
+        .unwrap();
+        let Ok(protocol::ClientMessage::JoinRoom(room)) =
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap())
+        else {
+            panic!("expected a room to be rejoined");
+        };
+        assert_eq!(room.0, "rust");
+        assert!(ws_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn sent_message_is_pending_until_echoed() {
+        let (mut chat, mut ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+
+        chat.current_input = tui_input::Input::new(String::from("hi"));
+        chat.send_chat_message().unwrap();
+        assert_eq!(chat.received_messages.len(), 1);
+        assert!(chat.received_messages[0].to_string().ends_with("you: hi"));
+        let Ok(protocol::ClientMessage::SendMessage { id: Some(id), .. }) =
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap())
+        else {
+            panic!("expected a chat message with an id");
+        };
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::PropagateMessage(
+                alice(),
+                String::from("hi"),
+                None,
+                protocol::RoomId::default(),
+                Some(id),
+            )
+            .into(),
+        )
+        .unwrap();
+        assert_eq!(chat.received_messages.len(), 1);
+        assert!(chat.received_messages[0].to_string().ends_with("alice: hi"));
+        assert!(chat.pending.is_empty());
+    }
+
+    #[test]
+    fn unsent_message_is_marked_failed() {
+        let (mut chat, ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+        drop(ws_rx);
+
+        chat.current_input = tui_input::Input::new(String::from("hi"));
+        chat.send_chat_message().unwrap();
+        assert_eq!(chat.received_messages[0].style.fg, Some(Color::Red));
+        assert!(chat.pending.is_empty());
+    }
+
+    #[test]
+    fn direct_messages_are_prefixed() {
+        let (mut chat, _ws_rx, _event_rx) = chat();
+
+        chat.handle_ws_message(
+            &protocol::ServerMessage::PropagateDirect {
+                from: alice(),
+                to: String::from("bob"),
+                text: String::from("psst"),
+            }
+            .into(),
+        )
+        .unwrap();
+        assert!(
+            chat.received_messages[0]
+                .to_string()
+                .ends_with("[DM → bob] alice: psst")
+        );
+    }
+
+    #[test]
+    fn typing_is_sent_once_and_shown_until_disconnect() {
+        let (mut chat, mut ws_rx, _event_rx) = chat();
+        chat.token = Some(String::from("token"));
+        chat.mode = Mode::Insert;
+
+        for c in ['h', 'i'] {
+            chat.handle_key_event(KeyEvent::from(KeyCode::Char(c)))
+                .unwrap();
+        }
+        assert!(matches!(
+            protocol::ClientMessage::try_from(&ws_rx.try_recv().unwrap()),
+            Ok(protocol::ClientMessage::Typing(_, true))
+        ));
+        assert!(ws_rx.try_recv().is_err());
+
+        chat.handle_ws_message(&protocol::ServerMessage::UserTyping(alice(), true).into())
+            .unwrap();
+        assert!(chat.typing_users.contains("alice"));
+        chat.handle_ws_message(
+            &protocol::ServerMessage::Notification(
+                protocol::ServerNotification::ClientDisconnected(alice()),
+            )
+            .into(),
+        )
+        .unwrap();
+        assert!(chat.typing_users.is_empty());
+    }
+}
@@ -1,2 +1,87 @@
-/// Responsible for asynchronously encoding and resizing images in the background.
-pub struct Image {}
+use color_eyre::eyre::Result;
+use ratatui::{
+    Frame,
+    crossterm::event,
+    layout::{Constraint, Rect},
+    style::{Style, Stylize},
+    text::Span,
+    widgets::{Block, BorderType, Clear},
+};
+use ratatui_image::{StatefulImage, picker::Picker, protocol::StatefulProtocol};
+
+use crate::{AppEvent, EventSender, component::Component, components::center_area};
+
+/// Pop-up showing an image received in the chat, drawn with whatever graphics protocol
+/// the terminal supports, or with half blocks otherwise.
+/// Spawned by the [App] on [`AppEvent::SpawnImage`].
+///
+/// [App]: crate::App
+pub struct Image {
+    event_tx: EventSender,
+
+    /// Resized and encoded to fit the pop-up as it's rendered.
+    protocol: StatefulProtocol,
+}
+
+impl std::fmt::Debug for Image {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Image").finish_non_exhaustive()
+    }
+}
+
+impl Image {
+    /// Decodes `bytes`, in any format the `image` crate can guess.
+    ///
+    /// # Errors
+    ///
+    /// If the image can't be decoded.
+    pub fn new(
+        event_tx: EventSender,
+        picker: &Picker,
+        bytes: &[u8],
+    ) -> image::ImageResult<Box<Self>> {
+        let image = image::load_from_memory(bytes)?;
+        Ok(Box::new(Self {
+            event_tx,
+            protocol: picker.new_resize_protocol(image),
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Component for Image {
+    fn render(&mut self, frame: &mut Frame, area: Rect, is_focused: bool) {
+        if !is_focused {
+            return;
+        }
+        let area = center_area(area, Constraint::Ratio(2, 3), Constraint::Ratio(2, 3));
+        frame.render_widget(Clear, area);
+
+        let block = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().magenta())
+            .title_top(Span::raw(" Image ").into_left_aligned_line())
+            .title_bottom(
+                (Span::raw(" q").bold().green() + Span::raw(" to close ")).right_aligned(),
+            );
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+        frame.render_stateful_widget(StatefulImage::default(), inner, &mut self.protocol);
+    }
+
+    async fn handle_event(&mut self, event: AppEvent, is_focused: bool) -> Result<bool> {
+        if !is_focused {
+            return Ok(false);
+        }
+        let AppEvent::KeyEvent(key_event) = event else {
+            return Ok(false);
+        };
+        Ok(match key_event.code {
+            event::KeyCode::Char('q' | 'й' | 'v' | 'м') | event::KeyCode::Esc => {
+                self.event_tx.send(AppEvent::ComponentUnfocus)?;
+                true
+            }
+            _ => false,
+        })
+    }
+}
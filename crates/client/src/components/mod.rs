@@ -1,9 +1,52 @@
+use std::time::Duration;
+
+use color_eyre::eyre::Result;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use tokio::sync::mpsc::{Sender, error::TrySendError};
+use websocket::message::Message;
+
+use crate::EventSender;
+
 mod auth;
 mod chat;
 mod image;
 mod notify;
+mod rooms;
+mod users;
 
 pub use auth::Auth;
 pub use chat::Chat;
 pub use image::Image;
 pub use notify::*;
+pub use rooms::Rooms;
+pub use users::Users;
+
+/// Queues `message` for the server, telling the user if it wasn't because the queue is full,
+/// i.e. the connection can't keep up. Returns whether it was queued.
+fn try_queue(
+    ws_tx: &Sender<Message>,
+    event_tx: &mut EventSender,
+    message: Message,
+) -> Result<bool> {
+    match ws_tx.try_send(message) {
+        Ok(()) => Ok(true),
+        Err(TrySendError::Full(_)) => {
+            event_tx.notify(
+                "Sending too fast, that wasn't sent.",
+                Urgency::Warning,
+                Duration::from_secs(3),
+            )?;
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Centers a pop-up of the given size within `area`.
+fn center_area(area: Rect, horizontal: Constraint, vertical: Constraint) -> Rect {
+    let [area] = Layout::horizontal([horizontal])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([vertical]).flex(Flex::Center).areas(area);
+    area
+}
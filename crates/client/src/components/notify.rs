@@ -4,6 +4,7 @@ use std::time;
 use color_eyre::eyre::Result;
 use ratatui::{
     Frame,
+    crossterm::event::{self, KeyModifiers},
     layout::{Constraint, Layout, Rect},
     style::{Style, Stylize},
     text::{Line, Text},
@@ -47,6 +48,12 @@ struct TimedNotification<'a> {
     duration: time::Duration,
 }
 
+/// Toasts rendered at once, the rest are collapsed into a "+N more" line.
+const MAX_VISIBLE: usize = 5;
+
+/// Shows [`AppEvent::Notify`] toasts in the top right corner until they expire.
+///
+/// <kbd>Ctrl+x</kbd> dismisses the oldest one early.
 #[derive(Debug)]
 pub struct Notification<'a> {
     notifications: Vec<TimedNotification<'a>>,
@@ -66,6 +73,20 @@ impl Notification<'_> {
             .retain(|notif| notif.timestamp + notif.duration >= now);
     }
 
+    /// Removes the oldest toast, if any, returning whether there was one.
+    fn dismiss_oldest(&mut self) -> bool {
+        if self.notifications.is_empty() {
+            return false;
+        }
+        self.notifications.remove(0);
+        true
+    }
+
+    /// Toasts that don't fit under [`MAX_VISIBLE`].
+    fn hidden(&self) -> usize {
+        self.notifications.len().saturating_sub(MAX_VISIBLE)
+    }
+
     fn get_toast_area(paragraph: &Paragraph, area: Rect, y_offset: u16) -> (Rect, u16) {
         let inner_width =
             (area.width.saturating_sub(2)).min(paragraph.line_width().saturating_sub(2) as u16);
@@ -100,7 +121,7 @@ impl Component for Notification<'_> {
             .bold();
 
         let mut offset_height: u16 = 0;
-        for notif in &self.notifications {
+        for notif in self.notifications.iter().take(MAX_VISIBLE) {
             let icon = format!(" {}  ", notif.urgency.icon());
             let paragraph = Paragraph::new(notif.text.clone())
                 .wrap(ratatui::widgets::Wrap { trim: false })
@@ -119,6 +140,17 @@ impl Component for Notification<'_> {
 
             offset_height = offset_height.saturating_add(height);
         }
+
+        let hidden = self.hidden();
+        if hidden > 0 {
+            let [_, more_area] =
+                Layout::vertical([Constraint::Length(offset_height), Constraint::Length(1)])
+                    .areas(notification_area);
+            frame.render_widget(Clear, more_area);
+            Paragraph::new(Line::from(format!("+{hidden} more ")).right_aligned())
+                .dim()
+                .render(more_area, frame.buffer_mut());
+        }
     }
 
     async fn handle_event(&mut self, event: AppEvent, _is_focused: bool) -> Result<bool> {
@@ -132,7 +164,76 @@ impl Component for Notification<'_> {
                 });
                 true
             }
+            AppEvent::KeyEvent(key)
+                if key.modifiers.contains(KeyModifiers::CONTROL)
+                    && matches!(key.code, event::KeyCode::Char('x' | 'ч')) =>
+            {
+                self.purge_expired();
+                self.dismiss_oldest()
+            }
             _ => false,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ratatui::{
+        crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
+        text::Text,
+    };
+
+    use super::{MAX_VISIBLE, Notification, Urgency};
+    use crate::{AppEvent, component::Component};
+
+    fn notify(text: &str) -> AppEvent {
+        AppEvent::Notify(
+            Text::from(text.to_owned()),
+            Urgency::Info,
+            Duration::from_secs(30),
+        )
+    }
+
+    fn dismiss() -> AppEvent {
+        AppEvent::KeyEvent(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::CONTROL))
+    }
+
+    #[tokio::test]
+    async fn overflow_is_counted() {
+        let mut notification = Notification::new();
+        for i in 0..MAX_VISIBLE + 2 {
+            notification
+                .handle_event(notify(&i.to_string()), false)
+                .await
+                .unwrap();
+        }
+        assert_eq!(notification.hidden(), 2);
+    }
+
+    #[tokio::test]
+    async fn oldest_toast_is_dismissed() {
+        let mut notification = Notification::new();
+        assert!(!notification.handle_event(dismiss(), false).await.unwrap());
+
+        notification
+            .handle_event(notify("first"), false)
+            .await
+            .unwrap();
+        notification
+            .handle_event(notify("second"), false)
+            .await
+            .unwrap();
+        assert!(notification.handle_event(dismiss(), false).await.unwrap());
+        assert_eq!(notification.notifications.len(), 1);
+        assert_eq!(
+            notification.notifications[0].text,
+            Text::from("second".to_owned())
+        );
+
+        // plain `x` is left to whoever is typing
+        let plain = AppEvent::KeyEvent(KeyEvent::from(KeyCode::Char('x')));
+        assert!(!notification.handle_event(plain, false).await.unwrap());
+    }
+}
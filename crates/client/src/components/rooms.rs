@@ -0,0 +1,209 @@
+use color_eyre::eyre::Result;
+use common::protocol;
+use ratatui::{
+    Frame,
+    crossterm::event::{self},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{
+        Block, BorderType, Clear, List, ListItem, ListState, Paragraph, StatefulWidget, Widget,
+    },
+};
+use tokio::sync::mpsc::Sender;
+use tui_input::backend::crossterm::EventHandler;
+use websocket::message::Message;
+
+use crate::{
+    AppEvent, EventSender,
+    component::Component,
+    components::{center_area, try_queue},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Focus {
+    #[default]
+    Rooms,
+    Input,
+}
+
+impl Focus {
+    fn next(self) -> Self {
+        match self {
+            Self::Rooms => Self::Input,
+            Self::Input => Self::Rooms,
+        }
+    }
+}
+
+/// Pop-up listing joined rooms, allowing to switch the active one or to join a new one.
+/// Spawned by the [App] on [`AppEvent::SpawnRooms`].
+///
+/// [App]: crate::App
+#[derive(Debug)]
+pub struct Rooms {
+    ws_tx: Sender<Message>,
+    event_tx: EventSender,
+
+    focus: Focus,
+
+    joined: Vec<protocol::RoomId>,
+    state: ListState,
+    room_input: tui_input::Input,
+}
+
+impl Rooms {
+    #[must_use]
+    pub fn new(
+        ws_tx: Sender<Message>,
+        event_tx: EventSender,
+        rooms: Vec<protocol::RoomId>,
+    ) -> Box<Self> {
+        Box::new(Self {
+            ws_tx,
+            event_tx,
+            focus: Focus::default(),
+            joined: rooms,
+            state: ListState::default(),
+            room_input: tui_input::Input::default(),
+        })
+    }
+
+    fn switch_to_selected(&mut self) -> Result<()> {
+        if let Some(room) = self.state.selected().and_then(|i| self.joined.get(i)) {
+            self.event_tx.send(AppEvent::SwitchRoom(room.clone()))?;
+        }
+        Ok(())
+    }
+
+    fn join_new(&mut self) -> Result<()> {
+        let name = self.room_input.value().trim();
+        if name.is_empty() {
+            return Ok(());
+        }
+        let room = protocol::RoomId(name.to_string());
+        if try_queue(
+            &self.ws_tx,
+            &mut self.event_tx,
+            protocol::ClientMessage::JoinRoom(room.clone()).into(),
+        )? {
+            self.event_tx.send(AppEvent::SwitchRoom(room))?;
+        }
+        Ok(())
+    }
+
+    fn handle_rooms_event(&mut self, event: event::KeyEvent) -> bool {
+        match event.code {
+            event::KeyCode::Char('j' | 'о' | 's' | 'і') | event::KeyCode::Down => {
+                self.state.select_next();
+                true
+            }
+            event::KeyCode::Char('k' | 'л' | 'w' | 'ц') | event::KeyCode::Up => {
+                self.state.select_previous();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_input_event(&mut self, event: event::KeyEvent) -> bool {
+        self.room_input
+            .handle_event(&event::Event::Key(event))
+            .is_some()
+    }
+}
+
+#[async_trait::async_trait]
+impl Component for Rooms {
+    async fn init(&mut self) -> Result<()> {
+        self.state.select_first();
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect, is_focused: bool) {
+        if !is_focused {
+            return;
+        }
+        let area = center_area(area, Constraint::Ratio(1, 3), Constraint::Ratio(2, 3));
+        frame.render_widget(Clear, area);
+        let outer_borders = Block::bordered()
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().magenta());
+        outer_borders.render(area, frame.buffer_mut());
+
+        let [list_area, input_area] = Layout::vertical([Constraint::Fill(1), Constraint::Max(3)])
+            .margin(1)
+            .areas(area);
+
+        let focus = self.focus;
+        let focused_style = |target: Focus| {
+            if focus == target {
+                Style::new().magenta()
+            } else {
+                Style::new()
+            }
+        };
+
+        let room_list = List::new(
+            self.joined
+                .iter()
+                .map(|room| ListItem::from(Line::raw(String::from("# ") + &room.0))),
+        )
+        .block(
+            Block::bordered()
+                .border_type(BorderType::Rounded)
+                .title_top(Span::raw(" Rooms ").into_left_aligned_line())
+                .title_bottom(
+                    (Span::raw(" j↓  k↑").bold().green() + Span::raw(" to scroll "))
+                        .right_aligned(),
+                ),
+        )
+        .style(focused_style(Focus::Rooms))
+        .highlight_symbol(">");
+        StatefulWidget::render(room_list, list_area, frame.buffer_mut(), &mut self.state);
+
+        Paragraph::new(self.room_input.value())
+            .block(
+                Block::bordered()
+                    .border_type(BorderType::Rounded)
+                    .title_top(Span::raw(" Join room ").into_left_aligned_line())
+                    .style(focused_style(Focus::Input)),
+            )
+            .render(input_area, frame.buffer_mut());
+    }
+
+    async fn handle_event(&mut self, event: AppEvent, is_focused: bool) -> Result<bool> {
+        if !is_focused {
+            return Ok(false);
+        }
+        if let AppEvent::KeyEvent(key_event) = event {
+            if match self.focus {
+                Focus::Rooms => self.handle_rooms_event(key_event),
+                Focus::Input => self.handle_input_event(key_event),
+            } {
+                return Ok(true);
+            }
+            Ok(match key_event.code {
+                event::KeyCode::Char('q' | 'й') | event::KeyCode::Esc => {
+                    self.event_tx.send(AppEvent::ComponentUnfocus)?;
+                    true
+                }
+                event::KeyCode::Tab => {
+                    self.focus = self.focus.next();
+                    true
+                }
+                event::KeyCode::Enter => {
+                    match self.focus {
+                        Focus::Rooms => self.switch_to_selected()?,
+                        Focus::Input => self.join_new()?,
+                    }
+                    self.event_tx.send(AppEvent::ComponentUnfocus)?;
+                    true
+                }
+                _ => false,
+            })
+        } else {
+            Ok(false)
+        }
+    }
+}
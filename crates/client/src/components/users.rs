@@ -0,0 +1,100 @@
+use color_eyre::eyre::Result;
+use common::protocol;
+use ratatui::{
+    Frame,
+    crossterm::event::{self},
+    layout::{Constraint, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, BorderType, Clear, List, ListItem, ListState, StatefulWidget},
+};
+
+use crate::{
+    AppEvent, EventSender, component::Component, components::center_area, into_ratatui_color,
+};
+
+/// Pop-up listing everyone online, each in their color.
+/// Spawned by the [App] on [`AppEvent::SpawnUsers`], once the server answers
+/// [`protocol::ClientMessage::ListUsers`].
+///
+/// [App]: crate::App
+#[derive(Debug)]
+pub struct Users {
+    event_tx: EventSender,
+
+    /// Sorted by name, as sent by the server.
+    online: Vec<protocol::MessageSender>,
+    state: ListState,
+}
+
+impl Users {
+    #[must_use]
+    pub fn new(event_tx: EventSender, online: Vec<protocol::MessageSender>) -> Box<Self> {
+        Box::new(Self {
+            event_tx,
+            online,
+            state: ListState::default(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Component for Users {
+    async fn init(&mut self) -> Result<()> {
+        self.state.select_first();
+        Ok(())
+    }
+
+    fn render(&mut self, frame: &mut Frame, area: Rect, is_focused: bool) {
+        if !is_focused {
+            return;
+        }
+        let area = center_area(area, Constraint::Ratio(1, 4), Constraint::Ratio(2, 3));
+        frame.render_widget(Clear, area);
+
+        let user_list = List::new(self.online.iter().map(|user| {
+            ListItem::from(Line::from(Span::styled(
+                user.name.clone(),
+                into_ratatui_color(user.color),
+            )))
+        }))
+        .block(
+            Block::bordered()
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().magenta())
+                .title_top(
+                    Span::raw(format!(" Online: {} ", self.online.len())).into_left_aligned_line(),
+                )
+                .title_bottom(
+                    (Span::raw(" j↓  k↑").bold().green() + Span::raw(" to scroll "))
+                        .right_aligned(),
+                ),
+        )
+        .highlight_symbol(">");
+        StatefulWidget::render(user_list, area, frame.buffer_mut(), &mut self.state);
+    }
+
+    async fn handle_event(&mut self, event: AppEvent, is_focused: bool) -> Result<bool> {
+        if !is_focused {
+            return Ok(false);
+        }
+        let AppEvent::KeyEvent(key_event) = event else {
+            return Ok(false);
+        };
+        Ok(match key_event.code {
+            event::KeyCode::Char('j' | 'о') | event::KeyCode::Down => {
+                self.state.select_next();
+                true
+            }
+            event::KeyCode::Char('k' | 'л') | event::KeyCode::Up => {
+                self.state.select_previous();
+                true
+            }
+            event::KeyCode::Char('q' | 'й' | 'u' | 'г') | event::KeyCode::Esc => {
+                self.event_tx.send(AppEvent::ComponentUnfocus)?;
+                true
+            }
+            _ => false,
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+
+/// User preferences, read from `tungstopterin/config.toml` in the platform config directory.
+/// Missing keys (or a missing file altogether) fall back to [`Config::default`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Lines scrolled per `j`/`k` press. See [`Config::scroll_step`].
+    scroll_step: usize,
+    /// Messages queued for the server before further ones are refused. See [`Config::send_queue`].
+    send_queue: usize,
+    /// Whether chat messages are kept between sessions. See [`Config::history_path`].
+    save_history: bool,
+    /// Where they're kept, in the platform data directory if unset.
+    history_path: Option<PathBuf>,
+    /// Whether the terminal bell rings when someone mentions the user.
+    mention_bell: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scroll_step: 1,
+            send_queue: 64,
+            save_history: false,
+            history_path: None,
+            mention_bell: true,
+        }
+    }
+}
+
+impl Config {
+    pub const MAX_SCROLL_STEP: usize = 100;
+
+    #[must_use]
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("tungstopterin").join("config.toml"))
+    }
+
+    /// Reads the config from [`Config::path`], if there is one.
+    ///
+    /// # Errors
+    /// If the file exists, but can't be read or isn't valid TOML.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// # Errors
+    /// If `contents` isn't valid TOML or has keys of the wrong type.
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Lines scrolled per `j`/`k` press, within `1..=MAX_SCROLL_STEP`.
+    #[must_use]
+    pub fn scroll_step(&self) -> usize {
+        self.scroll_step.clamp(1, Self::MAX_SCROLL_STEP)
+    }
+
+    /// File chat messages are saved to on quit and restored from on start,
+    /// `None` unless `save_history` is turned on.
+    #[must_use]
+    pub fn history_path(&self) -> Option<PathBuf> {
+        if !self.save_history {
+            return None;
+        }
+        self.history_path.clone().or_else(|| {
+            dirs::data_dir().map(|dir| dir.join("tungstopterin").join("history.msgpack"))
+        })
+    }
+
+    #[must_use]
+    pub fn mention_bell(&self) -> bool {
+        self.mention_bell
+    }
+
+    /// Messages queued for the server before further ones are refused, at least 1.
+    #[must_use]
+    pub fn send_queue(&self) -> usize {
+        self.send_queue.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Config;
+
+    #[test]
+    fn scroll_step_is_clamped() {
+        assert_eq!(Config::from_toml("").unwrap().scroll_step(), 1);
+        assert_eq!(
+            Config::from_toml("scroll_step = 3").unwrap().scroll_step(),
+            3
+        );
+        assert_eq!(
+            Config::from_toml("scroll_step = 0").unwrap().scroll_step(),
+            1
+        );
+        assert_eq!(
+            Config::from_toml("scroll_step = 100000")
+                .unwrap()
+                .scroll_step(),
+            Config::MAX_SCROLL_STEP
+        );
+        assert!(Config::from_toml("scroll_step = -1").is_err());
+    }
+
+    #[test]
+    fn history_is_opt_in() {
+        assert_eq!(Config::from_toml("").unwrap().history_path(), None);
+        assert_eq!(
+            Config::from_toml("history_path = \"/tmp/h\"")
+                .unwrap()
+                .history_path(),
+            None
+        );
+        assert_eq!(
+            Config::from_toml("save_history = true\nhistory_path = \"/tmp/h\"")
+                .unwrap()
+                .history_path(),
+            Some(PathBuf::from("/tmp/h"))
+        );
+    }
+
+    #[test]
+    fn mention_bell_is_on_by_default() {
+        assert!(Config::from_toml("").unwrap().mention_bell());
+        assert!(
+            !Config::from_toml("mention_bell = false")
+                .unwrap()
+                .mention_bell()
+        );
+    }
+
+    #[test]
+    fn send_queue_is_never_empty() {
+        assert_eq!(Config::from_toml("").unwrap().send_queue(), 64);
+        assert_eq!(Config::from_toml("send_queue = 8").unwrap().send_queue(), 8);
+        assert_eq!(Config::from_toml("send_queue = 0").unwrap().send_queue(), 1);
+    }
+}
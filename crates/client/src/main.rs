@@ -1,9 +1,11 @@
 #![warn(clippy::pedantic)]
-use std::{collections::VecDeque, sync::Arc, time::Duration};
+use std::{collections::VecDeque, path::PathBuf, pin::pin, sync::Arc, time::Duration};
 
-use color_eyre::eyre::Result;
+use clap::Parser;
+use color_eyre::eyre::{OptionExt, Result};
 use common::protocol;
 use component::Component;
+use futures::future::OptionFuture;
 use ratatui::{
     DefaultTerminal,
     crossterm::{
@@ -12,10 +14,13 @@ use ratatui::{
     },
     prelude::*,
 };
+use ratatui_image::picker::Picker;
 use rustls_native_certs::load_native_certs;
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::TcpStream,
-    sync::mpsc::{UnboundedReceiver, UnboundedSender, error::SendError},
+    sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender, error::SendError},
+    task::JoinHandle,
 };
 use tokio_rustls::{
     TlsConnector,
@@ -26,13 +31,25 @@ use tokio_rustls::{
 };
 use tokio_util::sync::CancellationToken;
 use websocket::{
-    Server, WsRecv, WsRecvHalf, WsSend, WsSendHalf, WsStream, handshake::IntoWebsocket,
-    message::Message,
+    CLOSE_TIMEOUT, Server, UnpinStream, WsRecv, WsRecvHalf, WsSend, WsSendHalf, WsStream,
+    handshake::HANDSHAKE_TIMEOUT,
+    keepalive::{PongTracker, keepalive},
+    message::{Message, MessageError, Received, StatusCode},
 };
 
 use crate::components::Urgency;
 
-type TlsStream = tokio_rustls::client::TlsStream<TcpStream>;
+/// Any transport the server connection can be carried over.
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug> Transport for T {}
+
+/// Type-erased connection to the server, TLS or plain TCP, see [`Args::no_tls`].
+type Stream = Box<dyn Transport>;
+
+/// Delay before the first reconnection attempt, doubled after each failed one.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Cap of the delay between reconnection attempts.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
 
 fn into_ratatui_color(color: protocol::Color) -> ratatui::style::Color {
     #[allow(clippy::match_same_arms)]
@@ -60,12 +77,15 @@ fn into_protocol_color(color: Color) -> protocol::Color {
         Color::Blue => protocol::Color::Blue,
         Color::Magenta => protocol::Color::Magenta,
         Color::Cyan => protocol::Color::Cyan,
+        Color::Rgb(r, g, b) => protocol::Color::Truecolor(r, g, b),
         _ => protocol::Color::Text,
     }
 }
 
 pub mod component;
 pub mod components;
+pub mod config;
+pub mod scrollback;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AppEvent {
@@ -73,6 +93,12 @@ pub enum AppEvent {
     WsMessage(Message),
     /// Incoming terminal [`KeyEvent`][`crossterm::event::KeyEvent`].
     KeyEvent(crossterm::event::KeyEvent),
+    /// Incoming terminal [`MouseEvent`][`crossterm::event::MouseEvent`].
+    Mouse(crossterm::event::MouseEvent),
+    /// Text pasted into the terminal, newlines included.
+    Paste(String),
+    /// The terminal was resized to the given columns and rows.
+    Resize(u16, u16),
 
     /// Pop from the stack, *destroying a component*, and move focus one position down.
     ComponentUnfocus,
@@ -81,9 +107,26 @@ pub enum AppEvent {
 
     /// Spawn [`components::Auth`] pop-up.
     SpawnAuth,
+    /// Spawn [`components::Rooms`] pop-up listing the given joined rooms.
+    SpawnRooms(Vec<protocol::RoomId>),
+    /// Spawn [`components::Users`] pop-up listing the given online users.
+    SpawnUsers(Vec<protocol::MessageSender>),
+    /// Spawn [`components::Image`] pop-up showing the given encoded image.
+    SpawnImage(Vec<u8>),
+    /// Make the room active in [`components::Chat`], adding it to the joined ones if needed.
+    SwitchRoom(protocol::RoomId),
+
+    /// Re-establish the server connection after it was lost abnormally.
+    Reconnect,
+    /// The connection is re-established, but the session is new and has to be authenticated.
+    Reconnected,
 
     /// Spawn a notification for a period of time.
     Notify(Text<'static>, Urgency, Duration),
+    /// The user asked to be known by the given nickname, see [`components::Auth`].
+    Authenticating(String),
+    /// Ring the terminal bell, unless turned off with [`config::Config::mention_bell`].
+    Bell,
 }
 
 #[derive(Debug, Clone)]
@@ -151,73 +194,256 @@ impl ComponentStack {
 #[derive(Debug)]
 struct App {
     should_quit: bool,
+    /// Whether the terminal has to be cleared before the next draw, e.g. after a resize.
+    should_clear: bool,
 
     components: ComponentStack,
 
     event_rx: UnboundedReceiver<AppEvent>,
     event_tx: EventSender,
-    // TODO: Bounded sender here?
-    ws_tx: UnboundedSender<Message>,
+    /// Feeds the queue of [`App::spawn_ws_sender`], which outlives connections,
+    /// so that components keep their clones across reconnects.
+    /// Bounded by [`config::Config::send_queue`], components `try_send` and report it when full.
+    ws_tx: Sender<Message>,
+    /// `None` only while the connection is being swapped.
+    ws_sender: Option<WsSender>,
+    ws_receiver: JoinHandle<()>,
+    config: config::Config,
+    /// Where to reconnect to.
+    args: Args,
+    /// How [`components::Image`] draws, guessed from the terminal before reading its events.
+    picker: Picker,
 
     cancel_token: CancellationToken,
+    /// Cancels the tasks of the current connection, a child of `cancel_token`.
+    connection_cancel: CancellationToken,
+    /// Pending [`App::spawn_reconnect`], if the connection was lost.
+    reconnecting: Option<JoinHandle<Option<WsStream<Server, Stream>>>>,
 }
 
+/// Task of [`App::spawn_ws_sender`], handing back the send half and the queue once done.
+type WsSender = JoinHandle<(WsSendHalf<Server, Stream>, Receiver<Message>)>;
+
 impl App {
-    fn new(ws_rx: WsRecvHalf<Server, TlsStream>, ws_tx: WsSendHalf<Server, TlsStream>) -> Self {
+    fn new(
+        ws_rx: WsRecvHalf<Server, Stream>,
+        ws_tx: WsSendHalf<Server, Stream>,
+        config: config::Config,
+        args: Args,
+        picker: Picker,
+    ) -> Self {
         let app_cancel = CancellationToken::new();
+        let connection_cancel = app_cancel.child_token();
         let (event_tx, event_rx) = tokio::sync::mpsc::unbounded_channel::<AppEvent>();
-        let ws_tx = App::spawn_ws_sender(ws_tx);
+        let (shared_ws_tx, ws_queue) = tokio::sync::mpsc::channel::<Message>(config.send_queue());
+        let ws_sender = App::spawn_ws_sender(ws_tx, ws_queue, connection_cancel.clone());
 
-        let app = App {
+        let event_tx = EventSender(event_tx);
+        App::spawn_terminal_reader(&event_tx, app_cancel.child_token());
+        let ws_receiver = App::spawn_ws_receiver(&event_tx, &shared_ws_tx, ws_rx);
+
+        App {
             should_quit: false,
+            should_clear: false,
             components: ComponentStack::default(),
-            event_tx: EventSender(event_tx),
+            event_tx,
             event_rx,
-            ws_tx,
+            ws_tx: shared_ws_tx,
+            ws_sender: Some(ws_sender),
+            ws_receiver,
+            config,
+            args,
+            picker,
             cancel_token: app_cancel,
-        };
-        app.spawn_event_emitter(ws_rx, app.cancel_token.child_token());
-        app
+            connection_cancel,
+            reconnecting: None,
+        }
     }
 
-    fn spawn_event_emitter(
-        &self,
-        mut ws_rx: WsRecvHalf<Server, TlsStream>,
-        event_cancel: CancellationToken,
-    ) {
-        let inner_tx = self.event_tx.clone();
+    /// Spawns the terminal reader, running until `event_cancel` fires.
+    fn spawn_terminal_reader(event_tx: &EventSender, event_cancel: CancellationToken) {
+        let event_tx = event_tx.clone();
         tokio::spawn(async move {
-            let event_tx = inner_tx;
             loop {
                 if event_cancel.is_cancelled() {
                     break;
                 }
-                if matches!(crossterm::event::poll(Duration::from_millis(50)), Ok(true)) {
-                    if let Ok(crossterm::event::Event::Key(event)) = crossterm::event::read() {
+                if !matches!(crossterm::event::poll(Duration::from_millis(50)), Ok(true)) {
+                    continue;
+                }
+                match crossterm::event::read() {
+                    Ok(crossterm::event::Event::Key(event)) => {
                         _ = event_tx.send(AppEvent::KeyEvent(event));
                     }
+                    Ok(crossterm::event::Event::Mouse(event)) => {
+                        _ = event_tx.send(AppEvent::Mouse(event));
+                    }
+                    Ok(crossterm::event::Event::Paste(text)) => {
+                        _ = event_tx.send(AppEvent::Paste(text));
+                    }
+                    Ok(crossterm::event::Event::Resize(columns, rows)) => {
+                        _ = event_tx.send(AppEvent::Resize(columns, rows));
+                    }
+                    _ => {}
                 }
             }
         });
+    }
+
+    /// Spawns the WebSocket reader, returning its handle.
+    /// It finishes after receiving a *Close* frame, or when the connection drops,
+    /// which includes the server not answering the keepalive *Ping*s.
+    fn spawn_ws_receiver(
+        event_tx: &EventSender,
+        ws_tx: &Sender<Message>,
+        mut ws_rx: WsRecvHalf<Server, Stream>,
+    ) -> JoinHandle<()> {
+        let inner_tx = event_tx.clone();
+        // The send half is owned by the sender task, so pings are answered through its queue
+        // instead of `WsRecv::receive_with_control`.
+        let ws_tx = ws_tx.clone();
+        tokio::spawn(async move {
+            let config = ws_rx.config();
+            let pongs = PongTracker::default();
+            let pinger = &ws_tx;
+            let mut keepalive = pin!(keepalive(
+                move |ping| async move { pinger.send(ping).await.is_ok() },
+                &pongs,
+                config.keepalive_interval(),
+                config.keepalive_timeout(),
+            ));
+            loop {
+                let received = tokio::select! {
+                    received = ws_rx.receive_event() => received,
+                    // unanswered, same as a dropped connection
+                    _ = &mut keepalive => Ok(Received::Abnormal),
+                };
+                match received {
+                    Ok(Received::Message(Message::Ping(payload))) => {
+                        _ = ws_tx.send(Message::Pong(payload)).await;
+                    }
+                    Ok(Received::Message(Message::Pong(payload))) => pongs.record(&payload),
+                    Ok(Received::Message(msg)) => _ = inner_tx.send(AppEvent::WsMessage(msg)),
+                    Ok(Received::Closed(code, reason)) => {
+                        _ = inner_tx.send(AppEvent::WsMessage(Message::Close(code, reason)));
+                        break;
+                    }
+                    Err(error) => {
+                        // the server broke the protocol, tell it how before leaving
+                        let code = match error {
+                            MessageError::ProtocolViolated(code) => code,
+                            MessageError::IsNotFinal => StatusCode::ProtocolError,
+                        };
+                        _ = ws_tx.send(Message::Close(code, None)).await;
+                        _ = inner_tx.send(AppEvent::WsMessage(Message::Close(code, None)));
+                        break;
+                    }
+                    Ok(Received::Abnormal) => {
+                        // No close frame, report it the same way the RFC does.
+                        _ = inner_tx.send(AppEvent::WsMessage(Message::Close(
+                            StatusCode::CloseAbnormal,
+                            None,
+                        )));
+                        break;
+                    }
+                }
+            }
+        })
+    }
 
-        let inner_tx = self.event_tx.clone();
+    /// Stops background tasks and performs the closing handshake: the *Close* frame is sent
+    /// after whatever is still queued, and the server's echo is awaited for up to [`CLOSE_TIMEOUT`].
+    async fn quit(mut self) {
+        for component in &mut self.components.inner {
+            component.quit();
+        }
+        self.cancel_token.cancel();
+        let Some(Ok((mut ws_tx, _))) = OptionFuture::from(self.ws_sender).await else {
+            return;
+        };
+        // the receiving task finishes upon the echo
+        _ = tokio::time::timeout(CLOSE_TIMEOUT, self.ws_receiver).await;
+        _ = ws_tx.shutdown().await;
+    }
+
+    /// Spawns the task owning the WebSocket send half, sending whatever is queued.
+    /// Once `cancel` fires, it flushes whatever is still queued and closes the connection
+    /// with [`StatusCode::Normal`], handing the half and the queue back.
+    fn spawn_ws_sender<T: UnpinStream + Send + 'static>(
+        mut ws_tx: WsSendHalf<Server, T>,
+        mut queue: Receiver<Message>,
+        cancel: CancellationToken,
+    ) -> JoinHandle<(WsSendHalf<Server, T>, Receiver<Message>)> {
         tokio::spawn(async move {
-            while let Ok(msg) = ws_rx.receive().await {
-                _ = inner_tx.send(AppEvent::WsMessage(msg));
+            loop {
+                tokio::select! {
+                    biased;
+                    msg = queue.recv() => match msg {
+                        Some(msg) => _ = ws_tx.send(msg).await,
+                        None => break,
+                    },
+                    () = cancel.cancelled() => break,
+                }
             }
-        });
+            _ = ws_tx.close(None, None).await;
+            (ws_tx, queue)
+        })
     }
 
-    fn spawn_ws_sender(mut ws_tx: WsSendHalf<Server, TlsStream>) -> UnboundedSender<Message> {
-        let (shared_ws_tx, mut ws_rx) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    /// Spawns the task re-dialing the server, waiting [`RECONNECT_DELAY`] before the first
+    /// attempt and twice as long after each failed one, up to [`MAX_RECONNECT_DELAY`].
+    /// Resolves to the new connection, or `None` once `cancel` fires.
+    fn spawn_reconnect(
+        mut event_tx: EventSender,
+        args: Args,
+        cancel: CancellationToken,
+    ) -> JoinHandle<Option<WsStream<Server, Stream>>> {
         tokio::spawn(async move {
+            let mut delay = RECONNECT_DELAY;
             loop {
-                if let Some(msg) = ws_rx.recv().await {
-                    _ = ws_tx.send(msg).await;
+                _ = event_tx.notify(
+                    format!("Reconnecting in {}s…", delay.as_secs()),
+                    Urgency::Warning,
+                    delay,
+                );
+                let attempt = async {
+                    tokio::time::sleep(delay).await;
+                    connect(&args).await
+                };
+                tokio::select! {
+                    result = attempt => match result {
+                        Ok(ws) => return Some(ws),
+                        Err(_) => delay = (delay * 2).min(MAX_RECONNECT_DELAY),
+                    },
+                    () = cancel.cancelled() => return None,
                 }
             }
-        });
-        shared_ws_tx
+        })
+    }
+
+    /// Swaps the new connection in once [`App::spawn_reconnect`] is done.
+    async fn poll_reconnect(&mut self) {
+        let Some(reconnecting) = self.reconnecting.take_if(|handle| handle.is_finished()) else {
+            return;
+        };
+        let Ok(Some(ws)) = reconnecting.await else {
+            return;
+        };
+        let Some(Ok((_, mut queue))) = OptionFuture::from(self.ws_sender.take()).await else {
+            return;
+        };
+        // whatever was queued for the lost connection belongs to the old session
+        while queue.try_recv().is_ok() {}
+
+        let (ws_rx, ws_tx) = ws.into_split();
+        self.connection_cancel = self.cancel_token.child_token();
+        self.ws_sender = Some(App::spawn_ws_sender(
+            ws_tx,
+            queue,
+            self.connection_cancel.clone(),
+        ));
+        self.ws_receiver = App::spawn_ws_receiver(&self.event_tx, &self.ws_tx, ws_rx);
+        _ = self.event_tx.send(AppEvent::Reconnected);
     }
 
     async fn init_components(&mut self) -> Result<()> {
@@ -225,6 +451,7 @@ impl App {
         self.components.push_back(components::Chat::new(
             self.ws_tx.clone(),
             self.event_tx.clone(),
+            &self.config,
         ));
         self.components.push_back(components::Notification::new());
 
@@ -238,6 +465,11 @@ impl App {
         self.init_components().await?;
         while !self.should_quit {
             self.delegate_event().await?;
+            self.poll_reconnect().await;
+            if std::mem::take(&mut self.should_clear) {
+                // leftovers of the old size aren't always overwritten by the diffing draw
+                terminal.clear()?;
+            }
             terminal.draw(|frame| self.draw(frame))?;
         }
         Ok(())
@@ -293,6 +525,15 @@ impl App {
                 self.components.pop_focused();
                 self.components.focus = self.components.focus.saturating_sub(1);
             }
+            AppEvent::Reconnect if self.reconnecting.is_none() => {
+                // the sender hands its queue back once cancelled, see `poll_reconnect`
+                self.connection_cancel.cancel();
+                self.reconnecting = Some(App::spawn_reconnect(
+                    self.event_tx.clone(),
+                    self.args.clone(),
+                    self.cancel_token.child_token(),
+                ));
+            }
             AppEvent::SpawnAuth => {
                 let mut auth = components::Auth::new(self.ws_tx.clone(), self.event_tx.clone());
                 if auth.init().await.is_ok() {
@@ -300,50 +541,163 @@ impl App {
                     _ = self.event_tx.send(AppEvent::ComponentFocus);
                 }
             }
+            AppEvent::SpawnRooms(rooms) => {
+                let mut room_list =
+                    components::Rooms::new(self.ws_tx.clone(), self.event_tx.clone(), rooms);
+                if room_list.init().await.is_ok() {
+                    self.components.push_after_focused(room_list);
+                    _ = self.event_tx.send(AppEvent::ComponentFocus);
+                }
+            }
+            AppEvent::SpawnUsers(users) => {
+                let mut user_list = components::Users::new(self.event_tx.clone(), users);
+                if user_list.init().await.is_ok() {
+                    self.components.push_after_focused(user_list);
+                    _ = self.event_tx.send(AppEvent::ComponentFocus);
+                }
+            }
+            AppEvent::SpawnImage(bytes) => {
+                match components::Image::new(self.event_tx.clone(), &self.picker, &bytes) {
+                    Ok(image) => {
+                        self.components.push_after_focused(image);
+                        _ = self.event_tx.send(AppEvent::ComponentFocus);
+                    }
+                    Err(_) => {
+                        _ = self.event_tx.notify(
+                            "Couldn't decode the image.",
+                            Urgency::Warning,
+                            Duration::from_secs(3),
+                        );
+                    }
+                }
+            }
+            AppEvent::Resize(..) => self.should_clear = true,
+            AppEvent::Bell if self.config.mention_bell() => {
+                _ = crossterm::execute!(std::io::stdout(), crossterm::style::Print('\x07'));
+            }
             _ => {}
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // TODO: clap
-    color_eyre::install()?;
+/// Terminal client of the chat server.
+#[derive(Debug, Clone, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Address of the server, also sent as the `Host` header.
+    #[arg(long, default_value = "localhost:1337")]
+    server: String,
+    /// PEM root certificate to trust, in addition to the platform ones.
+    #[arg(long, default_value = "certs/root-ca.pem")]
+    root_ca: PathBuf,
+    /// Name the server certificate is verified against.
+    #[arg(long, default_value = "localhost")]
+    domain: String,
+    /// Connect over plain TCP, to a server started with `--no-tls`.
+    /// Meant for local development only: messages and the session token
+    /// travel unencrypted, and the server's identity isn't verified.
+    #[arg(long)]
+    no_tls: bool,
+}
 
-    let conn = TcpStream::connect("localhost:1337").await?;
-    conn.set_nodelay(true)?;
+/// Dials the server, then performs the TLS and WebSocket handshakes.
+/// TLS is skipped with [`Args::no_tls`].
+/// Fails if all of it takes longer than [`HANDSHAKE_TIMEOUT`].
+async fn connect(args: &Args) -> Result<WsStream<Server, Stream>> {
+    // neither dialing nor the TLS handshake give up on a silent server by themselves
+    let connecting = async {
+        let conn = TcpStream::connect(&args.server).await?;
+        conn.set_nodelay(true)?;
+
+        let conn: Stream = if args.no_tls {
+            Box::new(conn)
+        } else {
+            Box::new(connect_tls(args, conn).await?)
+        };
+        let mut ws = WsStream::<Server, _>::from_stream(conn);
+        ws.try_upgrade_with(&args.server, &[protocol::SUBPROTOCOL])
+            .await?;
+        Ok(ws)
+    };
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, connecting).await?
+}
 
+/// Performs the TLS handshake over `conn`, trusting [`Args::root_ca`] besides the platform roots.
+async fn connect_tls(
+    args: &Args,
+    conn: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
     let mut root_cert_store = rustls::RootCertStore::empty();
     for cert in load_native_certs().expect("could not load platform native certs") {
         root_cert_store.add(cert)?;
     }
     root_cert_store.add(
-        CertificateDer::pem_file_iter("certs/root-ca.pem")
-            .unwrap()
+        CertificateDer::pem_file_iter(&args.root_ca)?
             .flatten()
             .next()
-            .unwrap(),
+            .ok_or_eyre("no certificate found in the root CA file")?,
     )?;
 
-    let config = rustls::ClientConfig::builder()
+    let tls_config = rustls::ClientConfig::builder()
         .with_root_certificates(root_cert_store)
         .with_no_client_auth();
-    let connector = TlsConnector::from(Arc::new(config));
+    let connector = TlsConnector::from(Arc::new(tls_config));
 
-    let domain = ServerName::try_from("localhost")?.to_owned();
-    let conn = connector.connect(domain, conn).await?;
+    let domain = ServerName::try_from(args.domain.as_str())?.to_owned();
+    Ok(connector.connect(domain, conn).await?)
+}
 
-    let mut ws = WsStream::<Server, _>::from_stream(conn);
-    ws.try_upgrade("localhost:1337").await?;
-    let (ws_rx, ws_tx) = ws.into_split();
+#[tokio::main]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let args = Args::parse();
+    let config = config::Config::load()?;
+
+    let (ws_rx, ws_tx) = connect(&args).await?.into_split();
 
     let mut terminal = ratatui::init();
-    let mut app = App::new(ws_rx, ws_tx);
+    // for scrolling the chat, at the cost of the terminal's own text selection
+    crossterm::execute!(std::io::stdout(), event::EnableMouseCapture)?;
+    // so that pasted newlines don't send the message halfway
+    crossterm::execute!(std::io::stdout(), event::EnableBracketedPaste)?;
+    // has to ask the terminal before the event reader starts, or it eats the answer
+    let picker = Picker::from_query_stdio().unwrap_or_else(|_| Picker::from_fontsize((10, 20)));
+    let mut app = App::new(ws_rx, ws_tx, config, args, picker);
     app.run(&mut terminal).await?;
+    app.quit().await;
 
-    // TODO: Start closing handshake
-
+    crossterm::execute!(std::io::stdout(), event::DisableBracketedPaste)?;
+    crossterm::execute!(std::io::stdout(), event::DisableMouseCapture)?;
     ratatui::restore();
-    app.cancel_token.cancel();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::duplex, sync::mpsc::channel};
+    use tokio_util::sync::CancellationToken;
+    use websocket::{
+        Client, Server, WsRecv, WsStream,
+        message::{Message, StatusCode},
+    };
+
+    use super::App;
+
+    #[tokio::test]
+    async fn quitting_closes_with_normal_code() {
+        let (client_end, server_end) = duplex(1024);
+        let (_, ws_tx) = WsStream::<Server, _>::from_stream(client_end).into_split();
+        let cancel = CancellationToken::new();
+        let (_ws_tx, queue) = channel(1);
+        let ws_sender = App::spawn_ws_sender(ws_tx, queue, cancel.clone());
+
+        cancel.cancel();
+        ws_sender.await.unwrap();
+
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        assert_eq!(
+            server.receive().await.ok(),
+            Some(Message::Close(StatusCode::Normal, None))
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Chat messages kept between sessions, see [`Config::history_path`](crate::config::Config::history_path).
+
+use std::path::Path;
+
+use color_eyre::eyre::Result;
+use common::protocol;
+use serde::{Deserialize, Serialize};
+
+/// Newest messages kept, older ones are dropped when saving.
+pub const CAPACITY: usize = 1000;
+
+/// A received chat message, without its image.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggedMessage {
+    pub sender: protocol::MessageSender,
+    pub text: String,
+    pub room: protocol::RoomId,
+    /// Unix timestamp of receipt, in seconds.
+    pub sent_at: i64,
+}
+
+/// Reads the messages saved at `path`, none if there's no file yet.
+///
+/// # Errors
+/// If the file exists, but can't be read or wasn't written by [`save`].
+pub fn load(path: &Path) -> Result<Vec<LoggedMessage>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(rmp_serde::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the newest [`CAPACITY`] of `messages` to `path`, creating its directory if needed.
+///
+/// # Errors
+/// If the file or its directory can't be written.
+pub fn save(path: &Path, messages: &[LoggedMessage]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let newest = &messages[messages.len().saturating_sub(CAPACITY)..];
+    std::fs::write(path, rmp_serde::to_vec(newest)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use common::protocol;
+
+    use super::{CAPACITY, LoggedMessage, load, save};
+
+    fn message(text: String) -> LoggedMessage {
+        LoggedMessage {
+            sender: protocol::MessageSender {
+                name: String::from("alice"),
+                color: protocol::Color::Red,
+            },
+            text,
+            room: protocol::RoomId::default(),
+            sent_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn only_the_newest_messages_are_saved() {
+        let dir = std::env::temp_dir().join(format!("tungsto-scrollback-{}", std::process::id()));
+        let path = dir.join("history.msgpack");
+        assert!(load(&path).unwrap().is_empty());
+
+        let messages: Vec<_> = (0..=CAPACITY).map(|i| message(i.to_string())).collect();
+        save(&path, &messages).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.len(), CAPACITY);
+        assert_eq!(loaded.first().unwrap().text, "1");
+        assert_eq!(loaded.last(), messages.last());
+    }
+}
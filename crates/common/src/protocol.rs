@@ -1,24 +1,109 @@
+use std::borrow::Cow;
+
 use serde::{Deserialize, Serialize};
 use websocket::message::Message;
 
 pub type Token = String;
 
+/// Picked by the client for each message it sends, so it's only unique per client.
+pub type MessageId = u64;
+
 pub const NICKNAME_MAX_LEN: usize = 16;
 
+/// Whether `name` has something besides whitespace, and no control characters,
+/// newlines and escape sequences included. The length is checked separately,
+/// against [`NICKNAME_MAX_LEN`].
+///
+/// The server trims surrounding whitespace before checking.
+#[must_use]
+pub fn nickname_is_valid(name: &str) -> bool {
+    !name.trim().is_empty() && !name.chars().any(char::is_control)
+}
+
+/// `text` with control characters, but newlines, swapped for visible stand-ins,
+/// so that escape sequences and backspaces can't scramble the terminal it's shown in.
+///
+/// C0 ones and `DEL` become their Control Pictures (`␛`, `␈`, ...), C1 ones become `�`.
+#[must_use]
+pub fn escape_controls(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(|c| c.is_control() && c != '\n') {
+        return Cow::Borrowed(text);
+    }
+    text.chars()
+        .map(|c| match c {
+            '\n' => c,
+            '\0'..='\x1f' => char::from_u32(0x2400 + u32::from(c)).unwrap_or(c),
+            '\x7f' => '␡',
+            c if c.is_control() => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect()
+}
+
+/// Longest chat message text the server propagates, in bytes.
+/// Longer ones are rejected, see [`ServerMessage::Ack`].
+pub const MESSAGE_MAX_LEN: usize = 4096;
+
+/// WebSocket subprotocol this protocol is spoken over, bumped on breaking changes.
+pub const SUBPROTOCOL: &str = "tungsto.v1";
+
+/// Name of a chat room a client can be a member of.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RoomId(pub String);
+
+impl Default for RoomId {
+    /// The room every client joins upon authentication.
+    fn default() -> Self {
+        Self(String::from("lobby"))
+    }
+}
+
 #[non_exhaustive]
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub enum ClientMessage {
     /// An auth request with a user's display name and its color.
     Auth(MessageSender),
+    /// An auth request taking back the identity of a client that dropped the connection,
+    /// using the token it was given. Answered like [`ClientMessage::Auth`], with the same token.
+    Resume(Token),
     /// Constructed from a token provided by [`ServerMessage::AuthSuccess`], message text,
     /// and attached image bytes (the format is guessed by the client, and let's hope it supports it).
-    /// Does not imply that the message will *actually* be sent.
-    /// The client should only rely on [`ServerMessage::PropagateMessage`].
+    /// Always answered with [`ServerMessage::Ack`], carrying `id` so that the client can tell
+    /// which message it was about. Accepted ones are propagated first.
+    /// Targets `room`, defaulting to the lobby if omitted by the client.
+    /// Both `image` and `room` are optional, so that text-only clients can leave them out.
+    /// So is `id`, echoed back in the sender's copy of [`ServerMessage::PropagateMessage`].
     SendMessage {
         token: Token,
         text: String,
+        #[serde(default)]
         image: Option<Vec<u8>>,
+        #[serde(default)]
+        room: RoomId,
+        #[serde(default)]
+        id: Option<MessageId>,
     },
+    /// A request to become a member of a room, creating it if needed.
+    JoinRoom(RoomId),
+    /// A request to stop receiving messages from a room.
+    LeaveRoom(RoomId),
+    /// A request to be known under a new display name, validated like [`ClientMessage::Auth`].
+    /// Answered with [`ServerNotification::NicknameChanged`] to everyone on success,
+    /// and [`ServerMessage::ChangeNickFailed`] to the sender otherwise.
+    ChangeNick(Token, String),
+    /// A request for everyone online, answered with [`ServerMessage::UserList`].
+    ListUsers(Token),
+    /// A chat message for the user currently named `to` only, limited like
+    /// [`ClientMessage::SendMessage`]. Answered with [`ServerMessage::PropagateDirect`]
+    /// to both ends, or a [`ServerNotification::Literal`] if nobody goes by that name.
+    DirectMessage {
+        token: Token,
+        to: String,
+        text: String,
+    },
+    /// Whether the user started or stopped typing, relayed to everyone else
+    /// as [`ServerMessage::UserTyping`].
+    Typing(Token, bool),
 }
 
 #[non_exhaustive]
@@ -28,9 +113,73 @@ pub enum ServerMessage {
     AuthSuccess(Result<Token, AuthError>),
     /// A chat message from either this client or any other.
     /// See [`ClientMessage::SendMessage`] for field definition.
-    PropagateMessage(MessageSender, String, Option<Vec<u8>>),
+    /// The id is only set in the copy sent back to the sender, as it's only unique per client.
+    PropagateMessage(
+        MessageSender,
+        String,
+        #[serde(default)] Option<Vec<u8>>,
+        #[serde(default)] RoomId,
+        #[serde(default)] Option<MessageId>,
+    ),
     /// Any kind of notification issued by the server.
     Notification(ServerNotification),
+    /// Why [`ClientMessage::ChangeNick`] was rejected, the old name is kept.
+    ChangeNickFailed(AuthError),
+    /// Every authenticated client, sorted by name. See [`ClientMessage::ListUsers`].
+    UserList(Vec<MessageSender>),
+    /// A direct message, see [`ClientMessage::DirectMessage`].
+    /// Sent to the recipient, and back to the sender so they know it went through.
+    PropagateDirect {
+        from: MessageSender,
+        to: String,
+        text: String,
+    },
+    /// Whether [`ClientMessage::SendMessage`] with this `id` was propagated, and if not,
+    /// why, worded to be shown to the user.
+    Ack {
+        id: Option<MessageId>,
+        accepted: bool,
+        reason: Option<String>,
+    },
+    /// [`ClientMessage::DirectMessage`] wasn't delivered.
+    MessageRejected(RejectReason),
+    /// Someone else started or stopped typing, see [`ClientMessage::Typing`].
+    /// Clients should consider users who disconnect to have stopped.
+    UserTyping(MessageSender, bool),
+    /// Recent chat messages in the rooms a client starts in, oldest first.
+    /// Sent once right after a successful [`ServerMessage::AuthSuccess`].
+    History(Vec<HistoryEntry>),
+}
+
+/// A [`ServerMessage::PropagateMessage`] kept by the server, see [`ServerMessage::History`].
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch, when the server propagated it.
+    pub sent_at: u64,
+    /// Always without its image, which the server doesn't keep.
+    pub message: ServerMessage,
+    /// Size in bytes of the image the message was sent with, if any.
+    pub image_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RejectReason {
+    /// Text length exceeds [`MESSAGE_MAX_LEN`].
+    TooLong,
+    /// The sender is over the server's rate limit, it may try again later.
+    TooFast,
+    /// The token is unknown, or its owner isn't a member of the target room.
+    NotAllowed,
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::TooLong => "This message is too long to send.",
+            Self::TooFast => "Slow down, this message wasn't sent.",
+            Self::NotAllowed => "You can't send messages to this room.",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
@@ -39,8 +188,12 @@ pub enum AuthError {
     NicknameUnavailable,
     /// Nickname length exceeds the predefined maximum length.
     NicknameTooLong,
+    /// Nickname is blank or has control characters, see [`nickname_is_valid`].
+    NicknameInvalid,
     /// The user sending [`ClientMessage::Auth`] is already authenticated.
     AlreadyAuthorized,
+    /// The token given to [`ClientMessage::Resume`] is unknown, or it was kept for too long.
+    UnknownToken,
 }
 
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
@@ -51,6 +204,8 @@ pub enum ServerNotification {
     ClientConnected(MessageSender),
     /// A message about a client being disconnected.
     ClientDisconnected(MessageSender),
+    /// A message about a client, previously known by the name given, changing it.
+    NicknameChanged(String, MessageSender),
 }
 
 impl From<ClientMessage> for Message {
@@ -66,10 +221,7 @@ impl TryFrom<&Message> for ClientMessage {
     type Error = ();
 
     fn try_from(value: &Message) -> Result<Self, Self::Error> {
-        match value {
-            Message::Binary(buf) => Ok(rmp_serde::from_slice(buf).map_err(|_| ())?),
-            _ => Err(()),
-        }
+        rmp_serde::from_slice(value.as_binary().ok_or(())?).map_err(|_| ())
     }
 }
 
@@ -86,21 +238,18 @@ impl TryFrom<&Message> for ServerMessage {
     type Error = ();
 
     fn try_from(value: &Message) -> Result<Self, Self::Error> {
-        match value {
-            Message::Binary(buf) => Ok(rmp_serde::from_slice(buf).map_err(|_| ())?),
-            _ => Err(()),
-        }
+        rmp_serde::from_slice(value.as_binary().ok_or(())?).map_err(|_| ())
     }
 }
 
-#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MessageSender {
     pub name: String,
     pub color: Color,
 }
 
 #[non_exhaustive]
-#[derive(Debug, Default, Clone, Copy, Hash, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Color {
     #[default]
     Text,
@@ -112,3 +261,162 @@ pub enum Color {
     Blue,
     Magenta,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use serde::Serialize;
+    use websocket::message::Message;
+
+    use super::{
+        ClientMessage, Color, MessageSender, RoomId, ServerMessage, escape_controls,
+        nickname_is_valid,
+    };
+
+    fn sender() -> MessageSender {
+        MessageSender {
+            name: String::from("alice"),
+            color: Color::Red,
+        }
+    }
+
+    #[test]
+    fn room_messages_round_trip() {
+        let room = RoomId(String::from("rust"));
+
+        let message: Message = ClientMessage::JoinRoom(room.clone()).into();
+        assert!(matches!(
+            ClientMessage::try_from(&message),
+            Ok(ClientMessage::JoinRoom(r)) if r == room
+        ));
+
+        let message: Message = ClientMessage::LeaveRoom(room.clone()).into();
+        assert!(matches!(
+            ClientMessage::try_from(&message),
+            Ok(ClientMessage::LeaveRoom(r)) if r == room
+        ));
+
+        let message: Message = ClientMessage::SendMessage {
+            token: String::from("token"),
+            text: String::from("hi"),
+            image: None,
+            room: room.clone(),
+            id: Some(7),
+        }
+        .into();
+        assert!(matches!(
+            ClientMessage::try_from(&message),
+            Ok(ClientMessage::SendMessage { room: r, id: Some(7), .. }) if r == room
+        ));
+
+        let message: Message = ServerMessage::PropagateMessage(
+            sender(),
+            String::from("hi"),
+            None,
+            room.clone(),
+            Some(7),
+        )
+        .into();
+        assert!(matches!(
+            ServerMessage::try_from(&message),
+            Ok(ServerMessage::PropagateMessage(_, _, _, r, Some(7))) if r == room
+        ));
+    }
+
+    #[test]
+    fn images_round_trip() {
+        let image = vec![0x89, b'P', b'N', b'G'];
+        let message: Message = ServerMessage::PropagateMessage(
+            sender(),
+            String::from("look"),
+            Some(image.clone()),
+            RoomId::default(),
+            None,
+        )
+        .into();
+        assert!(matches!(
+            ServerMessage::try_from(&message),
+            Ok(ServerMessage::PropagateMessage(_, _, Some(i), _, None)) if i == image
+        ));
+    }
+
+    #[test]
+    fn missing_image_is_none() {
+        /// A client that never heard of images.
+        #[derive(Serialize)]
+        enum TextOnly {
+            #[allow(dead_code)]
+            Auth(MessageSender),
+            SendMessage {
+                token: String,
+                text: String,
+            },
+        }
+
+        let mut buf = vec![];
+        TextOnly::SendMessage {
+            token: String::from("token"),
+            text: String::from("hi"),
+        }
+        .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+
+        assert!(matches!(
+            ClientMessage::try_from(&Message::Binary(buf)),
+            Ok(ClientMessage::SendMessage { image: None, room, .. }) if room == RoomId::default()
+        ));
+    }
+
+    #[test]
+    fn missing_room_falls_back_to_lobby() {
+        /// Layout of the messages before rooms were introduced.
+        #[derive(Serialize)]
+        enum Legacy {
+            #[allow(dead_code)]
+            Auth(MessageSender),
+            SendMessage {
+                token: String,
+                text: String,
+                image: Option<Vec<u8>>,
+            },
+        }
+
+        let mut buf = vec![];
+        Legacy::SendMessage {
+            token: String::from("token"),
+            text: String::from("hi"),
+            image: None,
+        }
+        .serialize(&mut rmp_serde::Serializer::new(&mut buf))
+        .unwrap();
+
+        assert!(matches!(
+            ClientMessage::try_from(&Message::Binary(buf)),
+            Ok(ClientMessage::SendMessage { room, id: None, .. }) if room == RoomId::default()
+        ));
+        assert_eq!(RoomId::default().0, "lobby");
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        assert_eq!(escape_controls("\x1b[2Jgone"), "␛[2Jgone");
+        assert_eq!(escape_controls("ab\x08\x08cd"), "ab␈␈cd");
+        assert_eq!(escape_controls("\u{9b}31m\x7f"), "�31m␡");
+        assert_eq!(escape_controls("line\r\nnext"), "line␍\nnext");
+        assert!(matches!(
+            escape_controls("plain, Олег 🦀\nok"),
+            Cow::Borrowed("plain, Олег 🦀\nok")
+        ));
+    }
+
+    #[test]
+    fn nicknames_are_validated() {
+        for valid in ["alice", " bob ", "Олег", "🦀 crab"] {
+            assert!(nickname_is_valid(valid), "{valid:?}");
+        }
+        for invalid in ["", "   ", "a\nb", "tab\there", "\x1b[31mred", "\u{9b}31m"] {
+            assert!(!nickname_is_valid(invalid), "{invalid:?}");
+        }
+    }
+}
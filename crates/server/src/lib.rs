@@ -0,0 +1,1714 @@
+#![warn(clippy::pedantic)]
+use core::net::SocketAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::ErrorKind;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use common::protocol;
+use rand::Rng;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{Mutex, Semaphore, mpsc},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, info, warn};
+use websocket::{
+    Client, WsConfig, WsRecv, WsRecvHalf, WsSend, WsSendHalf, WsStream,
+    keepalive::{PongTracker, keepalive},
+    message::{Message, MessageError, StatusCode},
+};
+
+/// Any transport a client connection can be carried over.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug> Transport for T {}
+
+/// Type-erased client connection, so that [`Clients`] doesn't care about TLS.
+pub type Stream = Box<dyn Transport>;
+
+/// Whether the server speaks the `offered` subprotocol, see [`protocol::SUBPROTOCOL`].
+#[must_use]
+pub fn select_subprotocol(offered: &str) -> bool {
+    offered == protocol::SUBPROTOCOL
+}
+
+/// How many messages may queue up for a client before it's considered unreachable.
+pub const OUTBOX_CAPACITY: usize = 64;
+
+/// Default cap on connections [`serve`] handles at once, authenticated or not.
+pub const MAX_CLIENTS: usize = 1024;
+
+/// How long [`serve`] waits for goodbyes to be written out once shut down.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How long the identity of a client that dropped the connection is kept
+/// for [`protocol::ClientMessage::Resume`].
+pub const RESUME_GRACE: Duration = Duration::from_secs(30);
+
+/// How many recent chat messages are kept for [`protocol::ServerMessage::History`].
+/// Their images aren't, or a few big ones would pin that much memory and
+/// make the history too large for clients to receive.
+pub const HISTORY_CAPACITY: usize = 50;
+
+/// How many chat messages a client may send in a row before being limited.
+pub const MESSAGE_BURST: u32 = 10;
+/// How often a rate limited client may send another chat message.
+pub const MESSAGE_REFILL: Duration = Duration::from_millis(500);
+
+/// Token bucket holding up to [`MESSAGE_BURST`] messages, refilled one per [`MESSAGE_REFILL`].
+#[derive(Debug)]
+struct RateLimit {
+    tokens: u32,
+    /// When the last token was refilled, or the bucket was last seen full.
+    refilled_at: Instant,
+}
+
+impl RateLimit {
+    fn new() -> Self {
+        Self {
+            tokens: MESSAGE_BURST,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there's one left at `now`.
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        let refilled =
+            u32::try_from(elapsed.as_millis() / MESSAGE_REFILL.as_millis()).unwrap_or(u32::MAX);
+        self.tokens = self.tokens.saturating_add(refilled);
+        if self.tokens >= MESSAGE_BURST {
+            self.tokens = MESSAGE_BURST;
+            self.refilled_at = now;
+        } else {
+            self.refilled_at += MESSAGE_REFILL * refilled;
+        }
+
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+#[derive(Debug)]
+struct ClientData {
+    /// Feeds the client's writer task, see [`spawn_writer`].
+    tx: mpsc::Sender<Message>,
+    /// Cancelled to end the client's connection once it's been forgotten,
+    /// see [`Clients::disconnect_failed`].
+    evicted: CancellationToken,
+    name: String,
+    color: protocol::Color,
+    rooms: HashSet<protocol::RoomId>,
+    rate_limit: RateLimit,
+}
+
+/// Identity of a client that dropped the connection, see [`RESUME_GRACE`].
+#[derive(Debug)]
+struct Reservation {
+    name: String,
+    color: protocol::Color,
+    rooms: HashSet<protocol::RoomId>,
+    /// Kept as is, so that reconnecting doesn't get around it.
+    rate_limit: RateLimit,
+    expires_at: Instant,
+}
+
+impl ClientData {
+    /// Why a chat message with `text` can't be sent right now, if it can't.
+    /// Takes from the rate limit otherwise.
+    fn check_message(&mut self, text: &str) -> Option<protocol::RejectReason> {
+        if text.len() > protocol::MESSAGE_MAX_LEN {
+            Some(protocol::RejectReason::TooLong)
+        } else if !self.rate_limit.try_take(Instant::now()) {
+            Some(protocol::RejectReason::TooFast)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<&ClientData> for protocol::MessageSender {
+    fn from(value: &ClientData) -> Self {
+        Self {
+            name: value.name.clone(),
+            color: value.color,
+        }
+    }
+}
+
+impl From<&mut ClientData> for protocol::MessageSender {
+    fn from(value: &mut ClientData) -> Self {
+        Self {
+            name: value.name.clone(),
+            color: value.color,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Clients {
+    addr_map: HashMap<SocketAddr, ClientData>,
+    token_map: HashMap<protocol::Token, SocketAddr>,
+    /// The last [`HISTORY_CAPACITY`] propagated messages, oldest first.
+    history: VecDeque<protocol::HistoryEntry>,
+    /// Identities of clients that dropped the connection, by their token.
+    reserved: HashMap<protocol::Token, Reservation>,
+}
+
+impl Clients {
+    #[must_use]
+    pub fn new() -> Self {
+        Clients {
+            addr_map: HashMap::new(),
+            token_map: HashMap::new(),
+            history: VecDeque::with_capacity(HISTORY_CAPACITY),
+            reserved: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn by_addr(&self, address: SocketAddr) -> Option<&ClientData> {
+        self.addr_map.get(&address)
+    }
+
+    pub(crate) fn by_addr_mut(&mut self, address: SocketAddr) -> Option<&mut ClientData> {
+        self.addr_map.get_mut(&address)
+    }
+
+    pub(crate) fn addr_by_name(&self, name: &str) -> Option<SocketAddr> {
+        self.addr_map
+            .iter()
+            .find(|(_, client)| client.name == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    pub(crate) fn by_token(&self, token: &protocol::Token) -> Option<&ClientData> {
+        self.token_map
+            .get(token)
+            .and_then(|addr| self.by_addr(*addr))
+    }
+
+    // TODO: Move these into whoever owns Clients in the future.
+    /// 128 random bits, base64-encoded. Opaque to clients and unrelated to their address,
+    /// so it can't be guessed by someone who merely knows who they're talking to.
+    pub(crate) fn generate_token() -> protocol::Token {
+        let bytes: [u8; 16] = rand::rng().random();
+        STANDARD.encode(bytes)
+    }
+
+    /// Whether `name` is free to be taken, valid and not too long.
+    /// Names of clients that may still resume are taken.
+    fn check_nickname(&self, name: &str) -> Result<(), protocol::AuthError> {
+        if !protocol::nickname_is_valid(name) {
+            return Err(protocol::AuthError::NicknameInvalid);
+        }
+        let now = Instant::now();
+        if self.addr_map.values().any(|c| c.name == name)
+            || self
+                .reserved
+                .values()
+                .any(|r| r.name == name && r.expires_at > now)
+        {
+            return Err(protocol::AuthError::NicknameUnavailable);
+        }
+        if name.len() > protocol::NICKNAME_MAX_LEN {
+            return Err(protocol::AuthError::NicknameTooLong);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn try_connect(
+        &mut self,
+        address: SocketAddr,
+        client: ClientData,
+    ) -> Result<protocol::Token, protocol::AuthError> {
+        self.check_nickname(&client.name)?;
+
+        if self.addr_map.insert(address, client).is_some() {
+            Err(protocol::AuthError::AlreadyAuthorized)
+        } else {
+            let token = Clients::generate_token();
+            self.token_map.insert(token.clone(), address);
+            Ok(token)
+        }
+    }
+
+    /// Renames the client at `address` to `name` with surrounding whitespace trimmed,
+    /// returning its old name.
+    pub(crate) fn try_rename(
+        &mut self,
+        address: SocketAddr,
+        name: &str,
+    ) -> Result<String, protocol::AuthError> {
+        let name = name.trim().to_owned();
+        self.check_nickname(&name)?;
+        let client = self
+            .by_addr_mut(address)
+            .ok_or(protocol::AuthError::NicknameUnavailable)?;
+        Ok(std::mem::replace(&mut client.name, name))
+    }
+
+    /// Every authenticated client, sorted by name.
+    pub(crate) fn users(&self) -> Vec<protocol::MessageSender> {
+        let mut users: Vec<_> = self
+            .addr_map
+            .values()
+            .map(protocol::MessageSender::from)
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
+
+    pub(crate) fn disconnect(&mut self, address: SocketAddr) {
+        self.addr_map.remove(&address);
+        self.token_map.retain(|_, v| *v != address);
+    }
+
+    /// Same as [`Clients::disconnect`], but keeps the identity of the client
+    /// for [`RESUME_GRACE`], see [`Clients::try_resume`].
+    pub(crate) fn reserve(&mut self, address: SocketAddr) {
+        let token = self
+            .token_map
+            .iter()
+            .find(|(_, addr)| **addr == address)
+            .map(|(token, _)| token.clone());
+        let client = self.addr_map.remove(&address);
+        let (Some(token), Some(client)) = (token, client) else {
+            return self.disconnect(address);
+        };
+        self.token_map.remove(&token);
+        let now = Instant::now();
+        self.reserved.retain(|_, r| r.expires_at > now);
+        self.reserved.insert(
+            token,
+            Reservation {
+                name: client.name,
+                color: client.color,
+                rooms: client.rooms,
+                rate_limit: client.rate_limit,
+                expires_at: now + RESUME_GRACE,
+            },
+        );
+    }
+
+    /// Binds the identity reserved under `token` to the client at `address`, writing to `tx`
+    /// and ended through `evicted`.
+    pub(crate) fn try_resume(
+        &mut self,
+        address: SocketAddr,
+        token: &protocol::Token,
+        tx: mpsc::Sender<Message>,
+        evicted: CancellationToken,
+    ) -> Result<protocol::MessageSender, protocol::AuthError> {
+        let now = Instant::now();
+        self.reserved.retain(|_, r| r.expires_at > now);
+        if self.addr_map.contains_key(&address) {
+            return Err(protocol::AuthError::AlreadyAuthorized);
+        }
+        let reservation = self
+            .reserved
+            .remove(token)
+            .ok_or(protocol::AuthError::UnknownToken)?;
+        let client = ClientData {
+            tx,
+            evicted,
+            name: reservation.name,
+            color: reservation.color,
+            rooms: reservation.rooms,
+            rate_limit: reservation.rate_limit,
+        };
+        let sender = protocol::MessageSender::from(&client);
+        self.addr_map.insert(address, client);
+        self.token_map.insert(token.clone(), address);
+        Ok(sender)
+    }
+    //
+
+    /// Says goodbye to every client and forgets them, so that their writers shut down
+    /// once whatever is queued is written out.
+    pub(crate) fn shut_down(&mut self) {
+        let goodbye: Message = protocol::ServerMessage::Notification(
+            protocol::ServerNotification::Literal(String::from("The server is shutting down.")),
+        )
+        .into();
+        for client in self.addr_map.values() {
+            _ = client.tx.try_send(goodbye.clone());
+            _ = client
+                .tx
+                .try_send(Message::Close(StatusCode::GoingAway, None));
+        }
+        self.addr_map.clear();
+        self.token_map.clear();
+    }
+
+    /// Queues `message` for the client at `address` without waiting for it to be written.
+    pub(crate) fn send_to_addr(
+        &self,
+        address: SocketAddr,
+        message: Message,
+    ) -> std::io::Result<()> {
+        self.by_addr(address)
+            .ok_or::<std::io::Error>(ErrorKind::NotFound.into())?
+            .tx
+            .try_send(message)
+            .map_err(|_| ErrorKind::BrokenPipe.into())
+    }
+
+    /// Answers the [`protocol::ClientMessage::SendMessage`] with `id` from `address`,
+    /// accepting it unless there's a `rejection`.
+    pub(crate) fn ack(
+        &self,
+        address: SocketAddr,
+        id: Option<protocol::MessageId>,
+        rejection: Option<protocol::RejectReason>,
+    ) {
+        _ = self.send_to_addr(
+            address,
+            protocol::ServerMessage::Ack {
+                id,
+                accepted: rejection.is_none(),
+                reason: rejection.map(|reason| reason.to_string()),
+            }
+            .into(),
+        );
+    }
+
+    /// Queues `message` for every client matching `filter`. Clients whose writer is gone
+    /// or whose outbox is full are skipped, and their addresses returned.
+    fn send_where<F>(&self, message: &Message, filter: F) -> Vec<SocketAddr>
+    where
+        F: Fn(SocketAddr, &ClientData) -> bool,
+    {
+        let mut recipients = 0;
+        let failed: Vec<_> = self
+            .addr_map
+            .iter()
+            .filter(|(addr, client)| filter(**addr, client))
+            .inspect(|_| recipients += 1)
+            .filter(|(_, client)| client.tx.try_send(message.clone()).is_err())
+            .map(|(addr, _)| *addr)
+            .collect();
+        debug!(recipients, failed = failed.len(), "broadcast");
+        failed
+    }
+
+    /// Forgets clients a broadcast couldn't reach, and ends their connections.
+    pub(crate) fn disconnect_failed(&mut self, failed: Vec<SocketAddr>) {
+        for address in failed {
+            if let Some(client) = self.by_addr(address) {
+                warn!(%address, name = client.name, "unreachable, disconnecting");
+                client.evicted.cancel();
+            }
+            self.disconnect(address);
+        }
+    }
+
+    pub(crate) fn broadcast(&self, message: &Message) -> Vec<SocketAddr> {
+        self.send_where(message, |_, _| true)
+    }
+
+    pub(crate) fn broadcast_room(
+        &self,
+        room: &protocol::RoomId,
+        message: &Message,
+    ) -> Vec<SocketAddr> {
+        self.send_where(message, |_, client| client.rooms.contains(room))
+    }
+
+    pub(crate) fn broadcast_room_except(
+        &self,
+        room: &protocol::RoomId,
+        address: SocketAddr,
+        message: &Message,
+    ) -> Vec<SocketAddr> {
+        self.send_where(message, |addr, client| {
+            addr != address && client.rooms.contains(room)
+        })
+    }
+
+    /// Sends a chat message from `from` to everyone in `room`.
+    /// Only the sender's own copy carries `id`, since it means nothing to the rest.
+    pub(crate) fn propagate(
+        &mut self,
+        from: SocketAddr,
+        sender: protocol::MessageSender,
+        text: String,
+        image: Option<Vec<u8>>,
+        room: protocol::RoomId,
+        id: Option<protocol::MessageId>,
+    ) -> Vec<SocketAddr> {
+        self.remember(
+            protocol::ServerMessage::PropagateMessage(
+                sender.clone(),
+                text.clone(),
+                None,
+                room.clone(),
+                None,
+            ),
+            image.as_ref().map(Vec::len),
+        );
+        let Some(id) = id else {
+            return self.broadcast_room(
+                &room,
+                &protocol::ServerMessage::PropagateMessage(sender, text, image, room.clone(), None)
+                    .into(),
+            );
+        };
+        let mut failed = self.broadcast_room_except(
+            &room,
+            from,
+            &protocol::ServerMessage::PropagateMessage(
+                sender.clone(),
+                text.clone(),
+                image.clone(),
+                room.clone(),
+                None,
+            )
+            .into(),
+        );
+        let echo = protocol::ServerMessage::PropagateMessage(sender, text, image, room, Some(id));
+        if self.send_to_addr(from, echo.into()).is_err() {
+            failed.push(from);
+        }
+        failed
+    }
+
+    /// Keeps a propagated `message` for [`Clients::history_for`], forgetting the oldest one
+    /// past [`HISTORY_CAPACITY`]. Only the size of its image is kept.
+    fn remember(&mut self, message: protocol::ServerMessage, image_size: Option<usize>) {
+        if self.history.len() == HISTORY_CAPACITY {
+            self.history.pop_front();
+        }
+        let sent_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.history.push_back(protocol::HistoryEntry {
+            sent_at,
+            message,
+            image_size,
+        });
+    }
+
+    /// Remembered messages in the rooms the client at `address` is a member of.
+    pub(crate) fn history_for(&self, address: SocketAddr) -> Vec<protocol::HistoryEntry> {
+        let Some(client) = self.by_addr(address) else {
+            return vec![];
+        };
+        self.history
+            .iter()
+            .filter(|entry| {
+                matches!(&entry.message, protocol::ServerMessage::PropagateMessage(.., room, _)
+                    if client.rooms.contains(room))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Renames the client at `address`, telling everyone about it.
+    /// The client is told if the name is rejected instead.
+    pub(crate) fn change_nick(&mut self, address: SocketAddr, name: &str) -> Vec<SocketAddr> {
+        let old_name = match self.try_rename(address, name) {
+            Ok(old_name) => old_name,
+            Err(err) => {
+                _ = self.send_to_addr(
+                    address,
+                    protocol::ServerMessage::ChangeNickFailed(err).into(),
+                );
+                return vec![];
+            }
+        };
+        let Some(sender) = self.by_addr(address).map(protocol::MessageSender::from) else {
+            return vec![];
+        };
+        info!(%address, old_name, name = sender.name, "nickname changed");
+        self.broadcast(
+            &protocol::ServerMessage::Notification(protocol::ServerNotification::NicknameChanged(
+                old_name, sender,
+            ))
+            .into(),
+        )
+    }
+
+    /// Sends a direct message from `from` to the client named `to`, and back to the sender.
+    /// The sender is told if it's rejected, or if nobody goes by that name.
+    pub(crate) fn send_direct(
+        &mut self,
+        from: SocketAddr,
+        to: String,
+        text: String,
+    ) -> Vec<SocketAddr> {
+        let Some(client) = self.by_addr_mut(from) else {
+            return vec![];
+        };
+        let rejection = client.check_message(&text);
+        let sender = protocol::MessageSender::from(client);
+        if let Some(reason) = rejection {
+            info!(%from, name = sender.name, ?reason, "direct message rejected");
+            _ = self.send_to_addr(
+                from,
+                protocol::ServerMessage::MessageRejected(reason).into(),
+            );
+            return vec![];
+        }
+        let Some(target) = self.addr_by_name(&to) else {
+            _ = self.send_to_addr(
+                from,
+                protocol::ServerMessage::Notification(protocol::ServerNotification::Literal(
+                    format!("Nobody named {to} is online."),
+                ))
+                .into(),
+            );
+            return vec![];
+        };
+
+        let message: Message = protocol::ServerMessage::PropagateDirect {
+            from: sender,
+            to,
+            text,
+        }
+        .into();
+        let mut recipients = vec![target];
+        if target != from {
+            recipients.push(from);
+        }
+        recipients
+            .into_iter()
+            .filter(|address| self.send_to_addr(*address, message.clone()).is_err())
+            .collect()
+    }
+
+    pub(crate) fn broadcast_except_one(
+        &self,
+        address: SocketAddr,
+        message: &Message,
+    ) -> Vec<SocketAddr> {
+        self.send_where(message, |addr, _| addr != address)
+    }
+}
+
+/// Forgets the client and notifies everyone else. `code` is the one the client closed with,
+/// or [`StatusCode::CloseAbnormal`] if the connection just dropped.
+async fn on_disconnect(address: SocketAddr, clients: Arc<Mutex<Clients>>, code: StatusCode) {
+    let mut lock = clients.lock().await;
+    let maybe_sender = lock.by_addr(address).map(protocol::MessageSender::from);
+    if let Some(sender) = maybe_sender {
+        if code == StatusCode::CloseAbnormal {
+            info!(name = sender.name, "dropped the connection");
+        } else {
+            info!(name = sender.name, ?code, "disconnected");
+        }
+        let failed = lock.broadcast_except_one(
+            address,
+            &protocol::ServerMessage::Notification(
+                protocol::ServerNotification::ClientDisconnected(sender),
+            )
+            .into(),
+        );
+        if code == StatusCode::CloseAbnormal {
+            lock.reserve(address);
+        } else {
+            lock.disconnect(address);
+        }
+        lock.disconnect_failed(failed);
+    }
+}
+
+/// Owns the sending half of a connection, writing out whatever is queued for it.
+/// Once every sender is dropped, the connection is shut down. Stops early if a write
+/// fails, which closes the channel for everyone still queueing.
+fn spawn_writer(mut tx: WsSendHalf<Client, Stream>) -> mpsc::Sender<Message> {
+    let (outbox, mut queue) = mpsc::channel::<Message>(OUTBOX_CAPACITY);
+    tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            if tx.send(message).await.is_err() {
+                return;
+            }
+        }
+        _ = tx.shutdown().await;
+    });
+    outbox
+}
+
+/// Drives a single upgraded connection: authentication first, then chat
+/// messages until the client leaves. Clients are told apart by
+/// [`WsStream::peer_addr`], so it has to be set.
+///
+/// Authenticating is given as long as the handshake, see [`WsConfig::handshake_timeout`],
+/// after which the connection is closed with [`StatusCode::PolicyViolated`].
+///
+/// # Errors
+/// If the socket has no peer address, or the connection's writer stops
+/// before authentication is over.
+pub async fn on_connect(
+    socket: WsStream<Client, Stream>,
+    clients: Arc<Mutex<Clients>>,
+) -> std::io::Result<()> {
+    let addr = socket.peer_addr().ok_or(ErrorKind::InvalidInput)?;
+    let (mut rx, tx) = socket.into_split();
+    let outbox = spawn_writer(tx);
+
+    // started right away, so that peers vanishing before they authenticate are noticed too
+    let config = rx.config();
+    let pongs = PongTracker::default();
+    let pinger = &outbox;
+    let mut keepalive = pin!(keepalive(
+        move |ping| async move { pinger.send(ping).await.is_ok() },
+        &pongs,
+        config.keepalive_interval(),
+        config.keepalive_timeout(),
+    ));
+
+    let evicted = CancellationToken::new();
+    let mut auth_deadline = pin!(tokio::time::sleep(config.handshake_timeout()));
+    loop {
+        let authenticated = tokio::select! {
+            authenticated = handle_auth(&mut rx, &outbox, &evicted, &pongs, addr, Arc::clone(&clients)) => {
+                authenticated
+            }
+            _ = &mut keepalive => {
+                info!("peer stopped answering pings before authenticating");
+                return Ok(());
+            }
+            () = &mut auth_deadline => {
+                info!("didn't authenticate in time");
+                _ = outbox.send(Message::Close(StatusCode::PolicyViolated, None)).await;
+                return Ok(());
+            }
+        };
+        match authenticated {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(_) => {
+                // currently has no effect, but is probably the
+                // right thing to do
+                on_disconnect(addr, clients, StatusCode::CloseAbnormal).await;
+                return Ok(());
+            }
+        }
+    }
+
+    loop {
+        let received = tokio::select! {
+            received = rx.receive() => received,
+            () = evicted.cancelled() => {
+                // already forgotten, and whatever is queued may never be written out
+                _ = outbox.try_send(Message::Close(StatusCode::TryAgainLater, None));
+                return Ok(());
+            }
+            _ = &mut keepalive => {
+                info!("peer stopped answering pings");
+                on_disconnect(addr, clients, StatusCode::CloseAbnormal).await;
+                return Ok(());
+            }
+        };
+        match received {
+            Ok(Message::Close(code, _)) => {
+                // echo to complete the closing handshake, the server is the one to drop TCP,
+                // which the writer does once the client is forgotten.
+                // Forgotten already means the server closed first, see `Clients::shut_down`.
+                if clients.lock().await.by_addr(addr).is_some() {
+                    _ = outbox.send(Message::Close(code, None)).await;
+                }
+                on_disconnect(addr, clients, code).await;
+                return Ok(());
+            }
+            Ok(Message::Ping(payload)) => _ = outbox.send(Message::Pong(payload)).await,
+            Ok(Message::Pong(payload)) => pongs.record(&payload),
+            Ok(msg) => match protocol::ClientMessage::try_from(&msg) {
+                Ok(message) => {
+                    handle_client_message(message, addr, Arc::clone(&clients)).await;
+                }
+                Err(()) => {
+                    // not the whole message, it may well be megabytes of garbage
+                    warn!(len = msg.len(), "unparsable message");
+                }
+            },
+            Err(error @ MessageError::ProtocolViolated(code))
+                if code != StatusCode::CloseAbnormal =>
+            {
+                warn!(%error, "closing");
+                _ = outbox.send(Message::Close(code, None)).await;
+                on_disconnect(addr, clients, code).await;
+                return Ok(());
+            }
+            Err(_) => {
+                on_disconnect(addr, clients, StatusCode::CloseAbnormal).await;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Waits for an [`protocol::ClientMessage::Auth`] and registers the client.
+/// Returns whether it succeeded, `false` meaning the client may try again.
+/// *Ping*s are answered and *Pong*s recorded in `pongs` meanwhile.
+async fn handle_auth(
+    rx: &mut WsRecvHalf<Client, Stream>,
+    outbox: &mpsc::Sender<Message>,
+    evicted: &CancellationToken,
+    pongs: &PongTracker,
+    addr: SocketAddr,
+    clients: Arc<Mutex<Clients>>,
+) -> std::io::Result<bool> {
+    let client_msg = match rx.receive().await {
+        Ok(Message::Close(code, _)) => {
+            _ = outbox.send(Message::Close(code, None)).await;
+            return Err(ErrorKind::ConnectionAborted.into());
+        }
+        Ok(Message::Ping(payload)) => {
+            _ = outbox.send(Message::Pong(payload)).await;
+            return Ok(false);
+        }
+        Ok(Message::Pong(payload)) => {
+            pongs.record(&payload);
+            return Ok(false);
+        }
+        Ok(msg) => protocol::ClientMessage::try_from(&msg).ok(),
+        Err(MessageError::ProtocolViolated(StatusCode::CloseAbnormal)) => {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        Err(error @ MessageError::ProtocolViolated(code)) => {
+            warn!(%error, "closing during authentication");
+            _ = outbox.send(Message::Close(code, None)).await;
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Err(_) => return Ok(false),
+    };
+
+    let mut lock = clients.lock().await;
+    let connected = match client_msg {
+        Some(protocol::ClientMessage::Auth(protocol::MessageSender { name, color })) => {
+            let new_sender = protocol::MessageSender {
+                name: name.trim().to_owned(),
+                color,
+            };
+            lock.try_connect(
+                addr,
+                ClientData {
+                    tx: outbox.clone(),
+                    evicted: evicted.clone(),
+                    name: new_sender.name.clone(),
+                    color: new_sender.color,
+                    rooms: HashSet::from([protocol::RoomId::default()]),
+                    rate_limit: RateLimit::new(),
+                },
+            )
+            .map(|token| (token, new_sender))
+        }
+        Some(protocol::ClientMessage::Resume(token)) => lock
+            .try_resume(addr, &token, outbox.clone(), evicted.clone())
+            .map(|sender| (token, sender)),
+        _ => return Ok(false),
+    };
+    let (token, new_sender) = match connected {
+        Ok(connected) => connected,
+        Err(err) => {
+            drop(lock);
+            info!(?err, "authentication failed");
+            outbox
+                .send(protocol::ServerMessage::AuthSuccess(Err(err)).into())
+                .await
+                .map_err(|_| ErrorKind::BrokenPipe)?;
+            return Ok(false);
+        }
+    };
+
+    lock.send_to_addr(addr, protocol::ServerMessage::AuthSuccess(Ok(token)).into())?;
+    lock.send_to_addr(
+        addr,
+        protocol::ServerMessage::History(lock.history_for(addr)).into(),
+    )?;
+    info!(name = new_sender.name, "authenticated");
+    let failed = lock.broadcast_except_one(
+        addr,
+        &protocol::ServerMessage::Notification(protocol::ServerNotification::ClientConnected(
+            new_sender,
+        ))
+        .into(),
+    );
+    lock.disconnect_failed(failed);
+
+    Ok(true)
+}
+
+async fn handle_client_message(
+    message: protocol::ClientMessage,
+    addr: SocketAddr,
+    clients: Arc<Mutex<Clients>>,
+) {
+    match message {
+        protocol::ClientMessage::SendMessage {
+            token,
+            text,
+            image,
+            room,
+            id,
+        } => {
+            let mut lock = clients.lock().await;
+            let owner = lock.token_map.get(&token).copied();
+            let Some(client) = lock
+                .by_addr_mut(addr)
+                .filter(|client| owner == Some(addr) && client.rooms.contains(&room))
+            else {
+                warn!(?room, "unknown sender or room for a chat message");
+                lock.ack(addr, id, Some(protocol::RejectReason::NotAllowed));
+                return;
+            };
+            let rejection = client.check_message(&text);
+            let sender = protocol::MessageSender::from(client);
+            if let Some(reason) = rejection {
+                info!(name = sender.name, ?reason, "message rejected");
+                lock.ack(addr, id, Some(reason));
+                return;
+            }
+            let failed = lock.propagate(addr, sender, text, image, room, id);
+            lock.ack(addr, id, None);
+            lock.disconnect_failed(failed);
+        }
+        protocol::ClientMessage::DirectMessage { token, to, text } => {
+            let mut lock = clients.lock().await;
+            if lock.token_map.get(&token) != Some(&addr) {
+                warn!(to, "unknown sender of a direct message");
+                return;
+            }
+            let failed = lock.send_direct(addr, to, text);
+            lock.disconnect_failed(failed);
+        }
+        protocol::ClientMessage::JoinRoom(room) => {
+            if let Some(client) = clients.lock().await.by_addr_mut(addr) {
+                debug!(name = client.name, ?room, "joined a room");
+                client.rooms.insert(room);
+            }
+        }
+        protocol::ClientMessage::LeaveRoom(room) => {
+            if let Some(client) = clients.lock().await.by_addr_mut(addr) {
+                debug!(name = client.name, ?room, "left a room");
+                client.rooms.remove(&room);
+            }
+        }
+        protocol::ClientMessage::ChangeNick(token, name) => {
+            let mut lock = clients.lock().await;
+            if lock.token_map.get(&token) != Some(&addr) {
+                warn!("unknown sender changing nickname");
+                return;
+            }
+            let failed = lock.change_nick(addr, &name);
+            lock.disconnect_failed(failed);
+        }
+        protocol::ClientMessage::ListUsers(token) => {
+            let lock = clients.lock().await;
+            if lock.by_token(&token).is_none() {
+                warn!("unknown sender listing users");
+                return;
+            }
+            _ = lock.send_to_addr(addr, protocol::ServerMessage::UserList(lock.users()).into());
+        }
+        protocol::ClientMessage::Typing(token, typing) => {
+            let mut lock = clients.lock().await;
+            let Some(sender) = lock
+                .by_addr(addr)
+                .filter(|_| lock.token_map.get(&token) == Some(&addr))
+                .map(protocol::MessageSender::from)
+            else {
+                warn!("unknown sender typing");
+                return;
+            };
+            let failed = lock.broadcast_except_one(
+                addr,
+                &protocol::ServerMessage::UserTyping(sender, typing).into(),
+            );
+            lock.disconnect_failed(failed);
+        }
+        msg => warn!(?msg, "unhandled message"),
+    }
+}
+
+/// Accepts connections on `listener` until `shutdown` fires, upgrading each one after `wrap`
+/// (e.g. a TLS handshake) and running [`on_connect`] for it in a separate task.
+/// Every connection uses `config`, and is dropped if not upgraded within
+/// its [`WsConfig::handshake_timeout`], or not authenticated within as long again.
+///
+/// `hosts` are the `Host` headers clients may send, any other one is refused.
+/// If `origins` are given, browsers connecting from anywhere else are refused with
+/// `403 Forbidden`. Clients sending no `Origin` aren't browsers, e.g. the TUI, and pass.
+/// Past `max_clients` connections, new ones are closed right after being accepted.
+///
+/// On shutdown, clients are notified and closed with [`StatusCode::GoingAway`],
+/// and given [`SHUTDOWN_GRACE`] for that to be written out.
+///
+/// # Errors
+/// Never, currently. Failed connections are skipped.
+pub async fn serve<F, Fut>(
+    listener: TcpListener,
+    hosts: Vec<String>,
+    origins: Option<Vec<String>>,
+    wrap: F,
+    shutdown: CancellationToken,
+    max_clients: usize,
+    config: WsConfig,
+) -> std::io::Result<()>
+where
+    F: Fn(TcpStream) -> Fut,
+    Fut: Future<Output = std::io::Result<Stream>> + Send + 'static,
+{
+    let clients = Arc::new(Mutex::new(Clients::new()));
+    let hosts: Arc<[String]> = hosts.into();
+    let origins: Option<Arc<[String]>> = origins.map(Into::into);
+    let slots = Arc::new(Semaphore::new(max_clients));
+
+    loop {
+        let accepted = tokio::select! {
+            () = shutdown.cancelled() => break,
+            accepted = listener.accept() => accepted,
+        };
+        let Ok((socket, addr)) = accepted else {
+            continue;
+        };
+        // held by the connection's task until it's over
+        let Ok(slot) = Arc::clone(&slots).try_acquire_owned() else {
+            warn!(%addr, max_clients, "connection refused, too many open");
+            continue;
+        };
+        let wrapped = wrap(socket);
+        let hosts = Arc::clone(&hosts);
+        let origins = origins.clone();
+        let clients = Arc::clone(&clients);
+
+        tokio::spawn(
+            async move {
+                let _slot = slot;
+                let upgrade = async {
+                    let mut socket =
+                        WsStream::<Client, Stream>::from_stream_with_config(wrapped.await?, config)
+                            .with_peer_addr(addr);
+                    let info = socket
+                        .try_upgrade_checked(
+                            |host| hosts.iter().any(|h| h == host),
+                            |origin| {
+                                origins.as_ref().is_none_or(|origins| {
+                                    origin.is_none_or(|o| origins.iter().any(|h| h == o))
+                                })
+                            },
+                            select_subprotocol,
+                        )
+                        .await?;
+                    Ok::<_, std::io::Error>((socket, info))
+                };
+                match tokio::time::timeout(config.handshake_timeout(), upgrade).await {
+                    Ok(Ok((socket, info))) => {
+                        debug!(host = %info.host, path = %info.path, subprotocol = ?info.subprotocol, "upgraded");
+                        on_connect(socket, clients).await
+                    }
+                    Ok(Err(_)) => Ok(()),
+                    Err(_) => {
+                        info!("timed out during the handshake");
+                        Ok(())
+                    }
+                }
+            }
+            .instrument(tracing::info_span!("connection", %addr)),
+        );
+    }
+
+    info!("shutting down");
+    clients.lock().await.shut_down();
+    tokio::time::sleep(SHUTDOWN_GRACE).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::net::SocketAddr;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use common::protocol;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt, duplex},
+        sync::{Mutex, mpsc},
+    };
+    use tokio_util::sync::CancellationToken;
+    use websocket::{
+        Client, Server, WsRecv, WsSend, WsStream,
+        message::{Message, StatusCode},
+    };
+
+    use super::{
+        ClientData, Clients, HISTORY_CAPACITY, MESSAGE_BURST, MESSAGE_REFILL, OUTBOX_CAPACITY,
+        RateLimit, Stream, handle_client_message, on_connect, select_subprotocol,
+    };
+
+    /// Registers a client at `addr` in `rooms`, returning what gets queued for it.
+    fn add_client(
+        clients: &mut Clients,
+        addr: SocketAddr,
+        rooms: &[&str],
+    ) -> mpsc::Receiver<Message> {
+        let (tx, outbox) = mpsc::channel(OUTBOX_CAPACITY);
+        clients.addr_map.insert(
+            addr,
+            ClientData {
+                tx,
+                evicted: CancellationToken::new(),
+                name: addr.to_string(),
+                color: protocol::Color::default(),
+                rooms: rooms
+                    .iter()
+                    .map(|room| protocol::RoomId((*room).to_string()))
+                    .collect::<HashSet<_>>(),
+                rate_limit: RateLimit::new(),
+            },
+        );
+        outbox
+    }
+
+    /// Gives the client at `addr` the token `"token"`, and shares `clients` the way
+    /// connections do.
+    fn authed_clients(mut clients: Clients, addr: SocketAddr) -> Arc<Mutex<Clients>> {
+        clients.token_map.insert(String::from("token"), addr);
+        Arc::new(Mutex::new(clients))
+    }
+
+    #[tokio::test]
+    async fn room_broadcast_reaches_only_members() {
+        let mut clients = Clients::new();
+        let mut alice = add_client(&mut clients, "127.0.0.1:1".parse().unwrap(), &["rust"]);
+        let mut bob = add_client(
+            &mut clients,
+            "127.0.0.1:2".parse().unwrap(),
+            &["rust", "go"],
+        );
+        let mut carol = add_client(&mut clients, "127.0.0.1:3".parse().unwrap(), &["go"]);
+
+        let room = protocol::RoomId("rust".to_string());
+        let message = Message::text("hello");
+        assert!(clients.broadcast_room(&room, &message).is_empty());
+        assert!(
+            clients
+                .broadcast_room(&protocol::RoomId("empty".to_string()), &message)
+                .is_empty()
+        );
+        drop(clients);
+
+        assert_eq!(alice.recv().await, Some(message.clone()));
+        assert_eq!(bob.recv().await, Some(message));
+        assert_eq!(carol.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn broadcast_skips_unreachable_clients() {
+        let mut clients = Clients::new();
+        let alice_addr = "127.0.0.1:1".parse().unwrap();
+        let bob_addr = "127.0.0.1:2".parse().unwrap();
+        let carol_addr = "127.0.0.1:3".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &[]);
+        drop(add_client(&mut clients, bob_addr, &[]));
+        let mut carol = add_client(&mut clients, carol_addr, &[]);
+
+        let message = Message::text("hello");
+        assert_eq!(clients.broadcast(&message), vec![bob_addr]);
+        let bob_evicted = clients.by_addr(bob_addr).unwrap().evicted.clone();
+        clients.disconnect_failed(vec![bob_addr]);
+        assert!(clients.by_addr(bob_addr).is_none());
+        assert!(bob_evicted.is_cancelled());
+        drop(clients);
+
+        assert_eq!(alice.recv().await, Some(message.clone()));
+        assert_eq!(carol.recv().await, Some(message));
+    }
+
+    #[tokio::test]
+    async fn slow_client_does_not_hold_up_broadcast() {
+        let mut clients = Clients::new();
+        let slow_addr = "127.0.0.1:1".parse().unwrap();
+        let mut slow = add_client(&mut clients, slow_addr, &[]);
+        let mut fast = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &[]);
+
+        let message = Message::text("hello");
+        for _ in 0..OUTBOX_CAPACITY {
+            clients.send_to_addr(slow_addr, message.clone()).unwrap();
+        }
+        assert_eq!(clients.broadcast(&message), vec![slow_addr]);
+
+        assert_eq!(fast.recv().await, Some(message.clone()));
+        assert_eq!(slow.len(), OUTBOX_CAPACITY);
+        assert_eq!(slow.recv().await, Some(message));
+    }
+
+    #[tokio::test]
+    async fn nickname_change_is_validated_and_broadcast() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let bob_addr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &[]);
+        let mut bob = add_client(&mut clients, bob_addr, &[]);
+        let clients = authed_clients(clients, alice_addr);
+        let rename = |name: &str| {
+            protocol::ClientMessage::ChangeNick(String::from("token"), name.to_string())
+        };
+
+        handle_client_message(
+            rename(&bob_addr.to_string()),
+            alice_addr,
+            Arc::clone(&clients),
+        )
+        .await;
+        assert!(matches!(
+            protocol::ServerMessage::try_from(&alice.recv().await.unwrap()),
+            Ok(protocol::ServerMessage::ChangeNickFailed(
+                protocol::AuthError::NicknameUnavailable
+            ))
+        ));
+
+        handle_client_message(rename("alice"), alice_addr, Arc::clone(&clients)).await;
+        for outbox in [&mut alice, &mut bob] {
+            assert!(matches!(
+                protocol::ServerMessage::try_from(&outbox.recv().await.unwrap()),
+                Ok(protocol::ServerMessage::Notification(
+                    protocol::ServerNotification::NicknameChanged(old, sender)
+                )) if old == alice_addr.to_string() && sender.name == "alice"
+            ));
+        }
+
+        // someone else's token
+        handle_client_message(rename("mallory"), bob_addr, Arc::clone(&clients)).await;
+        assert_eq!(
+            clients.lock().await.by_addr(bob_addr).unwrap().name,
+            bob_addr.to_string()
+        );
+    }
+
+    #[test]
+    fn nicknames_are_trimmed_and_validated() {
+        let mut clients = Clients::new();
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        add_client(&mut clients, addr, &[]);
+
+        for invalid in ["  ", "new\nline", "\x1b[2Jboo"] {
+            assert!(matches!(
+                clients.try_rename(addr, invalid),
+                Err(protocol::AuthError::NicknameInvalid)
+            ));
+        }
+        clients.try_rename(addr, "  alice \t").unwrap();
+        assert_eq!(clients.by_addr(addr).unwrap().name, "alice");
+    }
+
+    #[tokio::test]
+    async fn message_id_is_only_echoed_to_sender() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let mut bob = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &["lobby"]);
+        let clients = authed_clients(clients, alice_addr);
+
+        handle_client_message(
+            protocol::ClientMessage::SendMessage {
+                token: String::from("token"),
+                text: String::from("hi"),
+                image: None,
+                room: protocol::RoomId::default(),
+                id: Some(7),
+            },
+            alice_addr,
+            clients,
+        )
+        .await;
+        let id =
+            |message: Option<Message>| match protocol::ServerMessage::try_from(&message.unwrap()) {
+                Ok(protocol::ServerMessage::PropagateMessage(.., id)) => id,
+                _ => panic!("expected a chat message"),
+            };
+        assert_eq!(id(alice.recv().await), Some(7));
+        assert_eq!(id(bob.recv().await), None);
+    }
+
+    #[tokio::test]
+    async fn overlong_message_is_rejected() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let mut bob = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &["lobby"]);
+        let clients = authed_clients(clients, alice_addr);
+
+        handle_client_message(
+            protocol::ClientMessage::SendMessage {
+                token: String::from("token"),
+                text: "a".repeat(protocol::MESSAGE_MAX_LEN + 1),
+                image: None,
+                room: protocol::RoomId::default(),
+                id: Some(7),
+            },
+            alice_addr,
+            clients,
+        )
+        .await;
+        assert!(matches!(
+            protocol::ServerMessage::try_from(&alice.recv().await.unwrap()),
+            Ok(protocol::ServerMessage::Ack {
+                id: Some(7),
+                accepted: false,
+                reason,
+            }) if reason == Some(protocol::RejectReason::TooLong.to_string())
+        ));
+        assert!(bob.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn message_to_a_foreign_room_is_rejected() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let bob_addr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let mut bob = add_client(&mut clients, bob_addr, &["secret"]);
+        clients.token_map.insert(String::from("bob"), bob_addr);
+        let clients = authed_clients(clients, alice_addr);
+
+        // bob's token doesn't let alice speak for him
+        for (token, room) in [("token", "secret"), ("forged", "lobby"), ("bob", "secret")] {
+            handle_client_message(
+                protocol::ClientMessage::SendMessage {
+                    token: String::from(token),
+                    text: String::from("hi"),
+                    image: None,
+                    room: protocol::RoomId(String::from(room)),
+                    id: Some(7),
+                },
+                alice_addr,
+                Arc::clone(&clients),
+            )
+            .await;
+            assert!(matches!(
+                protocol::ServerMessage::try_from(&alice.recv().await.unwrap()),
+                Ok(protocol::ServerMessage::Ack {
+                    id: Some(7),
+                    accepted: false,
+                    reason,
+                }) if reason == Some(protocol::RejectReason::NotAllowed.to_string())
+            ));
+        }
+        assert!(bob.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn flooding_sender_is_rate_limited() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let mut bob = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &["lobby"]);
+        let clients = authed_clients(clients, alice_addr);
+
+        for id in 0..=u64::from(MESSAGE_BURST) {
+            handle_client_message(
+                protocol::ClientMessage::SendMessage {
+                    token: String::from("token"),
+                    text: String::from("spam"),
+                    image: None,
+                    room: protocol::RoomId::default(),
+                    id: Some(id),
+                },
+                alice_addr,
+                Arc::clone(&clients),
+            )
+            .await;
+        }
+        for id in 0..u64::from(MESSAGE_BURST) {
+            assert!(bob.try_recv().is_ok());
+            assert!(alice.try_recv().is_ok());
+            assert!(matches!(
+                protocol::ServerMessage::try_from(&alice.try_recv().unwrap()),
+                Ok(protocol::ServerMessage::Ack {
+                    id: Some(acked),
+                    accepted: true,
+                    reason: None,
+                }) if acked == id
+            ));
+        }
+        assert!(bob.try_recv().is_err());
+        assert!(matches!(
+            protocol::ServerMessage::try_from(&alice.try_recv().unwrap()),
+            Ok(protocol::ServerMessage::Ack {
+                id: Some(id),
+                accepted: false,
+                reason,
+            }) if id == u64::from(MESSAGE_BURST)
+                && reason == Some(protocol::RejectReason::TooFast.to_string())
+        ));
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let mut limit = RateLimit::new();
+        let start = limit.refilled_at;
+        for _ in 0..MESSAGE_BURST {
+            assert!(limit.try_take(start));
+        }
+        assert!(!limit.try_take(start));
+        assert!(!limit.try_take(start + MESSAGE_REFILL / 2));
+        assert!(limit.try_take(start + MESSAGE_REFILL));
+        assert!(!limit.try_take(start + MESSAGE_REFILL * 3 / 2));
+
+        let later = start + MESSAGE_REFILL * (MESSAGE_BURST * 10);
+        for _ in 0..MESSAGE_BURST {
+            assert!(limit.try_take(later));
+        }
+        assert!(!limit.try_take(later));
+    }
+
+    #[tokio::test]
+    async fn direct_message_reaches_only_its_target() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let mut bob = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &["lobby"]);
+        let mut carol = add_client(&mut clients, "127.0.0.1:3".parse().unwrap(), &["lobby"]);
+        let clients = authed_clients(clients, alice_addr);
+
+        for to in ["127.0.0.1:2", "nobody"] {
+            handle_client_message(
+                protocol::ClientMessage::DirectMessage {
+                    token: String::from("token"),
+                    to: String::from(to),
+                    text: String::from("psst"),
+                },
+                alice_addr,
+                Arc::clone(&clients),
+            )
+            .await;
+        }
+        let is_direct = |message: Message| {
+            matches!(
+                protocol::ServerMessage::try_from(&message),
+                Ok(protocol::ServerMessage::PropagateDirect { from, to, text })
+                    if from.name == "127.0.0.1:1" && to == "127.0.0.1:2" && text == "psst"
+            )
+        };
+        assert!(is_direct(bob.try_recv().unwrap()));
+        assert!(is_direct(alice.try_recv().unwrap()));
+        assert!(matches!(
+            protocol::ServerMessage::try_from(&alice.try_recv().unwrap()),
+            Ok(protocol::ServerMessage::Notification(
+                protocol::ServerNotification::Literal(_)
+            ))
+        ));
+        assert!(bob.try_recv().is_err());
+        assert!(carol.try_recv().is_err());
+    }
+
+    #[test]
+    fn history_is_bounded_and_filtered_by_room() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let bob_addr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        let _alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let _bob = add_client(&mut clients, bob_addr, &["lobby"]);
+        let sender = protocol::MessageSender::from(clients.by_addr(alice_addr).unwrap());
+
+        clients.propagate(
+            alice_addr,
+            sender.clone(),
+            String::from("elsewhere"),
+            None,
+            protocol::RoomId(String::from("other")),
+            None,
+        );
+        for i in 0..=HISTORY_CAPACITY {
+            clients.propagate(
+                alice_addr,
+                sender.clone(),
+                i.to_string(),
+                None,
+                protocol::RoomId::default(),
+                Some(7),
+            );
+        }
+        let texts: Vec<_> = clients
+            .history_for(bob_addr)
+            .into_iter()
+            .map(|entry| match entry.message {
+                protocol::ServerMessage::PropagateMessage(_, text, _, _, id) => {
+                    assert_eq!(id, None);
+                    text
+                }
+                _ => panic!("expected a chat message"),
+            })
+            .collect();
+        assert_eq!(texts.len(), HISTORY_CAPACITY);
+        assert_eq!(texts.first().unwrap(), "1");
+        assert_eq!(texts.last().unwrap(), &HISTORY_CAPACITY.to_string());
+    }
+
+    #[test]
+    fn history_keeps_image_sizes_only() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let _alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let sender = protocol::MessageSender::from(clients.by_addr(alice_addr).unwrap());
+
+        clients.propagate(
+            alice_addr,
+            sender,
+            String::from("look"),
+            Some(vec![0; 4096]),
+            protocol::RoomId::default(),
+            None,
+        );
+        let history = clients.history_for(alice_addr);
+        assert_eq!(history[0].image_size, Some(4096));
+        assert!(matches!(
+            &history[0].message,
+            protocol::ServerMessage::PropagateMessage(_, text, None, _, _) if text == "look"
+        ));
+    }
+
+    #[tokio::test]
+    async fn typing_is_relayed_to_everyone_else() {
+        let mut clients = Clients::new();
+        let alice_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut alice = add_client(&mut clients, alice_addr, &["lobby"]);
+        let mut bob = add_client(&mut clients, "127.0.0.1:2".parse().unwrap(), &[]);
+        let clients = authed_clients(clients, alice_addr);
+
+        handle_client_message(
+            protocol::ClientMessage::Typing(String::from("token"), true),
+            alice_addr,
+            clients,
+        )
+        .await;
+        assert!(matches!(
+            protocol::ServerMessage::try_from(&bob.try_recv().unwrap()),
+            Ok(protocol::ServerMessage::UserTyping(sender, true)) if sender.name == "127.0.0.1:1"
+        ));
+        assert!(alice.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropped_client_can_resume_within_grace() {
+        let mut clients = Clients::new();
+        let old_addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let new_addr: SocketAddr = "127.0.0.1:2".parse().unwrap();
+        add_client(&mut clients, old_addr, &["rust"]);
+        clients.token_map.insert(String::from("token"), old_addr);
+
+        clients.reserve(old_addr);
+        assert!(clients.by_addr(old_addr).is_none());
+        assert!(matches!(
+            clients.check_nickname("127.0.0.1:1"),
+            Err(protocol::AuthError::NicknameUnavailable)
+        ));
+
+        let (tx, _outbox) = mpsc::channel(1);
+        let sender = clients
+            .try_resume(
+                new_addr,
+                &String::from("token"),
+                tx.clone(),
+                CancellationToken::new(),
+            )
+            .ok()
+            .unwrap();
+        assert_eq!(sender.name, "127.0.0.1:1");
+        let client = clients.by_token(&String::from("token")).unwrap();
+        assert!(
+            client
+                .rooms
+                .contains(&protocol::RoomId(String::from("rust")))
+        );
+        assert!(matches!(
+            clients.try_resume(
+                old_addr,
+                &String::from("token"),
+                tx,
+                CancellationToken::new()
+            ),
+            Err(protocol::AuthError::UnknownToken)
+        ));
+    }
+
+    #[test]
+    fn reservation_expires() {
+        let mut clients = Clients::new();
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        add_client(&mut clients, addr, &[]);
+        clients.token_map.insert(String::from("token"), addr);
+
+        clients.reserve(addr);
+        clients.reserved.get_mut("token").unwrap().expires_at = Instant::now();
+        assert!(clients.check_nickname("127.0.0.1:1").is_ok());
+        let (tx, _outbox) = mpsc::channel(1);
+        assert!(matches!(
+            clients.try_resume(addr, &String::from("token"), tx, CancellationToken::new()),
+            Err(protocol::AuthError::UnknownToken)
+        ));
+        assert!(clients.reserved.is_empty());
+    }
+
+    #[test]
+    fn users_are_sorted_by_name() {
+        let mut clients = Clients::new();
+        for port in [3, 1, 2] {
+            add_client(&mut clients, SocketAddr::from(([127, 0, 0, 1], port)), &[]);
+        }
+        let names: Vec<_> = clients.users().into_iter().map(|user| user.name).collect();
+        assert_eq!(names, ["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"]);
+    }
+
+    #[test]
+    fn tokens_are_random() {
+        let mut clients = Clients::new();
+        let addr: SocketAddr = "127.0.0.1:1".parse().unwrap();
+        let mut tokens = HashSet::new();
+        for _ in 0..2 {
+            let (tx, _) = mpsc::channel(1);
+            let client = ClientData {
+                tx,
+                evicted: CancellationToken::new(),
+                name: String::from("alice"),
+                color: protocol::Color::default(),
+                rooms: HashSet::new(),
+                rate_limit: RateLimit::new(),
+            };
+            let token = clients.try_connect(addr, client).ok().unwrap();
+            assert_ne!(token, addr.to_string());
+            assert!(!token.contains("127.0.0.1"));
+            assert!(tokens.insert(token));
+            clients.disconnect(addr);
+        }
+    }
+
+    #[tokio::test]
+    async fn unmasked_frame_is_closed_with_protocol_error() {
+        let (server_end, mut client_end) = duplex(1024);
+        let socket = WsStream::<Client, Stream>::from_stream(Box::new(server_end))
+            .with_peer_addr("127.0.0.1:1".parse().unwrap());
+        let clients = Arc::new(Mutex::new(Clients::new()));
+        let server = tokio::spawn(on_connect(socket, Arc::clone(&clients)));
+
+        // unmasked "hi" text frame
+        client_end
+            .write_all(&[0x81, 0x02, b'h', b'i'])
+            .await
+            .unwrap();
+
+        let mut rx = WsStream::<Server, _>::from_stream(client_end).rx;
+        assert_eq!(
+            rx.receive().await.ok(),
+            Some(Message::Close(StatusCode::ProtocolError, None))
+        );
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn evicted_client_is_closed() {
+        let addr = "127.0.0.1:1".parse().unwrap();
+        let (server_end, client_end) = duplex(1024);
+        let socket =
+            WsStream::<Client, Stream>::from_stream(Box::new(server_end)).with_peer_addr(addr);
+        let clients = Arc::new(Mutex::new(Clients::new()));
+        let server = tokio::spawn(on_connect(socket, Arc::clone(&clients)));
+
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        client
+            .send(
+                protocol::ClientMessage::Auth(protocol::MessageSender {
+                    name: String::from("alice"),
+                    color: protocol::Color::default(),
+                })
+                .into(),
+            )
+            .await
+            .unwrap();
+        // AuthSuccess, then History
+        for _ in 0..2 {
+            client.receive().await.unwrap();
+        }
+
+        clients.lock().await.disconnect_failed(vec![addr]);
+        server.await.unwrap().unwrap();
+        assert_eq!(
+            client.receive().await.ok(),
+            Some(Message::Close(StatusCode::TryAgainLater, None))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_text_and_oversized_frames_close_with_their_codes() {
+        for (frame, code) in [
+            // masked with a zero key, text "a\xff"
+            (
+                &[0x81, 0x82, 0, 0, 0, 0, b'a', 0xff][..],
+                StatusCode::InvalidPayloadData,
+            ),
+            // masked binary advertising 1 TiB
+            (
+                &[0x82, 0xff, 0, 0, 0x01, 0, 0, 0, 0, 0][..],
+                StatusCode::MessageTooBig,
+            ),
+        ] {
+            let (server_end, mut client_end) = duplex(1024);
+            let socket = WsStream::<Client, Stream>::from_stream(Box::new(server_end))
+                .with_peer_addr("127.0.0.1:1".parse().unwrap());
+            let server = tokio::spawn(on_connect(socket, Arc::new(Mutex::new(Clients::new()))));
+
+            client_end.write_all(frame).await.unwrap();
+            let mut rx = WsStream::<Server, _>::from_stream(client_end).rx;
+            assert_eq!(rx.receive().await.ok(), Some(Message::Close(code, None)));
+            server.await.unwrap().unwrap();
+        }
+    }
+
+    /// Performs a handshake offering `protocol`, returning the negotiated
+    /// subprotocol and the raw response.
+    async fn upgrade_offering(protocol: &str) -> (Option<String>, String) {
+        let (server_end, mut client_end) = duplex(1024);
+        let mut socket = WsStream::<Client, _>::from_stream(server_end);
+        client_end
+            .write_all(
+                format!(
+                    "\
+GET / HTTP/1.1\r
+Host: localhost:1337\r
+Upgrade: websocket\r
+Connection: upgrade\r
+Sec-Websocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r
+Sec-Websocket-Version: 13\r
+Sec-Websocket-Protocol: {protocol}\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let negotiated = socket
+            .try_upgrade_with("localhost:1337", select_subprotocol)
+            .await
+            .unwrap()
+            .subprotocol;
+        let mut response = vec![0u8; 1024];
+        let n = client_end.read(&mut response).await.unwrap();
+        (
+            negotiated,
+            String::from_utf8_lossy(&response[..n]).into_owned(),
+        )
+    }
+
+    #[tokio::test]
+    async fn echoes_known_subprotocol() {
+        let (negotiated, response) = upgrade_offering("tungsto.v1").await;
+        assert_eq!(negotiated.as_deref(), Some("tungsto.v1"));
+        assert!(response.contains("Sec-Websocket-Protocol: tungsto.v1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_subprotocol() {
+        let (negotiated, response) = upgrade_offering("bogus.v0").await;
+        assert_eq!(negotiated, None);
+        assert!(!response.contains("Sec-Websocket-Protocol"));
+    }
+}
@@ -0,0 +1,161 @@
+//! Spins up a real server on an ephemeral port and drives plain-TCP clients against it.
+#![allow(dead_code)]
+
+use core::net::SocketAddr;
+
+use common::protocol;
+use server::Stream;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use websocket::{
+    Server, WsConfig, WsRecv, WsSend, WsStream,
+    message::{Message, StatusCode},
+};
+
+/// A server running in the background for the duration of a test, without TLS.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    /// Shuts the server down gracefully once cancelled.
+    pub shutdown: CancellationToken,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl TestServer {
+    pub async fn spawn() -> Self {
+        Self::spawn_with_max_clients(server::MAX_CLIENTS).await
+    }
+
+    pub async fn spawn_with_max_clients(max_clients: usize) -> Self {
+        Self::spawn_with(max_clients, None, WsConfig::default()).await
+    }
+
+    /// Only accepts browsers connecting from `origins`.
+    pub async fn spawn_with_origins(origins: &[&str]) -> Self {
+        let origins = origins.iter().map(ToString::to_string).collect();
+        Self::spawn_with(server::MAX_CLIENTS, Some(origins), WsConfig::default()).await
+    }
+
+    /// Gives every connection `config`, e.g. to shorten its timeouts.
+    pub async fn spawn_with_config(max_clients: usize, config: WsConfig) -> Self {
+        Self::spawn_with(max_clients, None, config).await
+    }
+
+    async fn spawn_with(
+        max_clients: usize,
+        origins: Option<Vec<String>>,
+        config: WsConfig,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(server::serve(
+            listener,
+            vec![addr.to_string()],
+            origins,
+            |socket| async move { Ok(Box::new(socket) as Stream) },
+            shutdown.clone(),
+            max_clients,
+            config,
+        ));
+        Self {
+            addr,
+            shutdown,
+            task,
+        }
+    }
+
+    /// Connects, upgrades and authenticates a client named `name`.
+    pub async fn connect(&self, name: &str) -> TestClient {
+        let mut client = TestClient::upgrade(self.addr).await;
+        client.authenticate(name).await;
+        client
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub struct TestClient {
+    pub ws: WsStream<Server, TcpStream>,
+    pub token: Option<protocol::Token>,
+}
+
+impl TestClient {
+    /// Connects and performs the opening handshake, without authenticating.
+    pub async fn upgrade(addr: SocketAddr) -> Self {
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let mut ws = WsStream::<Server, _>::from_stream(socket);
+        let info = ws
+            .try_upgrade_with(&addr.to_string(), &[protocol::SUBPROTOCOL])
+            .await
+            .unwrap();
+        assert_eq!(info.subprotocol.as_deref(), Some(protocol::SUBPROTOCOL));
+        Self { ws, token: None }
+    }
+
+    pub async fn authenticate(&mut self, name: &str) {
+        self.send(protocol::ClientMessage::Auth(protocol::MessageSender {
+            name: name.to_string(),
+            color: protocol::Color::default(),
+        }))
+        .await;
+        match self.recv().await {
+            protocol::ServerMessage::AuthSuccess(Ok(token)) => self.token = Some(token),
+            other => panic!("{name} failed to authenticate: {other:?}"),
+        }
+    }
+
+    pub async fn send(&mut self, message: protocol::ClientMessage) {
+        self.ws.send(message.into()).await.unwrap();
+    }
+
+    /// Sends `text` to `room` on behalf of this client.
+    pub async fn say(&mut self, room: &str, text: &str) {
+        let token = self.token.clone().expect("not authenticated");
+        self.send(protocol::ClientMessage::SendMessage {
+            token,
+            text: text.to_string(),
+            image: None,
+            room: protocol::RoomId(room.to_string()),
+            id: None,
+        })
+        .await;
+    }
+
+    /// Receives the next raw WebSocket message.
+    pub async fn recv_raw(&mut self) -> Message {
+        self.ws
+            .receive()
+            .await
+            .unwrap_or_else(|_| panic!("connection dropped"))
+    }
+
+    /// Receives the next message, expecting it to be a [`protocol::ServerMessage`].
+    pub async fn recv(&mut self) -> protocol::ServerMessage {
+        let message = self.recv_raw().await;
+        protocol::ServerMessage::try_from(&message)
+            .unwrap_or_else(|()| panic!("not a server message: {message:?}"))
+    }
+
+    /// Skips notifications until a chat message arrives, returning its sender, text and room.
+    pub async fn recv_chat(&mut self) -> (String, String, protocol::RoomId) {
+        loop {
+            if let protocol::ServerMessage::PropagateMessage(sender, text, _, room, _) =
+                self.recv().await
+            {
+                return (sender.name, text, room);
+            }
+        }
+    }
+
+    /// Performs the closing handshake, returning the status code the server echoed.
+    pub async fn close(&mut self) -> Option<StatusCode> {
+        self.ws.close(None, None).await.unwrap()
+    }
+}
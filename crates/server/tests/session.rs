@@ -0,0 +1,187 @@
+mod harness;
+
+use std::time::Duration;
+
+use common::protocol;
+use harness::{TestClient, TestServer};
+use rand::Rng;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+use websocket::{
+    Server, WsConfig, WsStream,
+    handshake::UpgradeRejected,
+    message::{Message, StatusCode},
+};
+
+#[tokio::test]
+async fn message_reaches_everyone_and_closes_cleanly() {
+    let server = TestServer::spawn().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+
+    alice.say("lobby", "hi bob").await;
+
+    for client in [&mut alice, &mut bob] {
+        let (sender, text, room) = client.recv_chat().await;
+        assert_eq!(sender, "alice");
+        assert_eq!(text, "hi bob");
+        assert_eq!(room.0, "lobby");
+    }
+
+    assert_eq!(alice.close().await, Some(StatusCode::Normal));
+    assert_eq!(bob.close().await, Some(StatusCode::Normal));
+}
+
+#[tokio::test]
+async fn multi_megabyte_images_arrive_intact() {
+    let server = TestServer::spawn().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+    let mut image = vec![0u8; 2 * 1024 * 1024];
+    rand::rng().fill(&mut image[..]);
+
+    alice
+        .send(protocol::ClientMessage::SendMessage {
+            token: alice.token.clone().unwrap(),
+            text: String::from("look"),
+            image: Some(image.clone()),
+            room: protocol::RoomId(String::from("lobby")),
+            id: None,
+        })
+        .await;
+    loop {
+        if let protocol::ServerMessage::PropagateMessage(_, text, Some(received), _, _) =
+            bob.recv().await
+        {
+            assert_eq!(text, "look");
+            assert!(received == image);
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn shutdown_says_goodbye_to_everyone() {
+    let server = TestServer::spawn().await;
+    let mut alice = server.connect("alice").await;
+    let mut bob = server.connect("bob").await;
+
+    server.shutdown.cancel();
+    for client in [&mut alice, &mut bob] {
+        let mut notified = false;
+        let code = loop {
+            match client.recv_raw().await {
+                Message::Close(code, _) => break code,
+                message => {
+                    notified |= matches!(
+                        protocol::ServerMessage::try_from(&message),
+                        Ok(protocol::ServerMessage::Notification(
+                            protocol::ServerNotification::Literal(_)
+                        ))
+                    );
+                }
+            }
+        };
+        assert!(notified);
+        assert_eq!(code, StatusCode::GoingAway);
+    }
+}
+
+#[tokio::test]
+async fn connections_past_the_limit_are_refused() {
+    let server = TestServer::spawn_with_max_clients(1).await;
+    let mut alice = server.connect("alice").await;
+
+    let socket = TcpStream::connect(server.addr).await.unwrap();
+    let mut refused = WsStream::<Server, _>::from_stream(socket);
+    assert!(
+        refused
+            .try_upgrade_with(&server.addr.to_string(), &[protocol::SUBPROTOCOL])
+            .await
+            .is_err()
+    );
+
+    assert_eq!(alice.close().await, Some(StatusCode::Normal));
+}
+
+#[tokio::test]
+async fn only_allowed_origins_may_connect() {
+    let server = TestServer::spawn_with_origins(&["https://chat.example"]).await;
+    let upgrade = async |origin: Option<&str>| {
+        let socket = TcpStream::connect(server.addr).await.unwrap();
+        let mut ws = WsStream::<Server, _>::from_stream(socket);
+        let extra: Vec<_> = origin
+            .map(|o| ("Origin".to_string(), o.to_string()))
+            .into_iter()
+            .collect();
+        ws.try_upgrade_with_headers(&server.addr.to_string(), &extra)
+            .await
+    };
+
+    assert!(upgrade(Some("https://chat.example")).await.is_ok());
+    // not a browser, so not something a cross-site page could open
+    assert!(upgrade(None).await.is_ok());
+
+    let error = upgrade(Some("https://evil.example")).await.unwrap_err();
+    let rejected = error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<UpgradeRejected>())
+        .unwrap();
+    assert_eq!(rejected.status, 403);
+}
+
+#[tokio::test]
+async fn silent_connections_are_dropped_after_the_configured_handshake_timeout() {
+    let config = WsConfig::default().with_handshake_timeout(Duration::from_millis(100));
+    let server = TestServer::spawn_with_config(1, config).await;
+
+    let mut silent = TcpStream::connect(server.addr).await.unwrap();
+    // well under the default timeout
+    let read = tokio::time::timeout(Duration::from_secs(2), silent.read(&mut [0; 1])).await;
+    assert_eq!(read.unwrap().unwrap(), 0);
+
+    // and its slot is free again
+    TestClient::upgrade(server.addr).await;
+}
+
+#[tokio::test]
+async fn peers_silent_before_authenticating_are_dropped_by_the_keepalive() {
+    let config =
+        WsConfig::default().with_keepalive(Duration::from_millis(50), Duration::from_millis(50));
+    let server = TestServer::spawn_with_config(1, config).await;
+
+    // never reads, so never answers a ping
+    let _silent = TestClient::upgrade(server.addr).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    TestClient::upgrade(server.addr).await;
+}
+
+#[tokio::test]
+async fn idle_unauthenticated_connections_give_their_slot_back() {
+    const SLOTS: usize = 3;
+    let config = WsConfig::default().with_handshake_timeout(Duration::from_millis(200));
+    let server = TestServer::spawn_with_config(SLOTS, config).await;
+
+    let mut idle = Vec::new();
+    for _ in 0..SLOTS {
+        idle.push(TestClient::upgrade(server.addr).await);
+    }
+    let socket = TcpStream::connect(server.addr).await.unwrap();
+    let mut refused = WsStream::<Server, _>::from_stream(socket);
+    assert!(
+        refused
+            .try_upgrade_with(&server.addr.to_string(), &[protocol::SUBPROTOCOL])
+            .await
+            .is_err()
+    );
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let mut alice = server.connect("alice").await;
+    for client in &mut idle {
+        assert_eq!(
+            client.recv_raw().await,
+            Message::Close(StatusCode::PolicyViolated, None)
+        );
+    }
+    assert_eq!(alice.close().await, Some(StatusCode::Normal));
+}
@@ -0,0 +1,435 @@
+//! `permessage-deflate` (RFC 7692), behind the `deflate` feature.
+//!
+//! Negotiated automatically during the handshake: clients always offer it, servers accept
+//! the first offer they can honor. Otherwise, messages go uncompressed as usual.
+//! Only the default 15-bit window is supported, so offers limiting the server's window
+//! are declined.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::{
+    Client, Server, UnpinStream, WsStream,
+    frame::{Frame, Opcode},
+    message::{MessageError, StatusCode},
+};
+
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+/// RSV1, set on the first frame of every compressed message.
+pub(crate) const RSV_COMPRESSED: u8 = 0b100;
+
+/// Deflate blocks flushed with `Z_SYNC_FLUSH` end with these, which are left off the wire.
+const SYNC_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// Parameters of a negotiated `permessage-deflate` extension.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeflateParams {
+    /// The client resets its compressor after every message.
+    pub client_no_context_takeover: bool,
+    /// The server resets its compressor after every message.
+    pub server_no_context_takeover: bool,
+}
+
+impl DeflateParams {
+    /// Parses a single `permessage-deflate` offer as a server, i.e. a value of
+    /// `Sec-WebSocket-Extensions` between commas. Returns `None` for other extensions,
+    /// unknown or duplicate parameters, and window sizes we can't honor.
+    fn from_offer(offer: &str) -> Option<Self> {
+        let mut params = Self::default();
+        let mut seen: Vec<&str> = Vec::new();
+        for (name, value) in parse_extension(offer)? {
+            if seen.contains(&name) {
+                return None;
+            }
+            seen.push(name);
+            match (name, value) {
+                ("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+                ("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+                // we inflate with the max window anyway, so any limit on the client is fine
+                ("client_max_window_bits", _) | ("server_max_window_bits", Some("15")) => {}
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    /// Parses the extension accepted in a server response, as a client that offered
+    /// [`EXTENSION_NAME`] without parameters.
+    fn from_response(response: &str) -> Option<Self> {
+        let mut params = Self::default();
+        for (name, value) in parse_extension(response)? {
+            match (name, value) {
+                ("client_no_context_takeover", None) => params.client_no_context_takeover = true,
+                ("server_no_context_takeover", None) => params.server_no_context_takeover = true,
+                // a smaller window on the server still inflates fine with the max one
+                ("server_max_window_bits", Some(_)) => {}
+                _ => return None,
+            }
+        }
+        Some(params)
+    }
+
+    fn header_value(self) -> String {
+        let mut value = String::from(EXTENSION_NAME);
+        if self.client_no_context_takeover {
+            value.push_str("; client_no_context_takeover");
+        }
+        if self.server_no_context_takeover {
+            value.push_str("; server_no_context_takeover");
+        }
+        value
+    }
+}
+
+/// Splits `permessage-deflate; a; b=1` into its parameters, if the name matches.
+fn parse_extension(extension: &str) -> Option<Vec<(&str, Option<&str>)>> {
+    let mut parts = extension.split(';').map(str::trim);
+    if !parts.next()?.eq_ignore_ascii_case(EXTENSION_NAME) {
+        return None;
+    }
+    Some(
+        parts
+            .map(|param| match param.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (param, None),
+            })
+            .collect(),
+    )
+}
+
+/// Extensions listed in all `Sec-Websocket-Extensions` headers, in the order offered.
+fn offered_extensions(head: &str) -> impl Iterator<Item = &str> {
+    head.lines()
+        .filter(|l| {
+            l.to_ascii_lowercase()
+                .starts_with("sec-websocket-extensions:")
+        })
+        .filter_map(|l| l.split_once(':').map(|(_, value)| value))
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+}
+
+/// The header a client offers the extension with.
+pub(crate) fn offer_header() -> String {
+    format!("Sec-Websocket-Extensions: {EXTENSION_NAME}\r\n")
+}
+
+/// Picks the first offer in `request` we can honor, see [`DeflateParams::from_offer`].
+pub(crate) fn select_offer(request: &str) -> Option<DeflateParams> {
+    offered_extensions(request).find_map(DeflateParams::from_offer)
+}
+
+/// The header a server accepts `params` with.
+pub(crate) fn response_header(params: DeflateParams) -> String {
+    format!("Sec-Websocket-Extensions: {}\r\n", params.header_value())
+}
+
+/// Reads what the server accepted, if anything.
+///
+/// # Errors
+/// If the response lists more than one extension, or one we didn't offer.
+pub(crate) fn accepted_params(response: &str) -> std::io::Result<Option<DeflateParams>> {
+    let mut accepted = offered_extensions(response);
+    let Some(extension) = accepted.next() else {
+        return Ok(None);
+    };
+    if accepted.next().is_some() {
+        return Err(std::io::ErrorKind::InvalidData.into());
+    }
+    DeflateParams::from_response(extension)
+        .map(Some)
+        .ok_or(std::io::ErrorKind::InvalidData.into())
+}
+
+/// Compresses outgoing messages.
+#[derive(Debug)]
+pub(crate) struct Deflater {
+    compress: Compress,
+    no_context_takeover: bool,
+}
+
+impl Deflater {
+    pub(crate) fn new(no_context_takeover: bool) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            no_context_takeover,
+        }
+    }
+
+    /// Compresses the payload of a *Text* or *Binary* `frame` in place, setting RSV1.
+    /// Control frames are left alone.
+    pub(crate) fn deflate_frame(&mut self, frame: &mut Frame) -> std::io::Result<()> {
+        if frame.header.opcode.is_control() {
+            return Ok(());
+        }
+        frame.payload = self.deflate(&frame.payload)?;
+        frame.header.payload_len = (frame.payload.len() as u64).into();
+        frame.header.rsv |= RSV_COMPRESSED;
+        Ok(())
+    }
+
+    fn deflate(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(data.len() / 2 + 16);
+        let start = self.compress.total_in();
+        loop {
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            #[allow(clippy::cast_possible_truncation)] // never more than `data.len()`
+            let consumed = (self.compress.total_in() - start) as usize;
+            self.compress
+                .compress_vec(&data[consumed..], &mut output, FlushCompress::Sync)
+                .map_err(std::io::Error::other)?;
+            // done once the flush didn't run out of room
+            if self.compress.total_in() - start == data.len() as u64
+                && output.len() < output.capacity()
+            {
+                break;
+            }
+        }
+        if output.ends_with(&SYNC_TAIL) {
+            output.truncate(output.len() - SYNC_TAIL.len());
+        }
+        if self.no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+}
+
+/// Decompresses incoming messages.
+#[derive(Debug)]
+pub(crate) struct Inflater {
+    decompress: Decompress,
+    no_context_takeover: bool,
+}
+
+impl Inflater {
+    pub(crate) fn new(no_context_takeover: bool) -> Self {
+        Self {
+            decompress: Decompress::new(false),
+            no_context_takeover,
+        }
+    }
+
+    /// Decompresses the payload of a whole message in place if it has RSV1 set.
+    /// Fails if it would inflate past `max_size`.
+    pub(crate) fn inflate_frame(
+        &mut self,
+        frame: &mut Frame,
+        max_size: usize,
+    ) -> Result<(), MessageError> {
+        if frame.header.rsv & RSV_COMPRESSED == 0 {
+            return Ok(());
+        }
+        if !matches!(frame.header.opcode, Opcode::Text | Opcode::Binary) {
+            return Err(MessageError::ProtocolViolated(StatusCode::ProtocolError));
+        }
+        frame.payload = self.inflate(&frame.payload, max_size)?;
+        frame.header.payload_len = (frame.payload.len() as u64).into();
+        frame.header.rsv &= !RSV_COMPRESSED;
+        Ok(())
+    }
+
+    fn inflate(&mut self, data: &[u8], max_size: usize) -> Result<Vec<u8>, MessageError> {
+        let input = [data, &SYNC_TAIL].concat();
+        let mut output = Vec::with_capacity((data.len() * 2).min(max_size) + 16);
+        let start = self.decompress.total_in();
+        loop {
+            if output.len() > max_size {
+                return Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig));
+            }
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+            #[allow(clippy::cast_possible_truncation)] // never more than `input.len()`
+            let consumed = (self.decompress.total_in() - start) as usize;
+            let written = output.len();
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+                .map_err(|_| MessageError::ProtocolViolated(StatusCode::ProtocolError))?;
+            let done = self.decompress.total_in() - start == input.len() as u64;
+            if done && output.len() < output.capacity() {
+                break;
+            }
+            let stuck = status == Status::BufError
+                && written == output.len()
+                && consumed as u64 == self.decompress.total_in() - start;
+            if stuck {
+                return Err(MessageError::ProtocolViolated(StatusCode::ProtocolError));
+            }
+        }
+        if output.len() > max_size {
+            return Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig));
+        }
+        if self.no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
+
+impl<T: UnpinStream> WsStream<Server, T> {
+    /// Starts compressing as the client side of a connection that agreed on `params`.
+    pub(crate) fn enable_deflate(&mut self, params: DeflateParams) {
+        self.tx.2.deflater = Some(Deflater::new(params.client_no_context_takeover));
+        self.rx.3.inflater = Some(Inflater::new(params.server_no_context_takeover));
+    }
+}
+
+impl<T: UnpinStream> WsStream<Client, T> {
+    /// Starts compressing as the server side of a connection that agreed on `params`.
+    pub(crate) fn enable_deflate(&mut self, params: DeflateParams) {
+        self.tx.2.deflater = Some(Deflater::new(params.server_no_context_takeover));
+        self.rx.3.inflater = Some(Inflater::new(params.client_no_context_takeover));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+    use super::{DeflateParams, Deflater, Inflater, RSV_COMPRESSED, select_offer};
+    use crate::{
+        Client, Server, WsConfig, WsRecv, WsSend, WsStream,
+        frame::{Frame, FrameHeader},
+        message::Message,
+    };
+
+    #[test]
+    fn round_trip_keeps_context_unless_told_otherwise() {
+        let text = "hello hello hello hello hello".repeat(10);
+        for no_context_takeover in [false, true] {
+            let mut deflater = Deflater::new(no_context_takeover);
+            let mut inflater = Inflater::new(no_context_takeover);
+            let first = deflater.deflate(text.as_bytes()).unwrap();
+            let second = deflater.deflate(text.as_bytes()).unwrap();
+            assert!(first.len() < text.len());
+            // the second message refers back to the first one when context is kept
+            assert_eq!(second.len() < first.len(), !no_context_takeover);
+
+            assert_eq!(
+                inflater.inflate(&first, usize::MAX).ok().as_deref(),
+                Some(text.as_bytes())
+            );
+            assert_eq!(
+                inflater.inflate(&second, usize::MAX).ok().as_deref(),
+                Some(text.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn inflating_past_the_limit_fails() {
+        let data = vec![0u8; 64 * 1024];
+        let compressed = Deflater::new(false).deflate(&data).unwrap();
+        assert!(Inflater::new(false).inflate(&compressed, 1024).is_err());
+    }
+
+    #[test]
+    fn first_acceptable_offer_is_selected() {
+        assert_eq!(select_offer("Host: localhost\r\n"), None);
+        assert_eq!(
+            select_offer(
+                "Sec-WebSocket-Extensions: permessage-deflate; server_max_window_bits=10, \
+                 permessage-deflate; client_no_context_takeover; client_max_window_bits\r\n"
+            ),
+            Some(DeflateParams {
+                client_no_context_takeover: true,
+                server_no_context_takeover: false,
+            })
+        );
+        assert_eq!(
+            select_offer("Sec-WebSocket-Extensions: x-webkit-deflate-frame\r\n"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn negotiated_messages_are_compressed() {
+        let (client_end, server_end) = duplex(64 * 1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        let (c, s) = tokio::join!(
+            client.try_upgrade_with("localhost", &[]),
+            server.try_upgrade_with("localhost", |_| false),
+        );
+        c.unwrap();
+        s.unwrap();
+
+        let text = "compress me ".repeat(100);
+        client.send(Message::Text(text.clone())).await.unwrap();
+        let raw = server.read_frame_bytes().await.unwrap();
+        let header = FrameHeader::parse(&raw[..2], RSV_COMPRESSED).unwrap();
+        assert_eq!(header.rsv, RSV_COMPRESSED);
+        assert!(raw.len() < text.len());
+
+        // replay the raw frame and let the receiving side inflate it
+        let (mut replay, replay_end) = duplex(64 * 1024);
+        let mut receiver = WsStream::<Client, _>::from_stream(replay_end);
+        receiver.enable_deflate(DeflateParams::default());
+        replay.write_all(&raw).await.unwrap();
+        assert_eq!(receiver.receive().await.ok(), Some(Message::Text(text)));
+
+        server.send(Message::Ping(vec![1])).await.unwrap();
+        assert_eq!(client.receive().await.ok(), Some(Message::Ping(vec![1])));
+    }
+
+    #[tokio::test]
+    async fn compression_can_be_turned_off_on_either_end() {
+        for (client_compresses, server_compresses) in [(false, true), (true, false)] {
+            let (client_end, server_end) = duplex(64 * 1024);
+            let mut client = WsStream::<Server, _>::from_stream_with_config(
+                client_end,
+                WsConfig::default().with_compression(client_compresses),
+            );
+            let mut server = WsStream::<Client, _>::from_stream_with_config(
+                server_end,
+                WsConfig::default().with_compression(server_compresses),
+            );
+            let (c, s) = tokio::join!(
+                client.try_upgrade_with("localhost", &[]),
+                server.try_upgrade_with("localhost", |_| false),
+            );
+            assert_eq!(c.unwrap().header("sec-websocket-extensions"), None);
+            s.unwrap();
+
+            let text = "compress me ".repeat(100);
+            client.send(Message::Text(text.clone())).await.unwrap();
+            let raw = server.read_frame_bytes().await.unwrap();
+            assert!(raw.len() > text.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_without_an_offer_are_answered_without_extensions() {
+        let (mut client_end, server_end) = duplex(4096);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        client_end
+            .write_all(
+                b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: upgrade\r\nSec-Websocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-Websocket-Version: 13\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        server
+            .try_upgrade_with("localhost", |_| false)
+            .await
+            .unwrap();
+
+        let mut response = vec![0u8; 1024];
+        let n = client_end.read(&mut response).await.unwrap();
+        let response = String::from_utf8_lossy(&response[..n]).to_ascii_lowercase();
+        assert!(!response.contains("sec-websocket-extensions"));
+
+        // and frames go out as they are
+        server.send(Message::text("plain")).await.unwrap();
+        let mut frame = vec![0u8; 64];
+        let n = client_end.read(&mut frame).await.unwrap();
+        let frame = Frame::parse(&frame[..n], 0).unwrap();
+        assert_eq!(frame.header.rsv, 0);
+    }
+}
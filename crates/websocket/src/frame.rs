@@ -1,5 +1,9 @@
+use std::fmt::Write;
+
 use rand::RngCore;
 
+use crate::message::StatusCode;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Opcode {
     Continue = 0,
@@ -13,6 +17,14 @@ pub enum Opcode {
 
 pub struct InvalidOpcode;
 
+impl Opcode {
+    /// Whether this is a *Close*, *Ping* or *Pong* opcode, limited to 125 bytes of payload.
+    #[must_use]
+    pub fn is_control(self) -> bool {
+        self as u8 & 0b1000 != 0
+    }
+}
+
 impl TryFrom<u8> for Opcode {
     type Error = InvalidOpcode;
     fn try_from(value: u8) -> Result<Self, Self::Error> {
@@ -28,6 +40,12 @@ impl TryFrom<u8> for Opcode {
     }
 }
 
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?} ({:#x})", *self as u8)
+    }
+}
+
 /// A length of [Frame] payload. Due to the header being possibly partially parsed,
 /// can hold not only actual len, but also hints to parse next u16 or u64.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -62,11 +80,36 @@ impl From<u64> for PayloadLen {
     }
 }
 
+impl PayloadLen {
+    /// Whether the length is encoded in the smallest form that fits it, as senders must.
+    /// Hints are considered minimal, having no length to check yet.
+    #[must_use]
+    pub fn is_minimal(self) -> bool {
+        match self {
+            Self::ExactU16(n) => n > 125,
+            Self::ExactU64(n) => n > u16::MAX.into(),
+            Self::ExactU8(_) | Self::HintU16 | Self::HintU64 => true,
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadLen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ExactU8(n) => write!(f, "{n} bytes"),
+            Self::ExactU16(n) => write!(f, "{n} bytes (16-bit)"),
+            Self::ExactU64(n) => write!(f, "{n} bytes (64-bit)"),
+            Self::HintU16 => f.write_str("16-bit length follows"),
+            Self::HintU64 => f.write_str("64-bit length follows"),
+        }
+    }
+}
+
 /// [Frame] header that can be parsed from the first 2 bytes of it.
 /// If the length is 126 or 127, respective [`PayloadLen`] hint will be assigned.
 /// Enough bytes in the slice will convert to instance with exact length of the smallest possible
 /// unsigned int size.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameHeader {
     pub fin: bool,
     /// Only 3 rightmost bits count: RSV1 RSV2 RSV3 in BE order.
@@ -80,7 +123,7 @@ pub struct FrameHeader {
 }
 
 /// WebSocket Frame consisting of a [`FrameHeader`], a payload, and an optional masking key.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Frame {
     pub header: FrameHeader,
     pub masking_key: Option<u32>,
@@ -116,22 +159,114 @@ impl Frame {
         }
     }
 
-    /// Masks the payload.
-    /// The operation is *involutory*, meaning that unmasking is done
-    /// through this method as well.
-    /// # Panics
-    /// Panics if `masking_key` is *None*.
-    pub fn mask(&mut self) {
-        let key = self.masking_key.unwrap();
+    /// Creates a new *Ping* [Frame], truncating the payload to 125 bytes.
+    #[must_use]
+    pub fn ping(mut payload: Vec<u8>) -> Self {
+        payload.truncate(125);
+        Frame::new(true, Opcode::Ping, payload)
+    }
+
+    /// Creates a new *Pong* [Frame], truncating the payload to 125 bytes.
+    #[must_use]
+    pub fn pong(mut payload: Vec<u8>) -> Self {
+        payload.truncate(125);
+        Frame::new(true, Opcode::Pong, payload)
+    }
+
+    /// Creates a new *Close* [Frame], truncating the reason to at most 123 bytes
+    /// without splitting a character.
+    #[must_use]
+    pub fn close(code: StatusCode, reason: Option<String>) -> Self {
+        let mut reason = reason.unwrap_or_default();
+        reason.truncate(floor_char_boundary(&reason, 123));
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&u16::from(code).to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        Frame::new(true, Opcode::Close, payload)
+    }
+
+    /// Splits a final data frame into fragments of up to `chunk_size` bytes of payload:
+    /// the first keeps the opcode and RSV bits, the rest are *Continue* frames, and only
+    /// the last one has FIN set. Each fragment gets its own masking key.
+    ///
+    /// Control frames, already fragmented frames and a `chunk_size` of 0 are returned as is.
+    #[must_use]
+    pub fn fragment(self, chunk_size: usize) -> Vec<Frame> {
+        if self.header.opcode.is_control()
+            || !self.header.fin
+            || chunk_size == 0
+            || self.payload.len() <= chunk_size
+        {
+            return vec![self];
+        }
+        let chunks = self.payload.chunks(chunk_size);
+        let count = chunks.len();
+        chunks
+            .enumerate()
+            .map(|(i, chunk)| {
+                let opcode = if i == 0 {
+                    self.header.opcode
+                } else {
+                    Opcode::Continue
+                };
+                let mut fragment = Frame::new(i + 1 == count, opcode, chunk.to_vec());
+                fragment.header.masked = self.header.masked;
+                if i == 0 {
+                    fragment.header.rsv = self.header.rsv;
+                }
+                fragment
+            })
+            .collect()
+    }
 
-        for (index, byte) in self.payload.iter_mut().enumerate() {
-            *byte ^= key.to_be_bytes()[index % 4];
+    /// Masks the payload with `masking_key`, doing nothing if there is none.
+    /// The operation is *involutory*, so applying it twice gets the original payload back,
+    /// but see [`Frame::unmask`] for received frames.
+    pub fn mask(&mut self) {
+        if let Some(key) = self.masking_key {
+            apply_mask(&mut self.payload, key.to_be_bytes());
         }
     }
+
+    /// Unmasks the payload of a received frame, then drops the key and the MASK bit,
+    /// leaving a frame that reads as if it was sent unmasked.
+    /// Does nothing if there is no `masking_key`.
+    pub fn unmask(&mut self) {
+        self.mask();
+        self.header.masked = false;
+        self.masking_key = None;
+    }
+}
+
+/// The largest character boundary in `s` that is not past `index`.
+pub(crate) fn floor_char_boundary(s: &str, index: usize) -> usize {
+    s.char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&end| end <= index)
+        .last()
+        .unwrap_or(0)
+}
+
+/// XORs `payload` with `key` repeated over it, 8 bytes at a time.
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    let [a, b, c, d] = key;
+    let wide_key = u64::from_ne_bytes([a, b, c, d, a, b, c, d]);
+    let mut chunks = payload.chunks_exact_mut(8);
+    for chunk in &mut chunks {
+        let masked = u64::from_ne_bytes((&*chunk).try_into().unwrap()) ^ wide_key;
+        chunk.copy_from_slice(&masked.to_ne_bytes());
+    }
+    // the tail starts at a multiple of 8, so the key starts over too
+    for (byte, k) in chunks.into_remainder().iter_mut().zip(key.iter().cycle()) {
+        *byte ^= k;
+    }
 }
 
 impl From<FrameHeader> for Vec<u8> {
     fn from(value: FrameHeader) -> Self {
+        // length bits announcing 2 or 8 extended length bytes
+        const U16_HINT: u8 = 126;
+        const U64_HINT: u8 = 127;
         let mut result = Vec::with_capacity(2 + if value.masked { 4 } else { 0 });
 
         let first_bit =
@@ -145,21 +280,21 @@ impl From<FrameHeader> for Vec<u8> {
                 result.push(second_bit);
             }
             PayloadLen::ExactU16(len) => {
-                second_bit |= 126;
+                second_bit |= U16_HINT;
                 result.push(second_bit);
                 result.extend_from_slice(&((len).to_be_bytes()));
             }
             PayloadLen::ExactU64(len) => {
-                second_bit |= 127;
+                second_bit |= U64_HINT;
                 result.push(second_bit);
                 result.extend_from_slice(&len.to_be_bytes());
             }
             PayloadLen::HintU16 => {
-                second_bit |= 126;
+                second_bit |= U16_HINT;
                 result.push(second_bit);
             }
             PayloadLen::HintU64 => {
-                second_bit |= 127;
+                second_bit |= U64_HINT;
                 result.push(second_bit);
             }
         }
@@ -176,12 +311,55 @@ pub enum FrameError {
     LengthParsing,
     MaskingKeyParsing,
     PayloadTooShort,
+    /// A 64-bit payload length has its most significant bit set (RFC 6455, section 5.2).
+    LengthHighBitSet,
+    /// A payload length could've been encoded in a smaller form, see [`FrameHeader::parse_strict`].
+    NonMinimalLength,
+    /// An RSV bit is set without an extension defining it.
+    ReservedBitsSet,
+    /// A control frame uses the 16 or 64-bit length form.
+    ControlFrameTooLong,
+    /// A control frame has FIN unset. Only data frames can be fragmented.
+    FragmentedControlFrame,
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::FrameTooShort => "frame is shorter than its header",
+            Self::InvalidOpcode => "unknown opcode",
+            Self::LengthParsing => "payload length is cut short",
+            Self::MaskingKeyParsing => "masking key is cut short",
+            Self::PayloadTooShort => "payload is shorter than its length",
+            Self::LengthHighBitSet => "64-bit payload length has its most significant bit set",
+            Self::NonMinimalLength => "payload length isn't in its shortest form",
+            Self::ReservedBitsSet => "reserved bits set without a negotiated extension",
+            Self::ControlFrameTooLong => "control frame exceeds 125 bytes",
+            Self::FragmentedControlFrame => "control frame is fragmented",
+        })
+    }
 }
 
+impl std::error::Error for FrameError {}
+
 impl TryFrom<&[u8]> for FrameHeader {
     type Error = FrameError;
 
+    /// Parses a header with no extensions negotiated, see [`FrameHeader::parse`].
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, 0)
+    }
+}
+
+impl FrameHeader {
+    /// Parses a header, rejecting any RSV bits not present in `allowed_rsv`.
+    /// `allowed_rsv` uses the same layout as [`FrameHeader::rsv`], and should only have bits
+    /// set that a negotiated extension defines (e.g. RSV1 for *permessage-deflate*).
+    ///
+    /// # Errors
+    /// See [`FrameError`].
+    #[allow(clippy::missing_panics_doc)] // slices are length-checked before unwrapping
+    pub fn parse(value: &[u8], allowed_rsv: u8) -> Result<Self, FrameError> {
         if value.len() < 2 {
             return Err(FrameError::FrameTooShort);
         }
@@ -200,25 +378,64 @@ impl TryFrom<&[u8]> for FrameHeader {
                 }
                 127 => {
                     const U64_LEN: usize = 8;
-                    value
-                        .get(2..2 + U64_LEN)
-                        .map_or(PayloadLen::HintU64, |slice| {
+                    match value.get(2..2 + U64_LEN) {
+                        None => PayloadLen::HintU64,
+                        // the most significant bit must be 0 (RFC 6455, section 5.2)
+                        Some(slice) if slice[0] & 0x80 != 0 => {
+                            return Err(FrameError::LengthHighBitSet);
+                        }
+                        Some(slice) => {
                             PayloadLen::ExactU64(u64::from_be_bytes(slice.try_into().unwrap()))
-                        })
+                        }
+                    }
                 }
                 _ => unreachable!(),
             }
         };
 
+        let rsv = (value[0] & 0b0111_0000) >> 4;
+        if rsv & !allowed_rsv != 0 {
+            return Err(FrameError::ReservedBitsSet);
+        }
+
+        let opcode =
+            Opcode::try_from(value[0] & 0b0000_1111).map_err(|_| FrameError::InvalidOpcode)?;
+        // 7-bit lengths are at most 125, anything else is too long
+        if opcode.is_control() && !matches!(payload_len, PayloadLen::ExactU8(_)) {
+            return Err(FrameError::ControlFrameTooLong);
+        }
+        let fin = (value[0] >> 7) != 0;
+        if opcode.is_control() && !fin {
+            return Err(FrameError::FragmentedControlFrame);
+        }
+
         Ok(Self {
-            fin: (value[0] >> 7) != 0,
-            rsv: (value[0] & 0b0111_0000) >> 4,
-            opcode: Opcode::try_from(value[0] & 0b0000_1111)
-                .map_err(|_| FrameError::InvalidOpcode)?,
+            fin,
+            rsv,
+            opcode,
             masked: (value[1] >> 7) != 0,
             payload_len,
         })
     }
+
+    /// Same as [`FrameHeader::parse`], but also rejects lengths that could've been encoded
+    /// in a smaller form, see [`PayloadLen::is_minimal`].
+    ///
+    /// Peers are rarely this strict, so [`FrameHeader::parse`] is used unless asked for.
+    ///
+    /// # Errors
+    /// [`FrameError::NonMinimalLength`] for a non-minimal length, otherwise see [`FrameError`].
+    pub fn parse_strict(value: &[u8], allowed_rsv: u8) -> Result<Self, FrameError> {
+        Self::parse(value, allowed_rsv)?.minimal()
+    }
+
+    fn minimal(self) -> Result<Self, FrameError> {
+        if self.payload_len.is_minimal() {
+            Ok(self)
+        } else {
+            Err(FrameError::NonMinimalLength)
+        }
+    }
 }
 
 impl From<Frame> for Vec<u8> {
@@ -235,9 +452,22 @@ impl From<Frame> for Vec<u8> {
 impl TryFrom<Vec<u8>> for Frame {
     type Error = FrameError;
 
+    /// Parses a frame with no extensions negotiated, see [`Frame::parse`].
     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::parse(&value, 0)
+    }
+}
+
+impl Frame {
+    /// Parses a whole frame, rejecting any RSV bits not present in `allowed_rsv`.
+    /// See [`FrameHeader::parse`].
+    ///
+    /// # Errors
+    /// See [`FrameError`].
+    #[allow(clippy::missing_panics_doc)] // slices are length-checked before unwrapping
+    pub fn parse(value: &[u8], allowed_rsv: u8) -> Result<Self, FrameError> {
         const MASKING_KEY_LEN: usize = 4;
-        let header: FrameHeader = value.as_slice().try_into()?;
+        let header = FrameHeader::parse(value, allowed_rsv)?;
         let masking_key_index = match header.payload_len {
             PayloadLen::ExactU8(_) => 2,
             PayloadLen::ExactU16(_) => 4,
@@ -253,22 +483,77 @@ impl TryFrom<Vec<u8>> for Frame {
                     .map(u32::from_be_bytes)
             })
             .transpose()?;
+        let payload_index = if header.masked {
+            masking_key_index + MASKING_KEY_LEN
+        } else {
+            masking_key_index
+        };
         Ok(Frame {
             header,
             masking_key,
             payload: value
-                .get(masking_key_index + MASKING_KEY_LEN..)
+                .get(payload_index..)
                 .ok_or(FrameError::PayloadTooShort)?
                 .to_vec(),
         })
     }
+
+    /// Same as [`Frame::parse`], but also rejects non-minimal lengths,
+    /// see [`FrameHeader::parse_strict`].
+    ///
+    /// # Errors
+    /// See [`FrameHeader::parse_strict`].
+    pub fn parse_strict(value: &[u8], allowed_rsv: u8) -> Result<Self, FrameError> {
+        let frame = Self::parse(value, allowed_rsv)?;
+        frame.header.minimal()?;
+        Ok(frame)
+    }
+}
+
+/// Formats `bytes` 16 to a line, as an offset, hex, and printable ASCII columns.
+/// Meant for tests and logs of rejected frames, not anything sent over the wire.
+///
+/// ```
+/// use websocket::frame::hexdump;
+///
+/// assert_eq!(
+///     hexdump(&[0x81, 0x02, b'h', b'i']),
+///     "00000000  81 02 68 69                                      |..hi|",
+/// );
+/// ```
+#[must_use]
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if i > 0 {
+            dump.push('\n');
+        }
+        let _ = write!(dump, "{:08x} ", i * 16);
+        for byte in line {
+            let _ = write!(dump, " {byte:02x}");
+        }
+        dump.extend(std::iter::repeat_n("   ", 16 - line.len()));
+        dump.push_str("  |");
+        dump.extend(line.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                char::from(byte)
+            } else {
+                '.'
+            }
+        }));
+        dump.push('|');
+    }
+    dump
 }
 
 #[cfg(test)]
 mod tests {
+    use rand::Rng;
+
     use crate::frame::{Frame, PayloadLen};
+    use crate::message::StatusCode;
 
-    use super::{FrameHeader, Opcode};
+    use super::{FrameError, FrameHeader, Opcode, apply_mask, hexdump};
 
     #[test]
     fn unmasked_64bit_frame_into_bytes() {
@@ -286,10 +571,7 @@ mod tests {
         let bytes: Vec<u8> = unmasked_long.clone().into();
 
         println!("Unmasked 64-bit length: {unmasked_long:?}");
-        for byte in &bytes {
-            print!("{byte:08b} ");
-        }
-        println!("\n");
+        println!("{}\n", hexdump(&bytes));
 
         assert_eq!(bytes[0] >> 7, 0, "incorrect FIN bit");
         assert_eq!((bytes[0] & 0b0111_0000) >> 4, 0, "incorrect RSV bits");
@@ -313,20 +595,14 @@ mod tests {
         let bytes: Vec<u8> = masked_7bit.clone().into();
 
         println!("Yet to be masked 7-bit length: {masked_7bit:?}");
-        for byte in &bytes {
-            print!("{byte:08b} ");
-        }
-        println!();
+        println!("{}\n", hexdump(&bytes));
 
         assert_eq!(masked_7bit.payload[2], 0xff);
 
         masked_7bit.mask();
         let bytes: Vec<u8> = masked_7bit.clone().into();
         println!("Masked:");
-        for byte in &bytes {
-            print!("{byte:08b} ");
-        }
-        println!();
+        println!("{}\n", hexdump(&bytes));
 
         assert_eq!(masked_7bit.payload[2], 0xcf, "invalid masked payload");
 
@@ -340,13 +616,13 @@ mod tests {
     fn unmasked_64bit_raw_into_frame() {
         let unmasked_long_bytes = vec![2_u8, 127, 0, 0, 0, 0, 0, 0, 0, 4, 222, 173, 190, 239];
         println!("Raw unmasked 64-bit length:");
-        for byte in &unmasked_long_bytes {
-            print!("{byte:08b} ");
-        }
-        println!();
+        println!("{}\n", hexdump(&unmasked_long_bytes));
 
         let frame: Frame = unmasked_long_bytes.try_into().unwrap();
-        println!("Reconstructed: {frame:?}\n");
+        println!(
+            "Reconstructed: {}, {}\n",
+            frame.header.opcode, frame.header.payload_len
+        );
 
         assert!(!frame.header.fin, "incorrect FIN bit");
         assert_eq!(frame.header.rsv, 0, "incorrect RSV bits");
@@ -356,21 +632,19 @@ mod tests {
             PayloadLen::ExactU64(4),
             "incorrect payload length"
         );
+        assert_eq!(frame.payload, [0xde, 0xad, 0xbe, 0xef], "incorrect payload");
     }
 
     #[test]
     fn masked_7bit_raw_into_frame() {
         let masked_7bit_bytes = vec![176, 131, 0, 0, 48, 57, 255, 0, 207];
         println!("Raw masked 7-bit length:");
-        for byte in &masked_7bit_bytes {
-            print!("{byte:08b} ");
-        }
-        println!();
+        println!("{}\n", hexdump(&masked_7bit_bytes));
 
-        let mut frame: Frame = masked_7bit_bytes.try_into().unwrap();
+        let mut frame = Frame::parse(&masked_7bit_bytes, 0b011).unwrap();
 
         assert_eq!(frame.payload[2], 0xcf, "invalid masked payload");
-        frame.mask();
+        frame.unmask();
         assert_eq!(frame.payload[2], 0xff, "invalid unmasked payload");
 
         println!("Unmasked 7-bit: {frame:?}");
@@ -384,4 +658,221 @@ mod tests {
             "incorrect payload length"
         );
     }
+
+    #[test]
+    fn masking_without_a_key_does_nothing() {
+        let mut frame = Frame::try_from(vec![0b1000_0010, 1, 0xff]).unwrap();
+        frame.mask();
+        frame.unmask();
+        assert_eq!(frame.payload, [0xff]);
+        assert!(!frame.header.masked);
+    }
+
+    #[test]
+    fn reserved_bits_are_rejected() {
+        // fin, rsv, binary, unmasked, 1 byte of payload
+        let with_rsv = |rsv: u8| vec![0b1000_0010 | (rsv << 4), 1, 0xff];
+
+        assert_eq!(
+            Frame::try_from(with_rsv(0b001)),
+            Err(FrameError::ReservedBitsSet)
+        );
+        assert_eq!(
+            Frame::try_from(with_rsv(0b111)),
+            Err(FrameError::ReservedBitsSet)
+        );
+        assert_eq!(
+            Frame::parse(&with_rsv(0b111), 0b100),
+            Err(FrameError::ReservedBitsSet)
+        );
+        assert_eq!(
+            Frame::parse(&with_rsv(0b100), 0b100).unwrap().header.rsv,
+            0b100
+        );
+
+        let frame = Frame::try_from(with_rsv(0)).unwrap();
+        assert_eq!(frame.header.rsv, 0);
+        assert_eq!(Vec::<u8>::from(frame), with_rsv(0));
+    }
+
+    #[test]
+    fn hexdump_wraps_and_pads() {
+        let bytes: Vec<u8> = (b'a'..=b'r').chain([0, 0xff]).collect();
+        assert_eq!(
+            hexdump(&bytes),
+            "00000000  61 62 63 64 65 66 67 68 69 6a 6b 6c 6d 6e 6f 70  |abcdefghijklmnop|\n\
+             00000010  71 72 00 ff                                      |qr..|"
+        );
+        assert_eq!(hexdump(&[]), "");
+    }
+
+    #[test]
+    fn opcodes_and_lengths_display() {
+        assert_eq!(Opcode::Ping.to_string(), "Ping (0x9)");
+        assert_eq!(PayloadLen::ExactU16(300).to_string(), "300 bytes (16-bit)");
+        assert_eq!(PayloadLen::HintU64.to_string(), "64-bit length follows");
+    }
+
+    #[test]
+    fn non_minimal_lengths_are_rejected_when_strict() {
+        // fin, binary, unmasked, 10 bytes in the 16-bit form
+        let mut u16_form = vec![0b1000_0010, 126];
+        u16_form.extend_from_slice(&10u16.to_be_bytes());
+        u16_form.extend_from_slice(&[0; 10]);
+        // 200 bytes in the 64-bit form
+        let mut u64_form = vec![0b1000_0010, 127];
+        u64_form.extend_from_slice(&200u64.to_be_bytes());
+        u64_form.extend_from_slice(&[0; 200]);
+
+        for frame in [&u16_form, &u64_form] {
+            assert!(Frame::parse(frame, 0).is_ok());
+            assert_eq!(
+                Frame::parse_strict(frame, 0),
+                Err(FrameError::NonMinimalLength)
+            );
+        }
+
+        let minimal: Vec<u8> = Frame::new(true, Opcode::Binary, vec![0; 200]).into();
+        assert!(Frame::parse_strict(&minimal, 0).is_ok());
+    }
+
+    #[test]
+    fn length_with_the_top_bit_set_is_rejected() {
+        // fin, binary, unmasked, 64-bit length
+        let mut header = vec![0b1000_0010, 127];
+        header.extend_from_slice(&0x8000_0000_0000_0000_u64.to_be_bytes());
+        assert_eq!(
+            FrameHeader::try_from(&header[..]),
+            Err(FrameError::LengthHighBitSet)
+        );
+
+        let mut header = vec![0b1000_0010, 127];
+        header.extend_from_slice(&0x7fff_ffff_ffff_ffff_u64.to_be_bytes());
+        assert_eq!(
+            FrameHeader::try_from(&header[..]).map(|h| h.payload_len),
+            Ok(PayloadLen::ExactU64(0x7fff_ffff_ffff_ffff))
+        );
+    }
+
+    #[test]
+    fn long_control_frames_are_rejected() {
+        // fin, ping, unmasked, 16-bit length hint
+        assert_eq!(
+            FrameHeader::try_from(&[0b1000_1001, 126][..]),
+            Err(FrameError::ControlFrameTooLong)
+        );
+        // fin, close, unmasked, 64-bit length
+        let mut close = vec![0b1000_1000, 127];
+        close.extend_from_slice(&2_u64.to_be_bytes());
+        close.extend_from_slice(&1000_u16.to_be_bytes());
+        assert_eq!(Frame::try_from(close), Err(FrameError::ControlFrameTooLong));
+        assert_eq!(
+            FrameError::ControlFrameTooLong.to_string(),
+            "control frame exceeds 125 bytes"
+        );
+
+        let mut ping = vec![0b1000_1001, 125];
+        ping.extend_from_slice(&[0; 125]);
+        assert_eq!(Frame::try_from(ping).unwrap().payload.len(), 125);
+        assert!(Opcode::Pong.is_control());
+        assert!(!Opcode::Binary.is_control());
+    }
+
+    #[test]
+    fn fragmented_control_frames_are_rejected() {
+        // no fin, ping, unmasked, 1 byte of payload
+        assert_eq!(
+            Frame::try_from(vec![0b0000_1001, 1, 0xff]),
+            Err(FrameError::FragmentedControlFrame)
+        );
+        // non-final data frames are fine
+        assert!(
+            !Frame::try_from(vec![0b0000_0001, 1, b'a'])
+                .unwrap()
+                .header
+                .fin
+        );
+    }
+
+    #[test]
+    fn fragments_continue_and_finish_last() {
+        let fragments = Frame::new(true, Opcode::Text, b"hello".to_vec()).fragment(2);
+        let headers: Vec<_> = fragments
+            .iter()
+            .map(|f| (f.header.fin, f.header.opcode, f.payload.len()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                (false, Opcode::Text, 2),
+                (false, Opcode::Continue, 2),
+                (true, Opcode::Continue, 1)
+            ]
+        );
+        assert_eq!(Frame::ping(vec![0; 10]).fragment(2).len(), 1);
+    }
+
+    #[test]
+    fn frames_from_same_bytes_are_equal() {
+        let bytes = vec![130_u8, 4, 222, 173, 190, 239];
+        let first: Frame = bytes.clone().try_into().unwrap();
+        let second: Frame = bytes.try_into().unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn control_frame_constructors() {
+        let frame = Frame::ping(vec![1; 200]);
+        assert_eq!(frame.header.opcode, Opcode::Ping);
+        assert!(frame.header.fin);
+        assert_eq!(frame.payload.len(), 125);
+        assert_eq!(frame.header.payload_len, PayloadLen::ExactU8(125));
+
+        let frame = Frame::pong(vec![1, 2, 3]);
+        assert_eq!(frame.header.opcode, Opcode::Pong);
+        assert!(frame.header.fin);
+        assert_eq!(frame.payload, vec![1, 2, 3]);
+
+        let frame = Frame::close(StatusCode::GoingAway, Some("a".repeat(200)));
+        assert_eq!(frame.header.opcode, Opcode::Close);
+        assert!(frame.header.fin);
+        assert_eq!(frame.payload.len(), 125);
+        assert_eq!(frame.payload[..2], 1001_u16.to_be_bytes());
+
+        let frame = Frame::close(StatusCode::Normal, None);
+        assert_eq!(frame.payload, 1000_u16.to_be_bytes());
+    }
+
+    #[test]
+    fn close_reason_is_cut_on_a_char_boundary() {
+        // the 'є' takes bytes 122 and 123, so it doesn't fit
+        let reason = format!("{}є", "a".repeat(122));
+        let frame = Frame::close(StatusCode::Normal, Some(reason));
+        assert_eq!(frame.payload.len(), 124);
+        assert_eq!(frame.payload[2..], *"a".repeat(122).as_bytes());
+
+        let frame = Frame::close(StatusCode::Normal, Some("є".repeat(100)));
+        assert_eq!(frame.payload[2..], *"є".repeat(61).as_bytes());
+    }
+
+    #[test]
+    fn wide_masking_matches_bytewise() {
+        let mut rng = rand::rng();
+        for len in 0..64 {
+            let payload: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+            let key: [u8; 4] = rng.random();
+            // unaligned starts too
+            for offset in 0..len.min(8) {
+                let mut wide = payload[offset..].to_vec();
+                apply_mask(&mut wide, key);
+                let bytewise: Vec<u8> = payload[offset..]
+                    .iter()
+                    .enumerate()
+                    .map(|(index, byte)| byte ^ key[index % 4])
+                    .collect();
+                assert_eq!(wide, bytewise);
+            }
+        }
+    }
 }
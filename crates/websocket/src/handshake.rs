@@ -1,4 +1,4 @@
-use std::io::ErrorKind;
+use std::{io::ErrorKind, time::Duration};
 
 use base64::{Engine as _, engine::general_purpose::STANDARD};
 use rand::Rng;
@@ -14,6 +14,9 @@ use crate::WsStream;
 
 const SEC_WS_MAGIC: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
 
+/// How long [`IntoWebsocket::try_upgrade`] waits for the peer before giving up.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 fn generate_sec_key() -> String {
     let nonce: [u8; 16] = rand::rng().random();
     STANDARD.encode(nonce)
@@ -26,8 +29,25 @@ fn generate_response_key(key: String) -> String {
     STANDARD.encode(result)
 }
 
-fn validate_upgrade_headers<'a>(request: &'a str, host: &str) -> Option<&'a str> {
+/// Compares two byte slices in time independent of where they differ.
+/// Only the length is allowed to leak.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks the upgrade headers of `request`, returning its `Host` and `Sec-Websocket-Key`.
+/// The host has to be one of those accepted by `accept_host`.
+fn validate_upgrade_headers(
+    request: &str,
+    accept_host: impl Fn(&str) -> bool,
+) -> Option<(&str, &str)> {
     let lines: Vec<_> = request.lines().collect();
+    let host = lines
+        .iter()
+        .find(|l| l.to_ascii_lowercase().starts_with("host:"))
+        .and_then(|l| l.split_once(':'))
+        .map(|(_, h)| h.trim())
+        .filter(|h| accept_host(h))?;
 
     if !(lines
         .iter()
@@ -37,29 +57,244 @@ fn validate_upgrade_headers<'a>(request: &'a str, host: &str) -> Option<&'a str>
             .any(|l| l.eq_ignore_ascii_case("connection: upgrade"))
         && lines
             .iter()
-            .any(|l| l.eq_ignore_ascii_case("sec-websocket-version: 13"))
-        && lines.iter().any(|l| {
-            l.to_ascii_lowercase().starts_with("host:")
-                && l.split_once(": ").is_some_and(|(_, h)| h.trim() == host)
-        }))
+            .any(|l| l.eq_ignore_ascii_case("sec-websocket-version: 13")))
     {
         return None;
     }
 
-    lines
+    let key = lines
         .iter()
         .find(|l| l.to_ascii_lowercase().starts_with("sec-websocket-key:"))
-        .map(|l| l.split_once(": ").map(|(_, key)| key))?
+        .map(|l| l.split_once(": ").map(|(_, key)| key))??;
+    Some((host, key))
+}
+
+/// What was learned during the opening handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeInfo {
+    /// `Host` the request was made for.
+    pub host: String,
+    /// Path from the request line, e.g. `/room/general`.
+    pub path: String,
+    /// Headers of the peer's request or response, in the order received.
+    pub headers: Vec<(String, String)>,
+    /// Negotiated subprotocol, if any.
+    pub subprotocol: Option<String>,
+}
+
+impl HandshakeInfo {
+    fn new(host: &str, path: &str, message: &str, subprotocol: Option<String>) -> Self {
+        let headers = header_lines(message)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Self {
+            host: host.to_string(),
+            path: path.to_string(),
+            headers,
+            subprotocol,
+        }
+    }
+
+    /// Value of the first header called `name`, compared case-insensitively.
+    #[must_use]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Inner error of the [`ErrorKind::ConnectionRefused`] returned when the server
+/// answers the upgrade request with anything but `101 Switching Protocols`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradeRejected {
+    pub status: u16,
+    pub reason: String,
+}
+
+impl std::fmt::Display for UpgradeRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "upgrade rejected with {} {}", self.status, self.reason)
+    }
+}
+
+impl std::error::Error for UpgradeRejected {}
+
+/// Checks the `HTTP/1.1 <status> <reason>` line of a response,
+/// failing unless the status is `101`.
+fn check_status_line(response: &str) -> std::io::Result<()> {
+    let mut parts = response.lines().next().unwrap_or_default().splitn(3, ' ');
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(ErrorKind::InvalidData.into());
+    };
+    let status = status
+        .parse()
+        .ok()
+        .filter(|_| version.starts_with("HTTP/"))
+        .ok_or(ErrorKind::InvalidData)?;
+    if status == 101 {
+        return Ok(());
+    }
+    let reason = parts.next().unwrap_or_default().trim().to_string();
+    Err(std::io::Error::new(
+        ErrorKind::ConnectionRefused,
+        UpgradeRejected { status, reason },
+    ))
+}
+
+/// Path of a `GET <path> HTTP/1.1` request line.
+fn request_path(request: &str) -> Option<&str> {
+    let mut parts = request.lines().next()?.split_whitespace();
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("GET"), Some(path), Some(_)) if path.starts_with('/') => Some(path),
+        _ => None,
+    }
+}
+
+/// Formats `extra` as header lines, refusing any that would break out of its line
+/// or be misread as something else, with [`ErrorKind::InvalidInput`].
+fn format_extra_headers(extra: &[(String, String)]) -> std::io::Result<String> {
+    if extra.iter().any(|(name, value)| {
+        name.is_empty() || name.contains([':', '\r', '\n']) || value.contains(['\r', '\n'])
+    }) {
+        return Err(ErrorKind::InvalidInput.into());
+    }
+    Ok(extra
+        .iter()
+        .flat_map(|(name, value)| [name, ": ", value, "\r\n"])
+        .collect())
+}
+
+/// Trimmed names and values of the headers of `message`, skipping its first line.
+fn header_lines(message: &str) -> impl Iterator<Item = (&str, &str)> {
+    message
+        .lines()
+        .skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+/// Value of the first header of `message` called `name`, compared case-insensitively.
+fn header_value<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    header_lines(message)
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// Subprotocols listed in all `Sec-Websocket-Protocol` headers, in the order offered.
+/// Works for responses too, which should list exactly one.
+fn offered_protocols(request: &str) -> impl Iterator<Item = &str> {
+    request
+        .lines()
+        .filter(|l| {
+            l.to_ascii_lowercase()
+                .starts_with("sec-websocket-protocol:")
+        })
+        .filter_map(|l| l.split_once(':').map(|(_, value)| value))
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
 }
 
 #[allow(async_fn_in_trait)]
 pub trait IntoWebsocket {
-    async fn try_upgrade(&mut self, host: &str) -> std::io::Result<()>;
+    /// Performs the opening handshake, failing with [`ErrorKind::TimedOut`] if it takes
+    /// longer than `timeout`, e.g. because the peer never sends anything.
+    async fn try_upgrade_timeout(
+        &mut self,
+        host: &str,
+        timeout: Duration,
+    ) -> std::io::Result<HandshakeInfo>;
+
+    /// Same as [`IntoWebsocket::try_upgrade_timeout`] with the configured
+    /// [`WsConfig::handshake_timeout`](crate::WsConfig::handshake_timeout),
+    /// [`HANDSHAKE_TIMEOUT`] by default.
+    async fn try_upgrade(&mut self, host: &str) -> std::io::Result<HandshakeInfo> {
+        self.try_upgrade_timeout(host, HANDSHAKE_TIMEOUT).await
+    }
+}
+
+/// Maps an elapsed `timeout` to [`ErrorKind::TimedOut`].
+async fn timed<T>(
+    timeout: Duration,
+    future: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    tokio::time::timeout(timeout, future)
+        .await
+        .map_err(|_| ErrorKind::TimedOut)?
 }
 
 impl<T: UnpinStream> IntoWebsocket for WsStream<Server, T> {
-    async fn try_upgrade(&mut self, host: &str) -> std::io::Result<()> {
+    async fn try_upgrade_timeout(
+        &mut self,
+        host: &str,
+        timeout: Duration,
+    ) -> std::io::Result<HandshakeInfo> {
+        timed(timeout, self.try_upgrade_with(host, &[])).await
+    }
+
+    async fn try_upgrade(&mut self, host: &str) -> std::io::Result<HandshakeInfo> {
+        let timeout = self.rx.config().handshake_timeout();
+        self.try_upgrade_timeout(host, timeout).await
+    }
+}
+
+impl<T: UnpinStream> WsStream<Server, T> {
+    /// Same as [`IntoWebsocket::try_upgrade`], but offers `protocols` to the server,
+    /// most preferred first. The returned info carries the one the server picked, if any,
+    /// and the response headers. No timeout is applied, see [`HANDSHAKE_TIMEOUT`].
+    ///
+    /// # Errors
+    ///
+    /// Fails on I/O errors, with [`ErrorKind::ConnectionRefused`] wrapping
+    /// [`UpgradeRejected`] if the server refuses the upgrade, or with
+    /// [`ErrorKind::InvalidData`] on an invalid response,
+    /// including one picking a subprotocol or an extension that wasn't offered.
+    pub async fn try_upgrade_with(
+        &mut self,
+        host: &str,
+        protocols: &[&str],
+    ) -> std::io::Result<HandshakeInfo> {
+        self.upgrade(host, protocols, "").await
+    }
+
+    /// Same as [`IntoWebsocket::try_upgrade`], but also sends the `extra` headers,
+    /// e.g. `Authorization` or `Origin`. No timeout is applied, see [`HANDSHAKE_TIMEOUT`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`] before sending anything if a header name
+    /// or value contains a line break, otherwise as [`WsStream::try_upgrade_with`].
+    pub async fn try_upgrade_with_headers(
+        &mut self,
+        host: &str,
+        extra: &[(String, String)],
+    ) -> std::io::Result<HandshakeInfo> {
+        let extra = format_extra_headers(extra)?;
+        self.upgrade(host, &[], &extra).await
+    }
+
+    async fn upgrade(
+        &mut self,
+        host: &str,
+        protocols: &[&str],
+        extra_headers: &str,
+    ) -> std::io::Result<HandshakeInfo> {
         let sec_key = generate_sec_key();
+        let protocol_header = if protocols.is_empty() {
+            String::new()
+        } else {
+            format!("Sec-Websocket-Protocol: {}\r\n", protocols.join(", "))
+        };
+        #[cfg(feature = "deflate")]
+        let extension_header = if self.rx.config().compression() {
+            crate::deflate::offer_header()
+        } else {
+            String::new()
+        };
+        #[cfg(not(feature = "deflate"))]
+        let extension_header = "";
         self.send_raw(
             format!(
                 "\
@@ -68,48 +303,437 @@ Host: {host}\r
 Upgrade: websocket\r
 Connection: upgrade\r
 Sec-Websocket-Key: {sec_key}\r
-Sec-Websocket-Version: 13\r\n\r\n",
+Sec-Websocket-Version: 13\r\n{protocol_header}{extension_header}{extra_headers}\r\n",
             )
             .as_bytes(),
         )
         .await?;
         let response =
             String::from_utf8(self.read_http_bytes().await?).map_err(|_| ErrorKind::InvalidData)?;
+        check_status_line(&response)?;
 
         let resp_key = response
             .lines()
             .find(|l| l.to_ascii_lowercase().starts_with("sec-websocket-accept:"))
-            .ok_or::<std::io::Error>(ErrorKind::InvalidData.into())?
-            .split_once(": ")
-            .unwrap()
-            .1;
+            .and_then(|l| l.split_once(':'))
+            .map(|(_, key)| key.trim())
+            .ok_or::<std::io::Error>(ErrorKind::InvalidData.into())?;
 
-        if resp_key != generate_response_key(sec_key) {
+        if !constant_time_eq(
+            resp_key.as_bytes(),
+            generate_response_key(sec_key).as_bytes(),
+        ) {
             return Err(ErrorKind::InvalidData.into());
         }
 
-        Ok(())
+        let mut selected = offered_protocols(&response);
+        let protocol = selected.next();
+        if selected.next().is_some() || protocol.is_some_and(|p| !protocols.contains(&p)) {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        // RFC 7692 §5: an extension the client didn't offer fails the connection
+        if extension_header.is_empty()
+            && header_value(&response, "sec-websocket-extensions").is_some()
+        {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        #[cfg(feature = "deflate")]
+        if let Some(params) = crate::deflate::accepted_params(&response)? {
+            self.enable_deflate(params);
+        }
+        Ok(HandshakeInfo::new(
+            host,
+            "/",
+            &response,
+            protocol.map(str::to_string),
+        ))
     }
 }
 
 impl<T: UnpinStream> IntoWebsocket for WsStream<Client, T> {
-    async fn try_upgrade(&mut self, expected_host: &str) -> std::io::Result<()> {
+    async fn try_upgrade_timeout(
+        &mut self,
+        expected_host: &str,
+        timeout: Duration,
+    ) -> std::io::Result<HandshakeInfo> {
+        timed(timeout, self.try_upgrade_with(expected_host, |_| false)).await
+    }
+
+    async fn try_upgrade(&mut self, expected_host: &str) -> std::io::Result<HandshakeInfo> {
+        let timeout = self.rx.config().handshake_timeout();
+        self.try_upgrade_timeout(expected_host, timeout).await
+    }
+}
+
+impl<T: UnpinStream> WsStream<Client, T> {
+    /// Same as [`IntoWebsocket::try_upgrade`], but lets the caller pick a subprotocol
+    /// out of the ones offered by the client. The first offered protocol accepted by
+    /// `select_protocol` is echoed back in the response and returned along with
+    /// the requested path and headers.
+    ///
+    /// If none are accepted, the `Sec-Websocket-Protocol` header is omitted,
+    /// leaving it up to the client to fail the connection.
+    /// No timeout is applied, see [`HANDSHAKE_TIMEOUT`].
+    ///
+    /// # Errors
+    ///
+    /// Fails on I/O errors, or with [`ErrorKind::ConnectionRefused`] on an invalid request.
+    pub async fn try_upgrade_with<F>(
+        &mut self,
+        expected_host: &str,
+        select_protocol: F,
+    ) -> std::io::Result<HandshakeInfo>
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.try_upgrade_for(|host| host == expected_host, select_protocol)
+            .await
+    }
+
+    /// Same as [`WsStream::try_upgrade_with`], but accepts any `Host` allowed by
+    /// `accept_host`, e.g. when one listener serves several names.
+    /// The one requested is returned in [`HandshakeInfo::host`].
+    ///
+    /// # Errors
+    ///
+    /// Fails on I/O errors, or with [`ErrorKind::ConnectionRefused`] on an invalid request.
+    pub async fn try_upgrade_for<H, F>(
+        &mut self,
+        accept_host: H,
+        select_protocol: F,
+    ) -> std::io::Result<HandshakeInfo>
+    where
+        H: Fn(&str) -> bool,
+        F: Fn(&str) -> bool,
+    {
+        self.try_upgrade_checked(accept_host, |_| true, select_protocol)
+            .await
+    }
+
+    /// Same as [`WsStream::try_upgrade_for`], but also passes the `Origin` header browsers
+    /// send to `accept_origin`, `None` if there isn't one. Refused origins are answered with
+    /// `403 Forbidden`, so that other sites can't connect on behalf of a visitor
+    /// (cross-site WebSocket hijacking).
+    ///
+    /// # Errors
+    ///
+    /// Fails on I/O errors, or with [`ErrorKind::ConnectionRefused`] on an invalid request
+    /// or a refused origin.
+    pub async fn try_upgrade_checked<H, O, F>(
+        &mut self,
+        accept_host: H,
+        accept_origin: O,
+        select_protocol: F,
+    ) -> std::io::Result<HandshakeInfo>
+    where
+        H: Fn(&str) -> bool,
+        O: Fn(Option<&str>) -> bool,
+        F: Fn(&str) -> bool,
+    {
         let request =
             String::from_utf8(self.read_http_bytes().await?).map_err(|_| ErrorKind::InvalidData)?;
 
-        let sec_key = validate_upgrade_headers(&request, expected_host)
-            .ok_or(ErrorKind::ConnectionRefused)?;
+        let path = request_path(&request).ok_or(ErrorKind::ConnectionRefused)?;
+        let (host, sec_key) =
+            validate_upgrade_headers(&request, accept_host).ok_or(ErrorKind::ConnectionRefused)?;
+        if !accept_origin(header_value(&request, "origin")) {
+            self.send_raw(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            self.shutdown().await?;
+            return Err(ErrorKind::ConnectionRefused.into());
+        }
+        let protocol = offered_protocols(&request)
+            .find(|p| select_protocol(p))
+            .map(str::to_string);
+        #[cfg(feature = "deflate")]
+        let deflate =
+            crate::deflate::select_offer(&request).filter(|_| self.rx.config().compression());
+        #[cfg(feature = "deflate")]
+        let extension_header = deflate
+            .map(crate::deflate::response_header)
+            .unwrap_or_default();
+        #[cfg(not(feature = "deflate"))]
+        let extension_header = "";
 
         let response = format!(
             "\
 HTTP/1.1 101 Switching Protocols\r
 Upgrade: websocket\r
 Connection: upgrade\r
-Sec-Websocket-Accept: {key}\r\n\r\n",
-            key = generate_response_key(sec_key.to_string())
+Sec-Websocket-Accept: {key}\r\n{protocol_header}{extension_header}\r\n",
+            key = generate_response_key(sec_key.to_string()),
+            protocol_header = protocol
+                .as_ref()
+                .map(|p| format!("Sec-Websocket-Protocol: {p}\r\n"))
+                .unwrap_or_default(),
         );
 
         self.send_raw(response.as_bytes()).await?;
-        Ok(())
+        #[cfg(feature = "deflate")]
+        if let Some(params) = deflate {
+            self.enable_deflate(params);
+        }
+        Ok(HandshakeInfo::new(host, path, &request, protocol))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncWriteExt, duplex};
+
+    use std::{io::ErrorKind, time::Duration};
+
+    use super::{
+        HandshakeInfo, IntoWebsocket, UpgradeRejected, check_status_line, constant_time_eq,
+        generate_response_key, header_value, request_path, validate_upgrade_headers,
+    };
+    use crate::{Client, Server, WsConfig, WsStream};
+
+    #[tokio::test]
+    async fn silent_peer_times_out() {
+        let (_client_end, server_end) = duplex(4096);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        let error = server
+            .try_upgrade_timeout("localhost", Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn accept_key_comparison() {
+        // RFC 6455, section 1.3
+        let expected = generate_response_key(String::from("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert!(constant_time_eq(
+            b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            expected.as_bytes()
+        ));
+
+        let mut off_by_one = expected.clone().into_bytes();
+        off_by_one[7] ^= 1;
+        assert!(!constant_time_eq(&off_by_one, expected.as_bytes()));
+        assert!(!constant_time_eq(
+            &expected.as_bytes()[1..],
+            expected.as_bytes()
+        ));
+    }
+
+    /// Upgrades over an in-memory pipe, returning what the client and the server negotiated.
+    async fn negotiate(
+        offered: &[&str],
+        supported: &[&str],
+    ) -> (
+        std::io::Result<HandshakeInfo>,
+        std::io::Result<HandshakeInfo>,
+    ) {
+        let (client_end, server_end) = duplex(4096);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        tokio::join!(
+            client.try_upgrade_with("localhost", offered),
+            server.try_upgrade_with("localhost", |p| supported.contains(&p)),
+        )
+    }
+
+    #[tokio::test]
+    async fn server_picks_first_supported_protocol() {
+        let (client, server) = negotiate(&["chat.v2", "chat.v1"], &["chat.v1"]).await;
+        assert_eq!(client.unwrap().subprotocol.as_deref(), Some("chat.v1"));
+        assert_eq!(server.unwrap().subprotocol.as_deref(), Some("chat.v1"));
+    }
+
+    #[tokio::test]
+    async fn unsupported_protocols_are_omitted() {
+        let (client, server) = negotiate(&["chat.v2"], &["chat.v1"]).await;
+        assert_eq!(client.unwrap().subprotocol, None);
+        assert_eq!(server.unwrap().subprotocol, None);
+
+        let (client, server) = negotiate(&[], &["chat.v1"]).await;
+        assert_eq!(client.unwrap().subprotocol, None);
+        assert_eq!(server.unwrap().subprotocol, None);
+    }
+
+    #[tokio::test]
+    async fn request_path_and_headers_are_returned() {
+        let (mut client_end, server_end) = duplex(4096);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        client_end
+            .write_all(
+                b"GET /room/general HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                Connection: upgrade\r\nSec-Websocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-Websocket-Version: 13\r\nX-Forwarded-For: 10.0.0.1\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let info = server
+            .try_upgrade_with("localhost", |_| false)
+            .await
+            .unwrap();
+        assert_eq!(info.path, "/room/general");
+        assert_eq!(info.header("x-forwarded-for"), Some("10.0.0.1"));
+        assert_eq!(info.header("Sec-WebSocket-Version"), Some("13"));
+        assert_eq!(info.subprotocol, None);
+    }
+
+    #[tokio::test]
+    async fn extra_headers_reach_the_server() {
+        let (client_end, server_end) = duplex(4096);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        let extra = [
+            ("Authorization".to_string(), "Bearer abc".to_string()),
+            ("Origin".to_string(), "https://chat.example".to_string()),
+        ];
+        let (client, server) = tokio::join!(
+            client.try_upgrade_with_headers("localhost", &extra),
+            server.try_upgrade_with("localhost", |_| false),
+        );
+        client.unwrap();
+        let server = server.unwrap();
+        assert_eq!(server.header("authorization"), Some("Bearer abc"));
+        assert_eq!(server.header("origin"), Some("https://chat.example"));
+    }
+
+    #[tokio::test]
+    async fn origins_are_checked() {
+        let allowed = |origin: Option<&str>| origin == Some("https://chat.example");
+        for (origin, accepted) in [
+            (Some("https://chat.example"), true),
+            (Some("https://evil.example"), false),
+            (None, false),
+        ] {
+            let (client_end, server_end) = duplex(4096);
+            let mut client = WsStream::<Server, _>::from_stream(client_end);
+            let mut server = WsStream::<Client, _>::from_stream(server_end);
+            let extra: Vec<_> = origin
+                .map(|o| ("Origin".to_string(), o.to_string()))
+                .into_iter()
+                .collect();
+            let (client, server) = tokio::join!(
+                client.try_upgrade_with_headers("localhost", &extra),
+                server.try_upgrade_checked(|h| h == "localhost", allowed, |_| false),
+            );
+            if accepted {
+                client.unwrap();
+                assert_eq!(server.unwrap().header("origin"), origin);
+            } else {
+                assert_eq!(server.unwrap_err().kind(), ErrorKind::ConnectionRefused);
+                let error = client.unwrap_err();
+                let rejected = error
+                    .get_ref()
+                    .and_then(|e| e.downcast_ref::<UpgradeRejected>())
+                    .unwrap();
+                assert_eq!(rejected.status, 403);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn smuggled_headers_are_refused() {
+        let (client_end, _server_end) = duplex(4096);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        for extra in [
+            ("Cookie".to_string(), "a=b\r\nX-Admin: 1".to_string()),
+            ("X-Admin: 1\nCookie".to_string(), "a=b".to_string()),
+            (String::new(), "a=b".to_string()),
+        ] {
+            let error = client
+                .try_upgrade_with_headers("localhost", &[extra])
+                .await
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn any_accepted_host_passes() {
+        let request = |host: &str| {
+            format!(
+                "GET / HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\n\
+                Connection: upgrade\r\nSec-Websocket-Key: a2V5\r\n\
+                Sec-Websocket-Version: 13\r\n\r\n"
+            )
+        };
+        let hosts = ["chat.example", "chat.example:8443"];
+        let accept = |h: &str| hosts.contains(&h);
+        assert_eq!(
+            validate_upgrade_headers(&request("chat.example:8443"), accept),
+            Some(("chat.example:8443", "a2V5"))
+        );
+        assert_eq!(
+            validate_upgrade_headers(&request("chat.example"), accept),
+            Some(("chat.example", "a2V5"))
+        );
+        assert_eq!(
+            validate_upgrade_headers(&request("other.example"), accept),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_upgrade_carries_the_status() {
+        let (client_end, mut server_end) = duplex(4096);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end
+            .write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+        let error = client.try_upgrade_with("localhost", &[]).await.unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+        let rejected = error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<UpgradeRejected>())
+            .unwrap();
+        assert_eq!(rejected.status, 403);
+        assert_eq!(rejected.reason, "Forbidden");
+        assert_eq!(error.to_string(), "upgrade rejected with 403 Forbidden");
+    }
+
+    #[tokio::test]
+    async fn unoffered_extensions_fail_the_connection() {
+        let (client_end, server_end) = duplex(4096);
+        let mut client = WsStream::<Server, _>::from_stream_with_config(
+            client_end,
+            WsConfig::default().with_compression(false),
+        );
+        let mut server = tokio::io::BufReader::new(server_end);
+        let respond = async {
+            let request = String::from_utf8(crate::read_http_bytes(&mut server).await?).unwrap();
+            let key = header_value(&request, "sec-websocket-key").unwrap();
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                Connection: upgrade\r\nSec-Websocket-Accept: {}\r\n\
+                Sec-Websocket-Extensions: permessage-deflate\r\n\r\n",
+                generate_response_key(key.to_string()),
+            );
+            server.write_all(response.as_bytes()).await
+        };
+        let (client, server) = tokio::join!(client.try_upgrade_with("localhost", &[]), respond);
+        server.unwrap();
+        assert_eq!(client.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn malformed_status_lines_are_invalid() {
+        assert!(check_status_line("HTTP/1.1 101 Switching Protocols\r\n").is_ok());
+        for response in [
+            "",
+            "HTTP/1.1\r\n",
+            "HTTP/1.1 abc OK\r\n",
+            "SIP/2.0 101 OK\r\n",
+        ] {
+            assert_eq!(
+                check_status_line(response).unwrap_err().kind(),
+                ErrorKind::InvalidData
+            );
+        }
+    }
+
+    #[test]
+    fn request_line_must_be_a_get() {
+        assert_eq!(request_path("GET /a?b HTTP/1.1\r\n"), Some("/a?b"));
+        assert_eq!(request_path("POST / HTTP/1.1\r\n"), None);
+        assert_eq!(request_path("GET HTTP/1.1\r\n"), None);
     }
 }
@@ -0,0 +1,149 @@
+//! Noticing peers that vanished without closing, by pinging them periodically.
+//!
+//! [`keepalive`] only sends *Ping*s, the receiving side reports the *Pong*s it gets
+//! through [`PongTracker::record`], and races the two so that a dead peer ends the loop.
+
+use std::{
+    io::ErrorKind,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::message::Message;
+
+/// How often [`keepalive`] is meant to ping, give or take the timeout.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+/// How long [`keepalive`] is meant to wait for a *Pong*.
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The latest *Ping* answered, shared between the receiving side and [`keepalive`].
+#[derive(Debug, Clone, Default)]
+pub struct PongTracker(Arc<AtomicU64>);
+
+impl PongTracker {
+    /// Records a received *Pong*. Ones that weren't sent by [`keepalive`] are ignored.
+    pub fn record(&self, payload: &[u8]) {
+        if let Ok(seq) = payload.try_into().map(u64::from_be_bytes) {
+            self.0.fetch_max(seq, Ordering::Relaxed);
+        }
+    }
+
+    fn answered(&self, seq: u64) -> bool {
+        self.0.load(Ordering::Relaxed) >= seq
+    }
+}
+
+/// Sends a *Ping* through `ping` every `interval`, and checks that `pongs` got an answer
+/// `timeout` later.
+///
+/// Never finishes while the peer keeps answering, so it's meant to be raced against
+/// receiving. Resolves to `Ok` once `ping` returns `false`, i.e. the connection is gone
+/// anyway.
+///
+/// # Errors
+///
+/// Fails with [`ErrorKind::TimedOut`] once a *Ping* goes unanswered.
+pub async fn keepalive<F, Fut>(
+    mut ping: F,
+    pongs: &PongTracker,
+    interval: Duration,
+    timeout: Duration,
+) -> std::io::Result<()>
+where
+    F: FnMut(Message) -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut seq = 0u64;
+    loop {
+        tokio::time::sleep(interval).await;
+        seq += 1;
+        if !ping(Message::Ping(seq.to_be_bytes().to_vec())).await {
+            return Ok(());
+        }
+        tokio::time::sleep(timeout).await;
+        if !pongs.answered(seq) {
+            return Err(ErrorKind::TimedOut.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{future::ready, io::ErrorKind, time::Duration};
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::{PongTracker, keepalive};
+    use crate::message::Message;
+
+    const INTERVAL: Duration = Duration::from_millis(5);
+    const TIMEOUT: Duration = Duration::from_millis(20);
+
+    #[tokio::test]
+    async fn answered_pings_keep_it_going() {
+        let pongs = PongTracker::default();
+        let (tx, mut rx) = unbounded_channel();
+        let echo = {
+            let pongs = pongs.clone();
+            async move {
+                while let Some(Message::Ping(payload)) = rx.recv().await {
+                    pongs.record(&payload);
+                }
+            }
+        };
+        let pinging = keepalive(
+            |ping| ready(tx.send(ping).is_ok()),
+            &pongs,
+            INTERVAL,
+            TIMEOUT,
+        );
+        tokio::select! {
+            _ = pinging => panic!("answered pings shouldn't time out"),
+            () = echo => panic!("pings stopped"),
+            () = tokio::time::sleep(TIMEOUT * 4) => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn unanswered_ping_times_out() {
+        let pongs = PongTracker::default();
+        let (tx, _rx) = unbounded_channel();
+        let result = keepalive(
+            |ping| ready(tx.send(ping).is_ok()),
+            &pongs,
+            INTERVAL,
+            TIMEOUT,
+        )
+        .await;
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn stale_pongs_dont_count() {
+        let pongs = PongTracker::default();
+        let (tx, _rx) = unbounded_channel();
+        pongs.record(&0u64.to_be_bytes());
+        pongs.record(b"not one of ours");
+        let result = keepalive(
+            |ping| ready(tx.send(ping).is_ok()),
+            &pongs,
+            INTERVAL,
+            TIMEOUT,
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn finishes_once_the_connection_is_gone() {
+        let pongs = PongTracker::default();
+        assert!(
+            keepalive(|_| ready(false), &pongs, INTERVAL, TIMEOUT)
+                .await
+                .is_ok()
+        );
+    }
+}
@@ -1,15 +1,27 @@
 #![warn(clippy::pedantic)]
 
+#[cfg(feature = "deflate")]
+pub mod deflate;
 pub mod frame;
 pub mod handshake;
+pub mod keepalive;
 pub mod message;
+#[cfg(feature = "futures")]
+pub mod stream;
+#[cfg(test)]
+mod testing;
 
-use frame::{Frame, FrameHeader, PayloadLen};
-use message::MessageError;
-use std::{io::ErrorKind, marker::PhantomData};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use frame::{Frame, FrameHeader, Opcode, PayloadLen};
+use message::{MessageError, Utf8Validator, merge_frames};
+use std::{io::ErrorKind, marker::PhantomData, net::SocketAddr, time::Duration};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf,
+};
 
-use crate::message::{Message, StatusCode};
+use crate::message::{Message, Received, StatusCode};
+
+/// How long [`WsStream::close`] waits for the peer to echo the *Close* frame.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub trait UnpinReader: AsyncReadExt + Unpin {}
 impl<T: AsyncReadExt + Unpin> UnpinReader for T {}
@@ -18,38 +30,260 @@ impl<T: AsyncWriteExt + Unpin> UnpinWriter for T {}
 pub trait UnpinStream: UnpinReader + UnpinWriter {}
 impl<T: UnpinReader + UnpinWriter> UnpinStream for T {}
 
+/// Max size of the HTTP head [`read_http_bytes`] accepts.
+const MAX_HTTP_HEAD_SIZE: usize = 8 * 1024;
+
 /// Read HTTP headers separated by *\r\n*.
-/// Stop when encountering an empty line.
-async fn read_http_bytes<R>(stream: &mut R) -> std::io::Result<Vec<u8>>
+/// Stop when encountering an empty line, or fail past [`MAX_HTTP_HEAD_SIZE`].
+/// Whatever follows the head stays buffered in `reader`.
+async fn read_http_bytes<R>(reader: &mut R) -> std::io::Result<Vec<u8>>
 where
-    R: UnpinReader,
+    R: AsyncBufRead + Unpin,
 {
-    // PERF: Look into [BufReader]
-    let mut reader = BufReader::new(stream);
     let mut buf = String::new();
     loop {
-        let n = reader.read_line(&mut buf).await?;
+        // bounded, so that a single endless line can't grow `buf` either
+        let limit = (MAX_HTTP_HEAD_SIZE + 1 - buf.len()) as u64;
+        let n = (&mut *reader).take(limit).read_line(&mut buf).await?;
         if n == 0 {
             Err(ErrorKind::UnexpectedEof)?;
         }
-        if &buf[buf.len() - 4..] == "\r\n\r\n" {
+        if buf.ends_with("\r\n\r\n") {
             break;
         }
+        if buf.len() > MAX_HTTP_HEAD_SIZE {
+            Err(ErrorKind::InvalidData)?;
+        }
     }
     Ok(buf.into_bytes())
 }
 
+/// Size limits enforced while receiving, so that a peer can't make us allocate
+/// whatever length it advertises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvLimits {
+    /// Max payload of a single frame, checked before allocating it.
+    pub max_frame_size: usize,
+    /// Max payload of a whole message, summed across its fragments.
+    pub max_message_size: usize,
+}
+
+impl Default for RecvLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 16 * 1024 * 1024,
+            max_message_size: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Everything tunable about a connection, see [`WsStream::from_stream_with_config`].
+///
+/// ```
+/// # use std::time::Duration;
+/// # use websocket::WsConfig;
+/// let config = WsConfig::default()
+///     .with_max_message_size(1024 * 1024)
+///     .with_handshake_timeout(Duration::from_secs(5));
+/// assert_eq!(config.limits().max_message_size, 1024 * 1024);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WsConfig {
+    limits: RecvLimits,
+    handshake_timeout: Duration,
+    keepalive_interval: Duration,
+    keepalive_timeout: Duration,
+    compression: bool,
+    strict_lengths: bool,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            limits: RecvLimits::default(),
+            handshake_timeout: handshake::HANDSHAKE_TIMEOUT,
+            keepalive_interval: keepalive::KEEPALIVE_INTERVAL,
+            keepalive_timeout: keepalive::KEEPALIVE_TIMEOUT,
+            compression: true,
+            strict_lengths: false,
+        }
+    }
+}
+
+impl WsConfig {
+    #[must_use]
+    pub fn with_limits(mut self, limits: RecvLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.limits.max_frame_size = max_frame_size;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.limits.max_message_size = max_message_size;
+        self
+    }
+
+    /// Replaces the timeout of [`IntoWebsocket::try_upgrade`](handshake::IntoWebsocket::try_upgrade).
+    #[must_use]
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Replaces what's meant to be passed to [`keepalive::keepalive`].
+    #[must_use]
+    pub fn with_keepalive(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.keepalive_interval = interval;
+        self.keepalive_timeout = timeout;
+        self
+    }
+
+    /// Whether `permessage-deflate` is offered or accepted during the handshake.
+    /// Has no effect without the `deflate` feature.
+    #[must_use]
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Whether incoming frames with a length that could've been encoded in a smaller form
+    /// fail the connection, see [`Frame::parse_strict`].
+    /// Off by default, as lenient parsing interoperates with more peers.
+    #[must_use]
+    pub fn with_strict_lengths(mut self, strict: bool) -> Self {
+        self.strict_lengths = strict;
+        self
+    }
+
+    #[must_use]
+    pub fn limits(&self) -> RecvLimits {
+        self.limits
+    }
+
+    #[must_use]
+    pub fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    #[must_use]
+    pub fn keepalive_interval(&self) -> Duration {
+        self.keepalive_interval
+    }
+
+    #[must_use]
+    pub fn keepalive_timeout(&self) -> Duration {
+        self.keepalive_timeout
+    }
+
+    #[must_use]
+    pub fn compression(&self) -> bool {
+        self.compression
+    }
+
+    #[must_use]
+    pub fn strict_lengths(&self) -> bool {
+        self.strict_lengths
+    }
+}
+
+/// Per-message extension state of a receiving half, negotiated during the handshake.
+#[derive(Debug, Default)]
+struct RecvExtensions {
+    #[cfg(feature = "deflate")]
+    inflater: Option<deflate::Inflater>,
+}
+
+impl RecvExtensions {
+    /// RSV bits incoming frames may have set.
+    #[cfg_attr(not(feature = "deflate"), allow(clippy::unused_self))]
+    fn allowed_rsv(&self) -> u8 {
+        #[cfg(feature = "deflate")]
+        if self.inflater.is_some() {
+            return deflate::RSV_COMPRESSED;
+        }
+        0
+    }
+
+    /// Undoes whatever was applied to a whole message, `frame` being all of its fragments merged.
+    #[cfg_attr(
+        not(feature = "deflate"),
+        allow(
+            unused_mut,
+            unused_variables,
+            clippy::unused_self,
+            clippy::unnecessary_wraps
+        )
+    )]
+    fn decode(&mut self, mut frame: Frame, limits: RecvLimits) -> Result<Frame, MessageError> {
+        #[cfg(feature = "deflate")]
+        if let Some(inflater) = &mut self.inflater {
+            inflater.inflate_frame(&mut frame, limits.max_message_size)?;
+        }
+        Ok(frame)
+    }
+}
+
+/// Per-message extension state of a sending half, negotiated during the handshake.
+#[derive(Debug, Default)]
+struct SendExtensions {
+    #[cfg(feature = "deflate")]
+    deflater: Option<deflate::Deflater>,
+}
+
+impl SendExtensions {
+    /// Applies the extensions to a whole message, before masking.
+    #[cfg_attr(
+        not(feature = "deflate"),
+        allow(unused_variables, clippy::unused_self, clippy::unnecessary_wraps)
+    )]
+    fn encode(&mut self, frame: &mut Frame) -> std::io::Result<()> {
+        #[cfg(feature = "deflate")]
+        if let Some(deflater) = &mut self.deflater {
+            deflater.deflate_frame(frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fragments of a data message received so far. Kept in the receiving half, so that
+/// control frames interleaved with them can be returned without losing the message.
+#[derive(Debug, Default)]
+struct PartialMessage {
+    frames: Vec<Frame>,
+    size: usize,
+    utf8: Utf8Validator,
+}
+
+/// Inner error of the [`ErrorKind::InvalidData`] returned for frames
+/// over [`RecvLimits::max_frame_size`].
+#[derive(Debug)]
+struct FrameTooBig;
+
+impl std::fmt::Display for FrameTooBig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("frame payload exceeds the max frame size")
+    }
+}
+
+impl std::error::Error for FrameTooBig {}
+
 /// Read first 2 bytes, determine length, read additional 0/2/8 bytes.
 /// Read until exactly that many bytes are read + masking key.
-async fn read_frame_bytes<R>(stream: &mut R) -> std::io::Result<Vec<u8>>
+/// Fail before allocating if the payload is longer than `max_frame_size`.
+async fn read_frame_bytes<R>(stream: &mut R, max_frame_size: usize) -> std::io::Result<Vec<u8>>
 where
     R: UnpinReader,
 {
     let mut header_buf = [0u8; 2];
     stream.read_exact(&mut header_buf).await?;
-    let header: FrameHeader = header_buf[..]
-        .try_into()
-        .map_err(|_| ErrorKind::InvalidData)?;
+    // RSV bits are validated once the whole frame is parsed, only the length matters here
+    let header = FrameHeader::parse(&header_buf, 0b111).map_err(|_| ErrorKind::InvalidData)?;
 
     let mut payload_buf = [0u8; 8];
     let payload_len_bytes: usize;
@@ -66,14 +300,22 @@ where
         PayloadLen::HintU64 => {
             payload_len_bytes = 8;
             stream.read_exact(&mut payload_buf).await?;
+            // the most significant bit must be 0 (RFC 6455, section 5.2)
+            if payload_buf[0] & 0x80 != 0 {
+                return Err(ErrorKind::InvalidData.into());
+            }
             u64::from_be_bytes(payload_buf)
         }
         _ => unreachable!(),
     };
+    let payload_len = usize::try_from(payload_len)
+        .ok()
+        .filter(|len| *len <= max_frame_size)
+        .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, FrameTooBig))?;
 
     let frame_len: usize = 2 + payload_len_bytes + if header.masked { 4 } else { 0 };
-    #[allow(clippy::cast_possible_truncation)]
-    let mut frame_vec = vec![0u8; frame_len + payload_len as usize];
+    let mut frame_vec = vec![0u8; frame_len + payload_len];
+    frame_vec[2..2 + payload_len_bytes].copy_from_slice(&payload_buf[..payload_len_bytes]);
 
     let mut masked_key_buf = payload_buf;
     if header.masked {
@@ -86,13 +328,27 @@ where
     Ok(frame_vec)
 }
 
-pub trait Side {}
+/// The peer on the other end of a [`WsStream`], which decides the masking rules:
+/// `WsStream<Server, _>` talks to a server, so it's the client end, and vice versa.
+pub trait Side {
+    /// Whether frames sent to this peer are masked, as clients must do.
+    const MASK_OUTGOING: bool;
+    /// Whether frames received from this peer are masked, and are to be unmasked.
+    /// Unmasked ones are then a protocol error.
+    const UNMASK_INCOMING: bool;
+}
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Server;
-impl Side for Server {}
+impl Side for Server {
+    const MASK_OUTGOING: bool = true;
+    const UNMASK_INCOMING: bool = false;
+}
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct Client;
-impl Side for Client {}
+impl Side for Client {
+    const MASK_OUTGOING: bool = false;
+    const UNMASK_INCOMING: bool = true;
+}
 
 #[derive(Debug)]
 pub struct WsStream<S: Side, T: UnpinStream> {
@@ -101,133 +357,360 @@ pub struct WsStream<S: Side, T: UnpinStream> {
 }
 
 impl<S: Side, T: UnpinStream> WsStream<S, T> {
+    /// Same as [`WsStream::from_stream_with_config`] with the default [`WsConfig`].
     pub fn from_stream(stream: T) -> WsStream<S, T> {
+        Self::from_stream_with_config(stream, WsConfig::default())
+    }
+
+    /// Wraps `stream`, not upgraded yet, to be used with `config`.
+    pub fn from_stream_with_config(stream: T, config: WsConfig) -> WsStream<S, T> {
         let (rx, tx) = tokio::io::split(stream);
         WsStream {
-            rx: WsRecvHalf(rx, PhantomData::<S>),
-            tx: WsSendHalf(tx, PhantomData::<S>),
+            rx: WsRecvHalf(
+                BufReader::new(rx),
+                PhantomData::<S>,
+                config,
+                RecvExtensions::default(),
+                PartialMessage::default(),
+                None,
+            ),
+            tx: WsSendHalf(tx, PhantomData::<S>, SendExtensions::default(), None),
         }
     }
 
+    /// Remembers the address of the peer, for when `T` doesn't tell it by itself,
+    /// e.g. a TLS stream over TCP.
+    /// Both halves keep it after [`WsStream::into_split`].
+    #[must_use]
+    pub fn with_peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.rx.5 = Some(addr);
+        self.tx.3 = Some(addr);
+        self
+    }
+
+    /// The address given to [`WsStream::with_peer_addr`], if any.
+    #[must_use]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.rx.peer_addr()
+    }
+
     #[must_use]
     pub fn into_split(self) -> (WsRecvHalf<S, T>, WsSendHalf<S, T>) {
         (self.rx, self.tx)
     }
+
+    /// Undoes [`WsStream::into_split`]. Whatever was buffered or negotiated
+    /// by either half is kept.
+    ///
+    /// # Errors
+    /// If the halves weren't split off the same stream, handing both back.
+    // the halves are handed back by value, same as tokio's own `reunite`
+    #[allow(clippy::result_large_err)]
+    pub fn reunite(
+        rx: WsRecvHalf<S, T>,
+        tx: WsSendHalf<S, T>,
+    ) -> Result<WsStream<S, T>, ReuniteError<S, T>> {
+        if rx.0.get_ref().is_pair_of(&tx.0) {
+            Ok(WsStream { rx, tx })
+        } else {
+            Err(ReuniteError(rx, tx))
+        }
+    }
+
+    /// Replaces the [`RecvLimits`] of the receiving half.
+    #[must_use]
+    pub fn with_limits(mut self, limits: RecvLimits) -> Self {
+        self.rx = self.rx.with_limits(limits);
+        self
+    }
+
+    /// Performs the closing handshake, waiting up to [`CLOSE_TIMEOUT`] for the peer's echo.
+    /// See [`WsSend::close_handshake`].
+    ///
+    /// # Errors
+    /// If sending the *Close* frame or shutting the stream down fails.
+    pub async fn close(
+        &mut self,
+        code: Option<StatusCode>,
+        reason: Option<String>,
+    ) -> std::io::Result<Option<StatusCode>> {
+        self.close_with_timeout(code, reason, CLOSE_TIMEOUT).await
+    }
+
+    /// Same as [`WsStream::close`], but waits up to `timeout` for the peer's echo.
+    ///
+    /// # Errors
+    /// If sending the *Close* frame or shutting the stream down fails.
+    pub async fn close_with_timeout(
+        &mut self,
+        code: Option<StatusCode>,
+        reason: Option<String>,
+        timeout: Duration,
+    ) -> std::io::Result<Option<StatusCode>> {
+        self.tx
+            .close_handshake(&mut self.rx, code, reason, timeout)
+            .await
+    }
 }
 
+/// Reads through a [`BufReader`], so that frame headers and small payloads
+/// don't cost a read on the underlying stream each.
+#[derive(Debug)]
+pub struct WsRecvHalf<S: Side, T: UnpinStream>(
+    pub BufReader<ReadHalf<T>>,
+    PhantomData<S>,
+    WsConfig,
+    RecvExtensions,
+    PartialMessage,
+    Option<SocketAddr>,
+);
 #[derive(Debug)]
-pub struct WsRecvHalf<S: Side, T: UnpinStream>(pub ReadHalf<T>, PhantomData<S>);
+pub struct WsSendHalf<S: Side, T: UnpinStream>(
+    pub WriteHalf<T>,
+    PhantomData<S>,
+    SendExtensions,
+    Option<SocketAddr>,
+);
+
+impl<S: Side, T: UnpinStream> WsRecvHalf<S, T> {
+    /// Replaces the [`RecvLimits`] enforced by [`WsRecv::receive`].
+    #[must_use]
+    pub fn with_limits(mut self, limits: RecvLimits) -> Self {
+        self.2 = self.2.with_limits(limits);
+        self
+    }
+
+    #[must_use]
+    pub fn limits(&self) -> RecvLimits {
+        self.2.limits
+    }
+
+    /// The [`WsConfig`] the stream was created with.
+    #[must_use]
+    pub fn config(&self) -> WsConfig {
+        self.2
+    }
+
+    /// See [`WsStream::peer_addr`].
+    #[must_use]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.5
+    }
+}
+
+impl<S: Side, T: UnpinStream> WsSendHalf<S, T> {
+    /// See [`WsStream::peer_addr`].
+    #[must_use]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.3
+    }
+}
+
+/// Error of [`WsStream::reunite`], holding the halves that didn't match.
 #[derive(Debug)]
-pub struct WsSendHalf<S: Side, T: UnpinStream>(pub WriteHalf<T>, PhantomData<S>);
+pub struct ReuniteError<S: Side, T: UnpinStream>(pub WsRecvHalf<S, T>, pub WsSendHalf<S, T>);
+
+impl<S: Side, T: UnpinStream> std::fmt::Display for ReuniteError<S, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("tried to reunite halves that are not from the same stream")
+    }
+}
+
+impl<S: Side + std::fmt::Debug, T: UnpinStream + std::fmt::Debug> std::error::Error
+    for ReuniteError<S, T>
+{
+}
+
+/// Malformed headers are the peer's fault, anything else means the connection is gone.
+fn read_error_status(error: &std::io::Error) -> MessageError {
+    MessageError::ProtocolViolated(match error.get_ref() {
+        Some(inner) if inner.is::<FrameTooBig>() => StatusCode::MessageTooBig,
+        _ if error.kind() == ErrorKind::InvalidData => StatusCode::ProtocolError,
+        _ => StatusCode::CloseAbnormal,
+    })
+}
 
 #[allow(async_fn_in_trait)]
 pub trait WsSend {
     async fn send_raw(&mut self, data: &[u8]) -> std::io::Result<()>;
     async fn send(&mut self, message: Message) -> std::io::Result<()>;
+    /// Same as [`WsSend::send`], but splits the message into frames carrying up to
+    /// `chunk_size` bytes of payload each, see [`Frame::fragment`].
+    /// Control messages are never fragmented.
+    async fn send_fragmented(&mut self, message: Message, chunk_size: usize)
+    -> std::io::Result<()>;
+    /// Shuts the underlying writer down, e.g. sending TCP *FIN*.
+    async fn shutdown(&mut self) -> std::io::Result<()>;
+
+    /// Sends a *Close* frame, starting the closing handshake.
+    /// Without a `code`, closes with [`StatusCode::Normal`], as in a clean quit.
+    async fn close(
+        &mut self,
+        code: Option<StatusCode>,
+        reason: Option<String>,
+    ) -> std::io::Result<()> {
+        self.send(Message::Close(code.unwrap_or_default(), reason))
+            .await
+    }
+
+    /// Sends a *Close* frame, then reads from `rx` until the peer echoes it, the read fails,
+    /// or `timeout` runs out, and shuts the writer down afterwards.
+    /// Anything but *Close* received in the meantime is discarded.
+    ///
+    /// Returns the status code the peer echoed, if it did.
+    ///
+    /// # Errors
+    /// If sending the *Close* frame or shutting down fails.
+    async fn close_handshake<R: WsRecv>(
+        &mut self,
+        rx: &mut R,
+        code: Option<StatusCode>,
+        reason: Option<String>,
+        timeout: Duration,
+    ) -> std::io::Result<Option<StatusCode>> {
+        self.close(code, reason).await?;
+        let echo = tokio::time::timeout(timeout, async {
+            loop {
+                match rx.receive().await {
+                    Ok(Message::Close(code, _)) => break Some(code),
+                    Ok(_) => {}
+                    Err(_) => break None,
+                }
+            }
+        })
+        .await
+        .ok()
+        .flatten();
+        self.shutdown().await?;
+        Ok(echo)
+    }
 }
 
 #[allow(async_fn_in_trait)]
 pub trait WsRecv {
     async fn read_http_bytes(&mut self) -> std::io::Result<Vec<u8>>;
     async fn read_frame_bytes(&mut self) -> std::io::Result<Vec<u8>>;
+    /// Receives the next message, reassembling fragmented ones.
+    /// Control messages interleaved with fragments are returned as they arrive,
+    /// and the fragmented one is then returned by a later call, once complete.
     async fn receive(&mut self) -> Result<Message, MessageError>;
-}
-
-// TODO: Fix essentially duplicate implementations. Can I make a default implementation
-// of `read_http_bytes`, `read_frame_bytes`, `receive` and `send` somewhere else than module level
-// to make minor changes in individual impls?
-
-impl<T: UnpinStream> WsRecv for WsRecvHalf<Server, T> {
-    async fn read_http_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        read_http_bytes(&mut self.0).await
-    }
 
-    async fn read_frame_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        read_frame_bytes(&mut self.0).await
+    /// Same as [`WsRecv::receive`], but a *Close* frame and the connection dropping
+    /// are told apart from other messages and from errors.
+    ///
+    /// # Errors
+    /// Only if the peer violated the protocol, with the code to close the connection with.
+    async fn receive_event(&mut self) -> Result<Received, MessageError> {
+        match self.receive().await {
+            Ok(Message::Close(code, reason)) => Ok(Received::Closed(code, reason)),
+            Ok(message) => Ok(Received::Message(message)),
+            Err(MessageError::ProtocolViolated(StatusCode::CloseAbnormal)) => {
+                Ok(Received::Abnormal)
+            }
+            Err(e) => Err(e),
+        }
     }
 
-    async fn receive(&mut self) -> Result<Message, MessageError> {
-        let mut frames: Vec<Frame> = vec![];
+    /// Same as [`WsRecv::receive`], but answers every *Ping* with a *Pong* carrying the same
+    /// payload through `tx`, and swallows *Pong*s. Only *Text*, *Binary* and *Close* are returned.
+    ///
+    /// The *Pong* is sent before the next frame is read, so a slow `tx` stalls receiving too.
+    /// On the bright side, a peer flooding pings is never buffered, only answered one by one.
+    async fn receive_with_control<W: WsSend>(
+        &mut self,
+        tx: &mut W,
+    ) -> Result<Message, MessageError> {
         loop {
-            let data = self
-                .read_frame_bytes()
-                .await
-                .map_err(|_| MessageError::ProtocolViolated(StatusCode::CloseAbnormal))?;
-            let frame: Frame = data
-                .try_into()
-                .map_err(|_| MessageError::ProtocolViolated(StatusCode::ProtocolError))?;
-            let fin = frame.header.fin;
-
-            // avoid first allocation
-            if frames.is_empty() && fin {
-                return frame.try_into();
-            }
-
-            frames.push(frame);
-
-            if fin {
-                break;
+            match self.receive().await? {
+                Message::Ping(payload) => tx
+                    .send(Message::Pong(payload))
+                    .await
+                    .map_err(|e| read_error_status(&e))?,
+                Message::Pong(_) => {}
+                message => return Ok(message),
             }
         }
-        frames.try_into()
     }
 }
 
-impl<T: UnpinStream> WsSend for WsSendHalf<Server, T> {
-    async fn send_raw(&mut self, data: &[u8]) -> std::io::Result<()> {
-        self.0.write_all(data).await?;
-        self.0.flush().await?;
-        Ok(())
-    }
-
-    async fn send(&mut self, message: Message) -> std::io::Result<()> {
-        let binary: Vec<u8> = {
-            let mut frame: Frame = message.into();
-            frame.mask();
-            frame.into()
-        };
-        self.send_raw(&binary).await
+/// Masks an outgoing `frame` if the peer expects it, or strips the masking key otherwise.
+fn apply_masking<S: Side>(frame: &mut Frame) {
+    if S::MASK_OUTGOING {
+        frame.mask();
+    } else {
+        frame.header.masked = false;
+        frame.masking_key = None;
     }
 }
 
-impl<T: UnpinStream> WsRecv for WsRecvHalf<Client, T> {
+impl<S: Side, T: UnpinStream> WsRecv for WsRecvHalf<S, T> {
     async fn read_http_bytes(&mut self) -> std::io::Result<Vec<u8>> {
         read_http_bytes(&mut self.0).await
     }
 
     async fn read_frame_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        read_frame_bytes(&mut self.0).await
+        read_frame_bytes(&mut self.0, self.2.limits.max_frame_size).await
     }
 
     async fn receive(&mut self) -> Result<Message, MessageError> {
-        let mut frames: Vec<Frame> = vec![];
+        // dropped on errors, the connection is failed anyway
+        let mut partial = std::mem::take(&mut self.4);
         loop {
             let data = self
                 .read_frame_bytes()
                 .await
-                .map_err(|_| MessageError::ProtocolViolated(StatusCode::CloseAbnormal))?;
-            let mut frame: Frame = data
-                .try_into()
+                .map_err(|e| read_error_status(&e))?;
+            let parse = if self.2.strict_lengths {
+                Frame::parse_strict
+            } else {
+                Frame::parse
+            };
+            let mut frame = parse(&data, self.3.allowed_rsv())
                 .map_err(|_| MessageError::ProtocolViolated(StatusCode::ProtocolError))?;
-            frame.mask();
+            // clients must mask every frame they send, servers must not (RFC 6455, section 5.1)
+            if frame.header.masked != S::UNMASK_INCOMING {
+                return Err(MessageError::ProtocolViolated(StatusCode::ProtocolError));
+            }
+            frame.unmask();
+            // never fragmented, so they can't be part of the message in progress
+            if frame.header.opcode.is_control() {
+                self.4 = partial;
+                return self.3.decode(frame, self.2.limits)?.try_into();
+            }
+            // only *Continue* frames continue a message, and only they do
+            if partial.frames.is_empty() == (frame.header.opcode == Opcode::Continue) {
+                return Err(MessageError::ProtocolViolated(StatusCode::ProtocolError));
+            }
             let fin = frame.header.fin;
+            partial.size += frame.payload.len();
+            if partial.size > self.2.limits.max_message_size {
+                return Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig));
+            }
 
             // avoid first allocation
-            if frames.is_empty() && fin {
-                return frame.try_into();
+            if partial.frames.is_empty() && fin {
+                return self.3.decode(frame, self.2.limits)?.try_into();
+            }
+
+            // compressed text can only be validated once inflated
+            let first = partial.frames.first().unwrap_or(&frame).header;
+            if first.opcode == Opcode::Text && first.rsv == 0 {
+                partial.utf8.feed(&frame.payload)?;
             }
 
-            frames.push(frame);
+            partial.frames.push(frame);
 
             if fin {
                 break;
             }
         }
-        frames.try_into()
+        self.3
+            .decode(merge_frames(partial.frames)?, self.2.limits)?
+            .try_into()
     }
 }
 
-impl<T: UnpinStream> WsSend for WsSendHalf<Client, T> {
+impl<S: Side, T: UnpinStream> WsSend for WsSendHalf<S, T> {
     async fn send_raw(&mut self, data: &[u8]) -> std::io::Result<()> {
         self.0.write_all(data).await?;
         self.0.flush().await?;
@@ -236,14 +719,34 @@ impl<T: UnpinStream> WsSend for WsSendHalf<Client, T> {
 
     async fn send(&mut self, message: Message) -> std::io::Result<()> {
         let binary: Vec<u8> = {
-            let frame: Frame = message.into();
+            let mut frame: Frame = message.into();
+            self.2.encode(&mut frame)?;
+            apply_masking::<S>(&mut frame);
             frame.into()
         };
         self.send_raw(&binary).await
     }
+
+    async fn send_fragmented(
+        &mut self,
+        message: Message,
+        chunk_size: usize,
+    ) -> std::io::Result<()> {
+        let mut frame: Frame = message.into();
+        self.2.encode(&mut frame)?;
+        for mut fragment in frame.fragment(chunk_size) {
+            apply_masking::<S>(&mut fragment);
+            self.send_raw(&Vec::from(fragment)).await?;
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.0.shutdown().await
+    }
 }
 
-impl<T: UnpinStream> WsRecv for WsStream<Server, T> {
+impl<S: Side, T: UnpinStream> WsRecv for WsStream<S, T> {
     async fn read_http_bytes(&mut self) -> std::io::Result<Vec<u8>> {
         self.rx.read_http_bytes().await
     }
@@ -257,7 +760,7 @@ impl<T: UnpinStream> WsRecv for WsStream<Server, T> {
     }
 }
 
-impl<T: UnpinStream> WsSend for WsStream<Server, T> {
+impl<S: Side, T: UnpinStream> WsSend for WsStream<S, T> {
     async fn send_raw(&mut self, data: &[u8]) -> std::io::Result<()> {
         self.tx.send_raw(data).await
     }
@@ -265,28 +768,486 @@ impl<T: UnpinStream> WsSend for WsStream<Server, T> {
     async fn send(&mut self, message: Message) -> std::io::Result<()> {
         self.tx.send(message).await
     }
+
+    async fn send_fragmented(
+        &mut self,
+        message: Message,
+        chunk_size: usize,
+    ) -> std::io::Result<()> {
+        self.tx.send_fragmented(message, chunk_size).await
+    }
+
+    async fn shutdown(&mut self) -> std::io::Result<()> {
+        self.tx.shutdown().await
+    }
 }
 
-impl<T: UnpinStream> WsRecv for WsStream<Client, T> {
-    async fn read_http_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        self.rx.read_http_bytes().await
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, duplex};
+
+    use std::io::ErrorKind;
+
+    use crate::{
+        Client, MAX_HTTP_HEAD_SIZE, RecvLimits, ReuniteError, Server, WsConfig, WsRecv, WsSend,
+        WsStream,
+        message::{Message, MessageError, Received, StatusCode},
+        read_http_bytes,
+    };
+
+    #[tokio::test]
+    async fn pings_are_answered_transparently() {
+        let (client_end, server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        server.send(Message::Ping(vec![1, 2, 3])).await.unwrap();
+        server.send(Message::Pong(vec![4])).await.unwrap();
+        server.send(Message::text("hello")).await.unwrap();
+        server.tx.close(None, None).await.unwrap();
+
+        let (rx, tx) = (&mut client.rx, &mut client.tx);
+        assert_eq!(
+            rx.receive_with_control(tx).await.ok(),
+            Some(Message::text("hello"))
+        );
+        assert_eq!(
+            rx.receive_with_control(tx).await.ok(),
+            Some(Message::Close(StatusCode::Normal, None))
+        );
+        assert_eq!(
+            server.receive().await.ok(),
+            Some(Message::Pong(vec![1, 2, 3]))
+        );
     }
 
-    async fn read_frame_bytes(&mut self) -> std::io::Result<Vec<u8>> {
-        self.rx.read_frame_bytes().await
+    #[tokio::test]
+    async fn reunited_halves_keep_working() {
+        let (client_end, server_end) = duplex(1024);
+        let (rx, tx) = WsStream::<Server, _>::from_stream(client_end).into_split();
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        let mut client = WsStream::reunite(rx, tx).unwrap();
+        client.send(Message::Binary(vec![1])).await.unwrap();
+        assert_eq!(server.receive().await.ok(), Some(Message::Binary(vec![1])));
     }
 
-    async fn receive(&mut self) -> Result<Message, MessageError> {
-        self.rx.receive().await
+    #[tokio::test]
+    async fn peer_addr_is_kept_by_both_halves() {
+        let (client_end, _server_end) = duplex(1024);
+        let client = WsStream::<Server, _>::from_stream(client_end);
+        assert_eq!(client.peer_addr(), None);
+
+        let addr = "127.0.0.1:1337".parse().unwrap();
+        let (rx, tx) = client.with_peer_addr(addr).into_split();
+        assert_eq!(rx.peer_addr(), Some(addr));
+        assert_eq!(tx.peer_addr(), Some(addr));
+        assert_eq!(WsStream::reunite(rx, tx).unwrap().peer_addr(), Some(addr));
     }
-}
 
-impl<T: UnpinStream> WsSend for WsStream<Client, T> {
-    async fn send_raw(&mut self, data: &[u8]) -> std::io::Result<()> {
-        self.tx.send_raw(data).await
+    #[tokio::test]
+    async fn halves_of_different_streams_dont_reunite() {
+        let (a, _) = duplex(1024);
+        let (b, _) = duplex(1024);
+        let (rx, _) = WsStream::<Server, _>::from_stream(a).into_split();
+        let (_, tx) = WsStream::<Server, _>::from_stream(b).into_split();
+
+        let Err(ReuniteError(rx, tx)) = WsStream::reunite(rx, tx) else {
+            panic!("halves of different streams were reunited");
+        };
+        // handed back intact
+        assert!(!rx.0.get_ref().is_pair_of(&tx.0));
     }
 
-    async fn send(&mut self, message: Message) -> std::io::Result<()> {
-        self.tx.send(message).await
+    #[tokio::test]
+    async fn close_and_dropped_connection_are_told_apart() {
+        let (client_end, server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        server.send(Message::text("hi")).await.unwrap();
+        server
+            .send(Message::Close(
+                StatusCode::GoingAway,
+                Some(String::from("restarting")),
+            ))
+            .await
+            .unwrap();
+        drop(server);
+
+        assert_eq!(
+            client.receive_event().await.ok(),
+            Some(Received::Message(Message::text("hi")))
+        );
+        assert_eq!(
+            client.receive_event().await.ok(),
+            Some(Received::Closed(
+                StatusCode::GoingAway,
+                Some(String::from("restarting"))
+            ))
+        );
+        assert_eq!(client.receive_event().await.ok(), Some(Received::Abnormal));
+    }
+
+    #[tokio::test]
+    async fn configured_limits_are_enforced() {
+        let (client_end, server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream_with_config(
+            client_end,
+            WsConfig::default().with_max_message_size(8),
+        );
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        server.send(Message::Binary(vec![0; 8])).await.unwrap();
+        server
+            .send_fragmented(Message::Binary(vec![0; 9]), 4)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.receive().await.ok(),
+            Some(Message::Binary(vec![0; 8]))
+        );
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig))
+        ));
+        assert_eq!(client.rx.limits().max_message_size, 8);
+    }
+
+    #[tokio::test]
+    async fn close_waits_for_echo() {
+        let (client_end, server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+
+        let peer = tokio::spawn(async move {
+            server.send(Message::text("late")).await.unwrap();
+            let Ok(Message::Close(code, _)) = server.receive().await else {
+                panic!("expected a close frame");
+            };
+            server.tx.close(Some(code), None).await.unwrap();
+            server
+        });
+
+        assert_eq!(
+            client
+                .close(Some(StatusCode::GoingAway), None)
+                .await
+                .unwrap(),
+            Some(StatusCode::GoingAway)
+        );
+        // the write half is shut down afterwards
+        let mut server = peer.await.unwrap();
+        assert!(server.receive().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn huge_length_is_rejected_before_allocating() {
+        let (client_end, mut server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+
+        // fin, binary, unmasked, 64-bit length of 2^63 - 1
+        let mut header = vec![0b1000_0010, 127];
+        header.extend_from_slice(&((1_u64 << 63) - 1).to_be_bytes());
+        server_end.write_all(&header).await.unwrap();
+
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig))
+        ));
+    }
+
+    #[tokio::test]
+    async fn non_minimal_lengths_fail_only_when_strict() {
+        // fin, text, unmasked, 2 bytes in the 16-bit form
+        let frame = [0b1000_0001, 126, 0, 2, b'h', b'i'];
+        for strict in [false, true] {
+            let (client_end, mut server_end) = duplex(1024);
+            let config = WsConfig::default().with_strict_lengths(strict);
+            let mut client = WsStream::<Server, _>::from_stream_with_config(client_end, config);
+            server_end.write_all(&frame).await.unwrap();
+
+            let received = client.receive().await;
+            if strict {
+                assert!(matches!(
+                    received,
+                    Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+                ));
+            } else {
+                assert_eq!(received.ok(), Some(Message::text("hi")));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn length_with_the_top_bit_set_is_a_protocol_error() {
+        let (client_end, mut server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+
+        // fin, binary, unmasked, 64-bit length of 2^63
+        let mut header = vec![0b1000_0010, 127];
+        header.extend_from_slice(&0x8000_0000_0000_0000_u64.to_be_bytes());
+        server_end.write_all(&header).await.unwrap();
+
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fragments_count_towards_message_size() {
+        let (client_end, mut server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end).with_limits(RecvLimits {
+            max_frame_size: 4,
+            max_message_size: 5,
+        });
+
+        // unmasked text "abc", then a final continuation "def"
+        server_end
+            .write_all(&[0b0000_0001, 3, b'a', b'b', b'c'])
+            .await
+            .unwrap();
+        server_end
+            .write_all(&[0b1000_0000, 3, b'd', b'e', b'f'])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fragmented_message_is_reassembled() {
+        let (client_end, server_end) = duplex(64 * 1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        let payload: Vec<u8> = (0..=250).cycle().take(10 * 1024).collect();
+
+        client
+            .send_fragmented(Message::Binary(payload.clone()), 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            server.receive().await.ok(),
+            Some(Message::Binary(payload.clone()))
+        );
+
+        server
+            .send_fragmented(Message::Binary(payload.clone()), 1024)
+            .await
+            .unwrap();
+        assert_eq!(client.receive().await.ok(), Some(Message::Binary(payload)));
+    }
+
+    #[tokio::test]
+    async fn text_split_mid_codepoint_is_received() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        let euro = "€".as_bytes();
+        server_end.write_all(&[0x01, 0x02]).await.unwrap();
+        server_end.write_all(&euro[..2]).await.unwrap();
+        server_end.write_all(&[0x80, 0x01, euro[2]]).await.unwrap();
+
+        assert_eq!(client.receive().await.ok(), Some(Message::text("€")));
+    }
+
+    #[tokio::test]
+    async fn invalid_text_fails_before_the_last_fragment() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        // the final fragment never arrives
+        server_end
+            .write_all(&[0x01, 0x02, b'a', 0xff])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(
+                StatusCode::InvalidPayloadData
+            ))
+        ));
+    }
+
+    /// Feeds raw frames from a server, returning the first message received from them.
+    async fn receive_raw(bytes: &[u8]) -> Result<Message, MessageError> {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end.write_all(bytes).await.unwrap();
+        drop(server_end);
+        client.receive().await
+    }
+
+    #[tokio::test]
+    async fn invalid_text_and_oversized_frames_get_their_own_codes() {
+        assert!(matches!(
+            receive_raw(&[0x81, 0x02, b'a', 0xff]).await,
+            Err(MessageError::ProtocolViolated(
+                StatusCode::InvalidPayloadData
+            ))
+        ));
+        // advertises 1 TiB, over the default frame limit
+        assert!(matches!(
+            receive_raw(&[0x82, 0x7f, 0, 0, 0x01, 0, 0, 0, 0, 0]).await,
+            Err(MessageError::ProtocolViolated(StatusCode::MessageTooBig))
+        ));
+        // a 1-byte body can't hold a close code
+        assert!(matches!(
+            receive_raw(&[0x88, 0x01, 0x03]).await,
+            Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn frame_right_after_http_head_is_kept() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end
+            .write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n\x81\x02hi")
+            .await
+            .unwrap();
+
+        client.read_http_bytes().await.unwrap();
+        assert_eq!(client.receive().await.ok(), Some(Message::text("hi")));
+    }
+
+    #[tokio::test]
+    async fn fragments_out_of_sequence_are_rejected() {
+        let protocol_error = |result| {
+            matches!(
+                result,
+                Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+            )
+        };
+        // *Continue* with nothing to continue, final or not
+        assert!(protocol_error(receive_raw(&[0x80, 0x01, b'a']).await));
+        assert!(protocol_error(receive_raw(&[0x00, 0x01, b'a']).await));
+        // a new *Text* before the previous one is finished
+        assert!(protocol_error(
+            receive_raw(&[0x01, 0x01, b'a', 0x81, 0x01, b'b']).await
+        ));
+        // a new *Binary* in place of the last fragment
+        assert!(protocol_error(
+            receive_raw(&[0x01, 0x01, b'a', 0x82, 0x01, b'b']).await
+        ));
+    }
+
+    #[tokio::test]
+    async fn ping_between_fragments_is_returned_first() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end
+            .write_all(&[
+                0x01, 0x02, b'h', b'e', // first fragment
+                0x89, 0x01, b'p', // ping
+                0x80, 0x03, b'l', b'l', b'o', // last fragment
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(client.receive().await.ok(), Some(Message::Ping(vec![b'p'])));
+        assert_eq!(client.receive().await.ok(), Some(Message::text("hello")));
+    }
+
+    #[tokio::test]
+    async fn ping_between_fragments_is_answered() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end
+            .write_all(&[0x01, 0x01, b'a', 0x89, 0x00, 0x80, 0x01, b'b'])
+            .await
+            .unwrap();
+
+        let (rx, tx) = (&mut client.rx, &mut client.tx);
+        assert_eq!(
+            rx.receive_with_control(tx).await.ok(),
+            Some(Message::text("ab"))
+        );
+        let mut pong = [0u8; 6];
+        server_end.read_exact(&mut pong).await.unwrap();
+        // masked, with an empty payload
+        assert_eq!(pong[..2], [0x8a, 0x80]);
+    }
+
+    #[tokio::test]
+    async fn masked_frame_from_server_is_rejected() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        // masked "hi" text frame, with a zero key for simplicity
+        server_end
+            .write_all(&[0x81, 0x82, 0, 0, 0, 0, b'h', b'i'])
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.receive().await,
+            Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+        ));
+    }
+
+    #[tokio::test]
+    async fn server_frames_are_sent_unmasked() {
+        let (mut client_end, server_end) = duplex(1024);
+        let mut server = WsStream::<Client, _>::from_stream(server_end);
+        server.send(Message::text("hi")).await.unwrap();
+
+        let mut bytes = [0u8; 4];
+        client_end.read_exact(&mut bytes).await.unwrap();
+        assert_eq!(bytes, [0x81, 0x02, b'h', b'i']);
+    }
+
+    #[tokio::test]
+    async fn bare_close_frame_is_received() {
+        let (mut server_end, client_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+        server_end.write_all(&[0x88, 0x00]).await.unwrap();
+
+        assert_eq!(
+            client.receive().await.ok(),
+            Some(Message::Close(StatusCode::NoStatus, None))
+        );
+    }
+
+    #[tokio::test]
+    async fn short_http_line_is_not_a_panic() {
+        let (client_end, mut server_end) = duplex(1024);
+        server_end.write_all(b"\n").await.unwrap();
+        drop(server_end);
+
+        let error = read_http_bytes(&mut BufReader::new(client_end))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn oversized_http_head_is_rejected() {
+        let (client_end, mut server_end) = duplex(MAX_HTTP_HEAD_SIZE * 2);
+        let header = format!("X-Padding: {}\r\n", "a".repeat(MAX_HTTP_HEAD_SIZE));
+        server_end.write_all(header.as_bytes()).await.unwrap();
+
+        let error = read_http_bytes(&mut BufReader::new(client_end))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn close_times_out_without_echo() {
+        let (client_end, _server_end) = duplex(1024);
+        let mut client = WsStream::<Server, _>::from_stream(client_end);
+
+        assert_eq!(
+            client
+                .close_with_timeout(None, None, Duration::from_millis(10))
+                .await
+                .unwrap(),
+            None
+        );
     }
 }
@@ -1,7 +1,10 @@
 use crate::frame::{Frame, Opcode};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+// only so that the codes can stay written down as discriminants, see `From<StatusCode> for u16`
+#[repr(u16)]
 pub enum StatusCode {
+    #[default]
     Normal = 1000,
     GoingAway = 1001,
     ProtocolError = 1002,
@@ -15,6 +18,12 @@ pub enum StatusCode {
     MessageTooBig = 1009,
     UnsupportedExtension = 1010,
     InternalServerError = 1011,
+    ServiceRestart = 1012,
+    TryAgainLater = 1013,
+    BadGateway = 1014,
+
+    /// A code in the 3000-4999 range, left to libraries, frameworks and applications.
+    Application(u16),
 }
 
 impl From<u16> for StatusCode {
@@ -34,12 +43,51 @@ impl From<u16> for StatusCode {
             1009 => Self::MessageTooBig,
             1010 => Self::UnsupportedExtension,
             1011 => Self::InternalServerError,
+            1012 => Self::ServiceRestart,
+            1013 => Self::TryAgainLater,
+            1014 => Self::BadGateway,
+
+            3000..=4999 => Self::Application(value),
 
             _ => Self::UnsupportedData,
         }
     }
 }
 
+impl From<StatusCode> for u16 {
+    fn from(value: StatusCode) -> Self {
+        match value {
+            StatusCode::Normal => 1000,
+            StatusCode::GoingAway => 1001,
+            StatusCode::ProtocolError => 1002,
+            StatusCode::UnsupportedData => 1003,
+            StatusCode::NoStatus => 1005,
+            StatusCode::CloseAbnormal => 1006,
+            StatusCode::InvalidPayloadData => 1007,
+            StatusCode::PolicyViolated => 1008,
+            StatusCode::MessageTooBig => 1009,
+            StatusCode::UnsupportedExtension => 1010,
+            StatusCode::InternalServerError => 1011,
+            StatusCode::ServiceRestart => 1012,
+            StatusCode::TryAgainLater => 1013,
+            StatusCode::BadGateway => 1014,
+            StatusCode::Application(code) => code,
+        }
+    }
+}
+
+impl StatusCode {
+    /// Reads a code received in a *Close* frame. Fails for the ones reserved for
+    /// local use, such as [`StatusCode::NoStatus`], and anything unassigned.
+    #[must_use]
+    pub fn from_wire(code: u16) -> Option<Self> {
+        match code {
+            1000..=1003 | 1007..=1014 | 3000..=4999 => Some(code.into()),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message {
     /// Represents a frame with valid *UTF-8* text.
@@ -49,6 +97,7 @@ pub enum Message {
     /// Represents a *Close* frame with an optional `String`
     /// up to 123 bytes long.
     /// Converting this to a [Frame] will truncate the `String` if needed.
+    /// [`StatusCode::NoStatus`] converts to an empty body, so it can't carry a reason.
     Close(StatusCode, Option<String>),
     /// Represents a *Ping* frame with 125-byte payload.
     /// Converting this to a [Frame] will truncate the payload if needed.
@@ -58,6 +107,126 @@ pub enum Message {
     Pong(Vec<u8>),
 }
 
+/// What [`WsRecv::receive_event`](crate::WsRecv::receive_event) got, telling the peer
+/// closing the connection apart from the connection just ending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    /// Anything but a *Close*.
+    Message(Message),
+    /// The peer sent a *Close* frame.
+    Closed(StatusCode, Option<String>),
+    /// The connection ended without a *Close* frame.
+    Abnormal,
+}
+
+impl Message {
+    /// A *Text* message.
+    ///
+    /// ```
+    /// # use websocket::message::Message;
+    /// assert_eq!(Message::text("hi"), Message::Text(String::from("hi")));
+    /// ```
+    pub fn text(text: impl Into<String>) -> Self {
+        Self::Text(text.into())
+    }
+
+    /// The text of a *Text* message.
+    ///
+    /// ```
+    /// # use websocket::message::Message;
+    /// assert_eq!(Message::text("hi").as_text(), Some("hi"));
+    /// assert_eq!(Message::Binary(vec![1]).as_text(), None);
+    /// ```
+    #[must_use]
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// The bytes of a *Binary* message.
+    ///
+    /// ```
+    /// # use websocket::message::Message;
+    /// assert_eq!(Message::Binary(vec![1]).as_binary(), Some(&[1][..]));
+    /// assert_eq!(Message::text("hi").as_binary(), None);
+    /// ```
+    #[must_use]
+    pub fn as_binary(&self) -> Option<&[u8]> {
+        match self {
+            Self::Binary(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Whether it's a *Close*, *Ping* or *Pong*.
+    ///
+    /// ```
+    /// # use websocket::message::Message;
+    /// assert!(Message::Ping(vec![]).is_control());
+    /// assert!(!Message::text("hi").is_control());
+    /// ```
+    #[must_use]
+    pub fn is_control(&self) -> bool {
+        Opcode::from(self).is_control()
+    }
+
+    /// Length of the payload, before any truncation done when converting to a [Frame].
+    ///
+    /// ```
+    /// # use websocket::message::{Message, StatusCode};
+    /// assert_eq!(Message::text("hé").len(), 3);
+    /// assert_eq!(Message::Close(StatusCode::Normal, Some(String::from("bye"))).len(), 5);
+    /// assert_eq!(Message::Close(StatusCode::NoStatus, None).len(), 0);
+    /// ```
+    #[must_use]
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Text(text) => text.len(),
+            Self::Binary(bytes) | Self::Ping(bytes) | Self::Pong(bytes) => bytes.len(),
+            Self::Close(StatusCode::NoStatus, _) => 0,
+            Self::Close(_, reason) => 2 + reason.as_ref().map_or(0, String::len),
+        }
+    }
+
+    /// Whether the payload is empty.
+    ///
+    /// ```
+    /// # use websocket::message::Message;
+    /// assert!(Message::Ping(vec![]).is_empty());
+    /// assert!(!Message::text("hi").is_empty());
+    /// ```
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<String> for Message {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Binary(value)
+    }
+}
+
+impl From<&[u8]> for Message {
+    fn from(value: &[u8]) -> Self {
+        Self::Binary(value.to_vec())
+    }
+}
+
 impl From<&Message> for Opcode {
     fn from(value: &Message) -> Self {
         match value {
@@ -70,6 +239,7 @@ impl From<&Message> for Opcode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageError {
     /// [Message] construction failed due to a protocol-related error.
     ProtocolViolated(StatusCode),
@@ -78,6 +248,25 @@ pub enum MessageError {
     IsNotFinal,
 }
 
+impl std::fmt::Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = match self {
+            Self::ProtocolViolated(code) => *code,
+            Self::IsNotFinal => return f.write_str("message is missing its final fragment"),
+        };
+        let description = match code {
+            StatusCode::CloseAbnormal => "connection lost without a close frame",
+            StatusCode::InvalidPayloadData => "invalid UTF-8 in text frame",
+            StatusCode::MessageTooBig => "message exceeds the size limit",
+            StatusCode::UnsupportedData => "unsupported data",
+            _ => "protocol violated",
+        };
+        write!(f, "{description} ({})", u16::from(code))
+    }
+}
+
+impl std::error::Error for MessageError {}
+
 impl TryFrom<Frame> for Message {
     type Error = MessageError;
 
@@ -92,18 +281,21 @@ impl TryFrom<Frame> for Message {
                 |_| MessageError::ProtocolViolated(StatusCode::InvalidPayloadData),
             )?)),
             Opcode::Binary => Ok(Message::Binary(value.payload)),
+            // the body is optional, and so is the code
+            Opcode::Close if value.payload.is_empty() => {
+                Ok(Message::Close(StatusCode::NoStatus, None))
+            }
             Opcode::Close => Ok(Message::Close(
-                (u16::from_be_bytes(
+                StatusCode::from_wire(u16::from_be_bytes(
                     value
                         .payload
                         .get(0..2)
-                        .ok_or(MessageError::ProtocolViolated(
-                            StatusCode::InvalidPayloadData,
-                        ))?
+                        // a body has to start with the whole code (RFC 6455, section 5.5.1)
+                        .ok_or(MessageError::ProtocolViolated(StatusCode::ProtocolError))?
                         .try_into()
                         .unwrap(),
                 ))
-                .into(),
+                .ok_or(MessageError::ProtocolViolated(StatusCode::ProtocolError))?,
                 {
                     value
                         .payload
@@ -127,27 +319,73 @@ impl TryFrom<Vec<Frame>> for Message {
     type Error = MessageError;
 
     fn try_from(value: Vec<Frame>) -> Result<Self, Self::Error> {
-        if value.is_empty() {
-            return Err(MessageError::ProtocolViolated(StatusCode::UnsupportedData));
+        merge_frames(value)?.try_into()
+    }
+}
+
+/// Validates *UTF-8* of a fragmented *Text* message as it arrives, so that invalid bytes
+/// fail it before the rest is buffered. A codepoint cut in half by a fragment boundary
+/// is carried over to the next one.
+#[derive(Debug, Default)]
+pub(crate) struct Utf8Validator {
+    /// Start of an incomplete codepoint, at most 3 bytes.
+    tail: Vec<u8>,
+}
+
+impl Utf8Validator {
+    /// Fails as soon as `bytes` can't be a part of valid *UTF-8*, given what came before.
+    /// An incomplete codepoint at the very end is only caught by the final conversion.
+    pub(crate) fn feed(&mut self, mut bytes: &[u8]) -> Result<(), MessageError> {
+        let invalid = MessageError::ProtocolViolated(StatusCode::InvalidPayloadData);
+        if !self.tail.is_empty() {
+            let take = (4 - self.tail.len()).min(bytes.len());
+            let mut buf = std::mem::take(&mut self.tail);
+            let carried = buf.len();
+            buf.extend_from_slice(&bytes[..take]);
+            match std::str::from_utf8(&buf) {
+                Ok(_) => bytes = &bytes[take..],
+                Err(e) if e.valid_up_to() > 0 => bytes = &bytes[e.valid_up_to() - carried..],
+                Err(e) if e.error_len().is_some() => return Err(invalid),
+                // still incomplete, which means `bytes` is all used up
+                Err(_) => {
+                    self.tail = buf;
+                    return Ok(());
+                }
+            }
         }
-        if value[0].header.fin {
-            return value.into_iter().next().unwrap().try_into();
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Ok(()),
+            Err(e) if e.error_len().is_some() => Err(invalid),
+            Err(e) => {
+                self.tail = bytes[e.valid_up_to()..].to_vec();
+                Ok(())
+            }
         }
+    }
+}
 
-        let mut first = value[0].clone();
-        let buffer: Vec<u8> = value
-            .into_iter()
-            .map(|frame| frame.payload)
-            .reduce(|mut acc, payload| {
-                acc.extend_from_slice(&payload);
-                acc
-            })
-            .unwrap();
-        first.header.fin = true;
-        first.header.payload_len = (buffer.len() as u64).into();
-        first.payload = buffer;
-        first.try_into()
+/// Joins the fragments of a message into a single final [Frame] with the header of the first.
+pub(crate) fn merge_frames(value: Vec<Frame>) -> Result<Frame, MessageError> {
+    if value.is_empty() {
+        return Err(MessageError::ProtocolViolated(StatusCode::UnsupportedData));
     }
+    if value[0].header.fin {
+        return Ok(value.into_iter().next().unwrap());
+    }
+
+    let mut first = value[0].clone();
+    let buffer: Vec<u8> = value
+        .into_iter()
+        .map(|frame| frame.payload)
+        .reduce(|mut acc, payload| {
+            acc.extend_from_slice(&payload);
+            acc
+        })
+        .unwrap();
+    first.header.fin = true;
+    first.header.payload_len = (buffer.len() as u64).into();
+    first.payload = buffer;
+    Ok(first)
 }
 
 impl From<Message> for Frame {
@@ -156,17 +394,12 @@ impl From<Message> for Frame {
         let payload: Vec<u8> = match value {
             Message::Text(text) => text.into(),
             Message::Binary(binary) => binary,
-            Message::Close(code, reason) => {
-                let mut vector =
-                    Vec::with_capacity(reason.as_ref().map_or(0, |s| usize::max(123, s.len()) + 2));
-                vector.extend((code as u16).to_be_bytes().iter());
-                if let Some(s) = reason {
-                    let mut s = s.into_bytes();
-                    s.truncate(123);
-                    vector.extend(s.iter());
-                }
-                vector
+            // never goes on the wire, an empty body says the same
+            Message::Close(StatusCode::NoStatus, reason) => {
+                debug_assert!(reason.is_none(), "a Close without a status has no reason");
+                vec![]
             }
+            Message::Close(code, reason) => return Frame::close(code, reason),
             Message::Ping(mut binary) | Message::Pong(mut binary) => {
                 binary.truncate(125);
                 binary
@@ -175,3 +408,129 @@ impl From<Message> for Frame {
         Frame::new(true, opcode, payload)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, MessageError, StatusCode, Utf8Validator};
+    use crate::frame::{Frame, Opcode};
+
+    fn close_with(code: u16) -> Result<Message, MessageError> {
+        Frame::new(true, Opcode::Close, code.to_be_bytes().to_vec()).try_into()
+    }
+
+    #[test]
+    fn conversions_pick_text_or_binary() {
+        assert_eq!(Message::from("a"), Message::Text(String::from("a")));
+        assert_eq!(
+            Message::from(String::from("a")),
+            Message::Text(String::from("a"))
+        );
+        assert_eq!(Message::from(vec![1, 2]), Message::Binary(vec![1, 2]));
+        assert_eq!(Message::from(&[1, 2][..]), Message::Binary(vec![1, 2]));
+    }
+
+    #[test]
+    fn errors_say_what_went_wrong() {
+        assert_eq!(
+            MessageError::ProtocolViolated(StatusCode::InvalidPayloadData).to_string(),
+            "invalid UTF-8 in text frame (1007)"
+        );
+        assert_eq!(
+            MessageError::ProtocolViolated(StatusCode::ProtocolError).to_string(),
+            "protocol violated (1002)"
+        );
+        assert_eq!(
+            MessageError::IsNotFinal.to_string(),
+            "message is missing its final fragment"
+        );
+    }
+
+    #[test]
+    fn utf8_is_validated_across_fragments() {
+        let text = "aé€😀".as_bytes();
+        // every possible split, including ones in the middle of a codepoint
+        for i in 0..=text.len() {
+            for j in i..=text.len() {
+                let mut validator = Utf8Validator::default();
+                for fragment in [&text[..i], &text[i..j], &text[j..]] {
+                    assert!(validator.feed(fragment).is_ok(), "split at {i}, {j}");
+                }
+                assert!(validator.tail.is_empty());
+            }
+        }
+
+        let mut validator = Utf8Validator::default();
+        assert!(validator.feed(&[0xe2, 0x82]).is_ok());
+        assert!(validator.feed(b"a").is_err());
+        assert!(Utf8Validator::default().feed(&[b'a', 0xff]).is_err());
+    }
+
+    #[test]
+    fn empty_close_has_no_status() {
+        let frame: Frame = Message::Close(StatusCode::NoStatus, None).into();
+        assert!(frame.payload.is_empty());
+        assert_eq!(
+            Message::try_from(frame).ok(),
+            Some(Message::Close(StatusCode::NoStatus, None))
+        );
+    }
+
+    #[test]
+    fn close_reason_is_truncated_on_a_char_boundary() {
+        let frame: Frame = Message::Close(StatusCode::Normal, Some("a".repeat(200))).into();
+        assert_eq!(frame.payload[..2], 1000_u16.to_be_bytes());
+        assert_eq!(frame.payload[2..], *"a".repeat(123).as_bytes());
+
+        let frame: Frame = Message::Close(StatusCode::Normal, Some("bye".to_string())).into();
+        assert_eq!(frame.payload[2..], *b"bye");
+
+        let reason = format!("{}ї", "a".repeat(122));
+        let frame: Frame = Message::Close(StatusCode::Normal, Some(reason)).into();
+        assert_eq!(frame.payload[2..], *"a".repeat(122).as_bytes());
+        assert!(Message::try_from(frame).is_ok());
+    }
+
+    #[test]
+    fn malformed_close_bodies_are_told_apart() {
+        let half_a_code: Result<Message, _> =
+            Frame::new(true, Opcode::Close, vec![0x03]).try_into();
+        assert!(matches!(
+            half_a_code,
+            Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+        ));
+        let invalid_reason: Result<Message, _> =
+            Frame::new(true, Opcode::Close, vec![0x03, 0xe8, 0xff]).try_into();
+        assert!(matches!(
+            invalid_reason,
+            Err(MessageError::ProtocolViolated(
+                StatusCode::InvalidPayloadData
+            ))
+        ));
+    }
+
+    #[test]
+    fn reserved_close_codes_are_rejected() {
+        for code in [999, 1004, 1005, 1006, 1015, 2999, 5000] {
+            assert!(
+                matches!(
+                    close_with(code),
+                    Err(MessageError::ProtocolViolated(StatusCode::ProtocolError))
+                ),
+                "{code} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn registered_and_application_close_codes_are_accepted() {
+        for (code, status) in [
+            (1000, StatusCode::Normal),
+            (1013, StatusCode::TryAgainLater),
+            (3000, StatusCode::Application(3000)),
+            (4999, StatusCode::Application(4999)),
+        ] {
+            assert_eq!(close_with(code).ok(), Some(Message::Close(status, None)));
+            assert_eq!(u16::from(status), code);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Adapters into [`futures::Stream`] and [`futures::Sink`], behind the `futures` feature.
+//!
+//! Both are thin wrappers over [`WsRecv::receive`] and [`WsSend::send`], so limits,
+//! masking and control frame handling stay the same.
+//!
+//! The halves don't implement the traits themselves: `poll_next` and `poll_ready` would
+//! have to keep a half-done `receive` or `send` around between polls, and that future
+//! borrows the half it would be stored in. The adapters own the half instead, moving it
+//! into each future and getting it back once it's done.
+
+use futures::{Sink, Stream};
+
+use crate::{
+    Side, UnpinStream, WsRecv, WsRecvHalf, WsSend, WsSendHalf,
+    message::{Message, MessageError},
+};
+
+/// State of a receiving stream: the half while it's usable, nothing after an error.
+async fn next_message<R: WsRecv>(
+    rx: Option<R>,
+) -> Option<(Result<Message, MessageError>, Option<R>)> {
+    let mut rx = rx?;
+    match rx.receive().await {
+        Ok(message) => Some((Ok(message), Some(rx))),
+        Err(e) => Some((Err(e), None)),
+    }
+}
+
+async fn send_message<W: WsSend>(mut tx: W, message: Message) -> std::io::Result<W> {
+    tx.send(message).await?;
+    Ok(tx)
+}
+
+impl<S: Side, T: UnpinStream> WsRecvHalf<S, T> {
+    /// Turns the half into a [`Stream`] of received messages.
+    /// The stream ends right after yielding the first error.
+    /// It isn't [`Unpin`], so pin it before using [`futures::StreamExt::next`].
+    pub fn into_stream(self) -> impl Stream<Item = Result<Message, MessageError>> {
+        futures::stream::unfold(Some(self), next_message)
+    }
+}
+
+impl<S: Side, T: UnpinStream> WsSendHalf<S, T> {
+    /// Turns the half into a [`Sink`] sending every message it's fed.
+    /// It isn't [`Unpin`], so pin it before using [`futures::SinkExt`] methods.
+    pub fn into_sink(self) -> impl Sink<Message, Error = std::io::Error> {
+        futures::sink::unfold(self, send_message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::{SinkExt, StreamExt, stream};
+    use tokio::io::duplex;
+
+    use crate::{
+        Client, Server, WsStream,
+        message::{Message, MessageError, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn messages_forward_through_sink_and_stream() {
+        let (client_end, server_end) = duplex(4096);
+        let client = WsStream::<Server, _>::from_stream(client_end);
+        let server = WsStream::<Client, _>::from_stream(server_end);
+
+        let messages = vec![
+            Message::text("one"),
+            Message::Binary(vec![2]),
+            Message::text("three"),
+        ];
+        {
+            let mut sink = pin!(client.tx.into_sink());
+            sink.send_all(&mut stream::iter(messages.clone()).map(Ok))
+                .await
+                .unwrap();
+        }
+        drop(client.rx);
+
+        let mut received: Vec<_> = server.rx.into_stream().collect().await;
+        assert!(matches!(
+            received.pop(),
+            Some(Err(MessageError::ProtocolViolated(
+                StatusCode::CloseAbnormal
+            )))
+        ));
+        assert_eq!(
+            received.into_iter().map(Result::ok).collect::<Vec<_>>(),
+            messages.into_iter().map(Some).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! End-to-end tests over an in-memory pipe, going through the same handshake
+//! and framing a TCP connection would.
+
+use tokio::io::{DuplexStream, duplex};
+
+use crate::{Client, Server, WsConfig, WsStream, handshake::IntoWebsocket};
+
+/// Both ends of a connection upgraded over [`duplex`], the client one first.
+pub(crate) async fn upgraded_pair() -> (
+    WsStream<Server, DuplexStream>,
+    WsStream<Client, DuplexStream>,
+) {
+    upgraded_pair_with(WsConfig::default()).await
+}
+
+/// Same as [`upgraded_pair`], with both ends using `config`.
+pub(crate) async fn upgraded_pair_with(
+    config: WsConfig,
+) -> (
+    WsStream<Server, DuplexStream>,
+    WsStream<Client, DuplexStream>,
+) {
+    let (client_end, server_end) = duplex(64 * 1024);
+    let mut client = WsStream::<Server, _>::from_stream_with_config(client_end, config);
+    let mut server = WsStream::<Client, _>::from_stream_with_config(server_end, config);
+    let (c, s) = tokio::join!(
+        client.try_upgrade("localhost"),
+        server.try_upgrade("localhost"),
+    );
+    c.unwrap();
+    s.unwrap();
+    (client, server)
+}
+
+mod tests {
+    use rand::Rng;
+
+    use super::{upgraded_pair, upgraded_pair_with};
+    use crate::{
+        WsConfig, WsRecv, WsSend,
+        message::{Message, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn messages_round_trip() {
+        let (mut client, mut server) = upgraded_pair().await;
+
+        client.send(Message::text("hello")).await.unwrap();
+        assert_eq!(server.receive().await.ok(), Some(Message::text("hello")));
+
+        server.send(Message::Binary(vec![0, 1, 2])).await.unwrap();
+        assert_eq!(
+            client.receive().await.ok(),
+            Some(Message::Binary(vec![0, 1, 2]))
+        );
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages_are_reassembled() {
+        let (mut client, mut server) = upgraded_pair().await;
+        let text = "fragment me ".repeat(50);
+
+        client
+            .send_fragmented(Message::Text(text.clone()), 7)
+            .await
+            .unwrap();
+        assert_eq!(server.receive().await.ok(), Some(Message::Text(text)));
+
+        server
+            .send_fragmented(Message::Binary(vec![7; 300]), 64)
+            .await
+            .unwrap();
+        assert_eq!(
+            client.receive().await.ok(),
+            Some(Message::Binary(vec![7; 300]))
+        );
+    }
+
+    #[tokio::test]
+    async fn multi_megabyte_binaries_arrive_intact() {
+        let (mut client, mut server) = upgraded_pair().await;
+        // random, so that compression can't shrink it below the 16-bit length
+        let mut image = vec![0u8; 2 * 1024 * 1024];
+        rand::rng().fill(&mut image[..]);
+
+        // both ways, as only the client masks
+        let (sent, received) = tokio::join!(
+            client.send(Message::Binary(image.clone())),
+            server.receive()
+        );
+        sent.unwrap();
+        assert!(received.ok() == Some(Message::Binary(image.clone())));
+
+        let (sent, received) = tokio::join!(
+            server.send(Message::Binary(image.clone())),
+            client.receive()
+        );
+        sent.unwrap();
+        assert!(received.ok() == Some(Message::Binary(image)));
+    }
+
+    #[tokio::test]
+    async fn large_payloads_use_the_64_bit_length() {
+        let config = WsConfig::default().with_compression(false);
+        let (mut client, mut server) = upgraded_pair_with(config).await;
+        let payload = vec![0u8; usize::from(u16::MAX) + 1];
+        let expected = payload.len();
+
+        let (sent, raw) = tokio::join!(
+            client.send(Message::Binary(payload)),
+            server.read_frame_bytes()
+        );
+        sent.unwrap();
+        let raw = raw.unwrap();
+        // the 7-bit length says "64-bit length follows"
+        assert_eq!(raw[1] & 0x7f, 127);
+        assert_eq!(raw[2..10], u64::try_from(expected).unwrap().to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn pings_get_ponged() {
+        let (mut client, mut server) = upgraded_pair().await;
+
+        server.send(Message::Ping(vec![4, 2])).await.unwrap();
+        server.send(Message::text("after")).await.unwrap();
+        let (rx, tx) = (&mut client.rx, &mut client.tx);
+        assert_eq!(
+            rx.receive_with_control(tx).await.ok(),
+            Some(Message::text("after"))
+        );
+        assert_eq!(server.receive().await.ok(), Some(Message::Pong(vec![4, 2])));
+    }
+
+    #[tokio::test]
+    async fn close_handshake_completes() {
+        let (mut client, mut server) = upgraded_pair().await;
+
+        let peer = tokio::spawn(async move {
+            let Ok(Message::Close(code, reason)) = server.receive().await else {
+                panic!("expected a close frame");
+            };
+            assert_eq!(reason.as_deref(), Some("bye"));
+            server.tx.close(Some(code), None).await.unwrap();
+            server
+        });
+
+        assert_eq!(
+            client
+                .close(Some(StatusCode::Normal), Some(String::from("bye")))
+                .await
+                .unwrap(),
+            Some(StatusCode::Normal)
+        );
+        let mut server = peer.await.unwrap();
+        assert!(server.receive().await.is_err());
+    }
+}